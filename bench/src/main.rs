@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io::{self, BufWriter, stdout, Write};
+use std::path::Path;
+use std::process::exit;
+use std::time::Instant;
+
+use log::info;
+
+use mgr_map_extractor::NodeId;
+use mgr_map_extractor::graph::Graph;
+use mgr_route_generator::{generate_route, generate_route_with, Route, RouteDetails, RouteGeneratorStrategy};
+use mgr_route_generator::registry::ProviderRegistry;
+
+use crate::memory::TrackingAllocator;
+use crate::scenario::Scenario;
+
+mod memory;
+mod scenario;
+
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+const CSV_HEADER: &str = "route,starting_node,ending_node,provider,run,seed,reached,edges,route_length,optimal_length,optimality_gap,runtime_ms,peak_memory_kb";
+
+fn main() {
+    fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!("[{} {}] {}", record.level(), record.target(), message))
+        })
+        //Providers log every generation, only warnings are kept so they do not slow the measurements down
+        .level(log::LevelFilter::Warn)
+        .level_for("mgr_bench", log::LevelFilter::Info)
+        .chain(std::io::stderr())
+        .apply().unwrap();
+
+    let args: Vec<String> = std::env::args().collect();
+    let Some(scenario_path) = args.get(1) else {
+        eprintln!("Usage: {} <scenario file> [output CSV file]", args[0]);
+        exit(2);
+    };
+    let registry = ProviderRegistry::new();
+    let scenario = Scenario::load(Path::new(scenario_path), &registry).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        exit(1);
+    });
+    let mut output: Box<dyn Write> = match args.get(2) {
+        Some(path) => Box::new(BufWriter::new(File::create(path).unwrap_or_else(|error| {
+            eprintln!("Cannot create {}: {}", path, error);
+            exit(1);
+        }))),
+        None => Box::new(stdout()),
+    };
+
+    let graph = scenario.graph.load();
+    info!("Graph loaded - {} nodes", graph.nodes().len());
+
+    if let Err(error) = run_scenario(&scenario, &graph, &registry, &mut output) {
+        eprintln!("Cannot write the results: {}", error);
+        exit(1);
+    }
+}
+
+/// Generate every route of the scenario with every provider and write one CSV row per run
+fn run_scenario(scenario: &Scenario, graph: &Graph, registry: &ProviderRegistry, output: &mut dyn Write) -> io::Result<()> {
+    writeln!(output, "{}", CSV_HEADER)?;
+    for (route_idx, &(starting_node, ending_node)) in scenario.routes.iter().enumerate() {
+        let route_details = RouteDetails::new(starting_node, ending_node);
+        //Exact search with the default distance weights gives the shortest route
+        let optimal = generate_route(graph, RouteGeneratorStrategy::AStar, &route_details);
+        let optimal_length = reaches(&optimal, ending_node).then(|| route_length(&optimal));
+
+        for provider_name in &scenario.providers {
+            let Some(provider) = registry.get(provider_name) else {
+                continue;
+            };
+            for run in 0..scenario.runs {
+                let run_details = match scenario.seeds.get(run) {
+                    Some(&seed) => route_details.clone().with_seed(seed),
+                    None => route_details.clone(),
+                };
+
+                let memory_before = memory::reset_peak();
+                let started = Instant::now();
+                let route = generate_route_with(graph, provider, &run_details);
+                let runtime = started.elapsed();
+                let peak_memory = memory::peak().saturating_sub(memory_before);
+
+                let reached = reaches(&route, ending_node);
+                let length = route_length(&route);
+                let gap = optimal_length.filter(|&optimal| reached && optimal > 0.0).map(|optimal| (length - optimal) / optimal);
+                writeln!(output, "{},{},{},{},{},{},{},{},{:.2},{},{},{:.3},{}",
+                         route_idx,
+                         starting_node.0,
+                         ending_node.0,
+                         provider_name,
+                         run,
+                         route.seed.map(|seed| seed.to_string()).unwrap_or_default(),
+                         reached,
+                         route.edges.len(),
+                         length,
+                         optimal_length.map(|length| format!("{:.2}", length)).unwrap_or_default(),
+                         gap.map(|gap| format!("{:.4}", gap)).unwrap_or_default(),
+                         runtime.as_secs_f64() * 1000.0,
+                         peak_memory / 1024,
+                )?;
+                info!("Route {} {} run {} finished in {:?}", route_idx, provider_name, run, runtime);
+            }
+        }
+    }
+    output.flush()
+}
+
+fn reaches(route: &Route, ending_node: NodeId) -> bool {
+    route.edges.last().is_some_and(|edge| edge.to == ending_node)
+}
+
+fn route_length(route: &Route) -> f64 {
+    route.edges.iter().map(|edge| edge.length).sum()
+}
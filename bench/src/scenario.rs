@@ -0,0 +1,139 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mgr_map_extractor::graph::Graph;
+use mgr_map_extractor::{NodeId, produce_connection_graph_from};
+use mgr_route_generator::lattice::lattice_graph;
+use mgr_route_generator::registry::ProviderRegistry;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum GraphSource {
+    Pbf(PathBuf),
+    //Synthetic width x height lattice, node ids grow row by row starting with 1
+    Lattice { width: usize, height: usize },
+}
+
+/// Benchmark scenario read from a text file with one `key = value` entry per line:
+/// `graph` (path of the PBF file or `lattice <width> <height>`), `providers`, `runs`, `seeds`
+/// and any number of `route = <starting node> <ending node>` entries. Lines starting with # are comments
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Scenario {
+    pub(crate) graph: GraphSource,
+    pub(crate) routes: Vec<(NodeId, NodeId)>,
+    //Names of the providers in the registry, all of them when the scenario does not list any
+    pub(crate) providers: Vec<String>,
+    //How many times every provider generates every route
+    pub(crate) runs: usize,
+    //Seed of every run, runs without the seed draw a random one
+    pub(crate) seeds: Vec<u64>,
+}
+
+impl Scenario {
+    pub(crate) fn load(path: &Path, registry: &ProviderRegistry) -> Result<Scenario, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("Cannot read scenario {:?}: {}", path, error))?;
+        Scenario::parse(&text, registry)
+    }
+
+    pub(crate) fn parse(text: &str, registry: &ProviderRegistry) -> Result<Scenario, String> {
+        let mut graph = None;
+        let mut routes = vec![];
+        let mut providers: Vec<String> = registry.names().map(str::to_string).collect();
+        let mut runs = None;
+        let mut seeds = vec![];
+
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| format!("Line {}: {}", line_idx + 1, message);
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error("expected `key = value`"));
+            };
+            let values: Vec<&str> = value.split_whitespace().collect();
+
+            match key.trim() {
+                "graph" => graph = Some(match values.as_slice() {
+                    ["lattice", width, height] => GraphSource::Lattice {
+                        width: width.parse().map_err(|_| error("invalid lattice width"))?,
+                        height: height.parse().map_err(|_| error("invalid lattice height"))?,
+                    },
+                    [path] => GraphSource::Pbf(PathBuf::from(path)),
+                    _ => return Err(error("expected PBF file path or `lattice <width> <height>`")),
+                }),
+                "route" => match values.as_slice() {
+                    [from, to] => routes.push((
+                        NodeId(from.parse().map_err(|_| error("invalid starting node"))?),
+                        NodeId(to.parse().map_err(|_| error("invalid ending node"))?),
+                    )),
+                    _ => return Err(error("expected `route = <starting node> <ending node>`")),
+                },
+                "providers" => providers = values.iter()
+                    .map(|name| parse_provider(registry, name).ok_or_else(|| error(&format!("unknown provider {}", name))))
+                    .collect::<Result<_, _>>()?,
+                "runs" => runs = Some(value.trim().parse().map_err(|_| error("invalid number of runs"))?),
+                "seeds" => seeds = values.iter()
+                    .map(|seed| seed.parse().map_err(|_| error("invalid seed")))
+                    .collect::<Result<_, _>>()?,
+                other => return Err(error(&format!("unknown key {}", other))),
+            }
+        }
+
+        Ok(Scenario {
+            graph: graph.ok_or("Scenario has no graph")?,
+            routes,
+            providers,
+            runs: runs.unwrap_or(seeds.len().max(1)),
+            seeds,
+        })
+    }
+}
+
+/// Name of the registered provider, matched case-insensitively
+fn parse_provider(registry: &ProviderRegistry, name: &str) -> Option<String> {
+    registry.names().find(|provider| provider.eq_ignore_ascii_case(name)).map(str::to_string)
+}
+
+impl GraphSource {
+    pub(crate) fn load(&self) -> Graph {
+        match self {
+            GraphSource::Pbf(path) => produce_connection_graph_from(path).0,
+            GraphSource::Lattice { width, height } => lattice_graph(*width, *height),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scenario() {
+        let scenario = Scenario::parse("
+            # Comment
+            graph = lattice 5 4
+            providers = aco AStar
+            seeds = 7 8
+            route = 1 20
+            route = 5 16
+        ", &ProviderRegistry::new()).unwrap();
+
+        assert_eq!(scenario, Scenario {
+            graph: GraphSource::Lattice { width: 5, height: 4 },
+            routes: vec![(NodeId(1), NodeId(20)), (NodeId(5), NodeId(16))],
+            providers: vec!["ACO".to_string(), "AStar".to_string()],
+            runs: 2,
+            seeds: vec![7, 8],
+        });
+        assert_eq!(scenario.graph.load().nodes().len(), 20);
+    }
+
+    #[test]
+    fn test_invalid_scenario() {
+        let registry = ProviderRegistry::new();
+        assert_eq!(Scenario::parse("route = 1 2", &registry), Err("Scenario has no graph".to_string()));
+        assert_eq!(Scenario::parse("graph = map.pbf\nproviders = Dijkstra", &registry), Err("Line 2: unknown provider Dijkstra".to_string()));
+        assert_eq!(Scenario::parse("graph = map.pbf\nruns: 3", &registry), Err("Line 2: expected `key = value`".to_string()));
+        assert_eq!(Scenario::parse("graph = map.pbf", &registry).unwrap().providers.len(), registry.names().count());
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 
 use osmpbfreader::NodeId;
 
@@ -11,6 +12,7 @@ pub struct Graph {
     edges: Vec<Edge>,
     edge_by_node_id: HashMap<(NodeId, NodeId), Edge>,
     edge_connections: HashMap<NodeId, Vec<NodeId>>,// Map source node to list of target nodes
+    way_names: HashMap<(NodeId, NodeId), Arc<str>>,// Name of the way the edge belongs to, one entry for both directions of the edge
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -49,6 +51,7 @@ impl Graph {
             edges: Vec::with_capacity(DEFAULT_GRAPH_EDGES_CAPACITY),
             edge_by_node_id: Default::default(),
             edge_connections: Default::default(),
+            way_names: Default::default(),
         }
     }
 
@@ -67,7 +70,7 @@ impl Graph {
     }
 
     pub fn add_edge(&mut self, edge: Edge) {
-        self.edge_by_node_id.insert((edge.from, edge.to), edge.clone());
+        self.edge_by_node_id.insert((edge.from, edge.to), edge);
         self.edges.push(edge);
     }
 
@@ -88,6 +91,20 @@ impl Graph {
     pub fn edge_by_node_id(&self) -> &HashMap<(NodeId, NodeId), Edge> {
         &self.edge_by_node_id
     }
+
+    /// Name is shared by both directions of the edge
+    pub fn add_way_name(&mut self, from: NodeId, to: NodeId, name: Arc<str>) {
+        self.way_names.insert(undirected_key(from, to), name);
+    }
+
+    /// Name of the way that the edge belongs to, None when the way is unnamed
+    pub fn way_name(&self, from: NodeId, to: NodeId) -> Option<&str> {
+        self.way_names.get(&undirected_key(from, to)).map(Arc::as_ref)
+    }
+}
+
+fn undirected_key(from: NodeId, to: NodeId) -> (NodeId, NodeId) {
+    if from <= to { (from, to) } else { (to, from) }
 }
 
 
@@ -258,7 +275,7 @@ mod tests {
 
         let len = Edge::length(&p1, &p2);
 
-        assert_eq!(len, 5.0)
+        assert_eq!(len, 5.0 * 111.1 * 1000.0)
     }
 
     #[test]
@@ -299,6 +316,19 @@ mod tests {
         assert_eq!(g.edge_connections.get(&NodeId(1)), Some(&vec![NodeId(2), NodeId(3), NodeId(1)]));
     }
 
+    #[test]
+    fn test_way_names() {
+        let mut g = Graph::new();
+        let name: Arc<str> = Arc::from("Marymoncka");
+
+        g.add_way_name(NodeId(2), NodeId(1), name);
+
+        assert_eq!(g.way_name(NodeId(1), NodeId(2)), Some("Marymoncka"));
+        assert_eq!(g.way_name(NodeId(2), NodeId(1)), Some("Marymoncka"));
+        assert_eq!(g.way_name(NodeId(2), NodeId(3)), None);
+        assert_eq!(g.way_names.len(), 1);
+    }
+
     #[test]
     fn edge_equality_same_node_ids() {
         let e1 = Edge::new(NodeId(1), NodeId(2), 0.0, ApprovedHighwayType::Motorway);
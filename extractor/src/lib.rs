@@ -1,14 +1,17 @@
 use std::collections::HashSet;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
 
 use log::info;
 pub use osmpbfreader::NodeId;
-use osmpbfreader::OsmObj;
+use osmpbfreader::{OsmObj, OsmPbfReader};
 
 pub(crate) use loader::{DataFetcher, Loader, OutputFormat};
 pub use parser::ApprovedHighwayType;
 
 use crate::graph::{Edge, Graph, Node};
-use crate::parser::filter_way_data;
+use crate::parser::{filter_way_data, way_name};
 
 mod loader;
 mod parser;
@@ -26,8 +29,18 @@ pub fn produce_connection_graph() -> (Graph, CoordinateStats) {
     info!("Starting extractor");
     info!("Loading map data");
     let loader = Loader::new(DataFetcher::File, OutputFormat::Json, false);
-    let mut osm_reader = loader.load();
+    build_connection_graph(loader.load())
+}
+
+/// Same as produce_connection_graph, but with the map data read from the given PBF file
+pub fn produce_connection_graph_from(path: &Path) -> (Graph, CoordinateStats) {
+    info!("Starting extractor");
+    info!("Loading map data from {:?}", path);
+    let loader = Loader::new(DataFetcher::File, OutputFormat::Json, false);
+    build_connection_graph(loader.load_from(path))
+}
 
+fn build_connection_graph(mut osm_reader: OsmPbfReader<File>) -> (Graph, CoordinateStats) {
     let mut osm_nodes: HashSet<Node> = HashSet::with_capacity(100_000);
 
     // let mut osm_ways: HashSet<MapWay> = HashSet::new();
@@ -77,6 +90,7 @@ pub fn produce_connection_graph() -> (Graph, CoordinateStats) {
                 //Generate edges
                 let way_nodes_count = way.nodes.len();
                 if let Some((way, road_type)) = filter_way_data(&way) {
+                    let name: Option<Arc<str>> = way_name(way).map(Arc::from);
                     for way_node_idx in 0..(way_nodes_count - 1) {
                         let node_id_from = way.nodes[way_node_idx];
                         let node_id_to = way.nodes[way_node_idx + 1];
@@ -98,6 +112,10 @@ pub fn produce_connection_graph() -> (Graph, CoordinateStats) {
 
                         graph.add_edge_connection(node_id_from, node_id_to);
                         graph.add_edge_connection(node_id_to, node_id_from);
+
+                        if let Some(name) = &name {
+                            graph.add_way_name(node_id_from, node_id_to, name.clone());
+                        }
                     }
                 }
             }
@@ -1,4 +1,5 @@
 use std::fs::File;
+use std::path::Path;
 
 use log::debug;
 use osmpbfreader::OsmPbfReader;
@@ -18,6 +19,7 @@ pub enum OutputFormat {
 }
 
 #[derive(Debug, Clone, Copy)]
+#[allow(dead_code)] //Output format and merging are not implemented yet
 pub struct Loader {
     fetch_strategy: DataFetcher,
     data_output_format: OutputFormat,
@@ -40,9 +42,12 @@ impl Loader {
     }
 
     pub fn load(&self) -> OsmPbfReader<File> {
-        debug!("Loading data from {:?}", self.fetch_strategy);
+        self.load_from(Path::new(&INPUT_FILE))
+    }
+
+    pub fn load_from(&self, path: &Path) -> OsmPbfReader<File> {
+        debug!("Loading data from {:?} {:?}", self.fetch_strategy, path);
 
-        let path = std::path::Path::new(&INPUT_FILE);
         match File::open(path){
             Ok(file_handle) => {
                 osmpbfreader::OsmPbfReader::new(file_handle)       
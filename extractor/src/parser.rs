@@ -3,6 +3,9 @@ use std::str::FromStr;
 use osmpbfreader::Way;
 
 const TARGET_TAG_KEY: &str = "highway";
+const NAME_TAG_KEY: &str = "name";
+//Roads without a name are often identified by their reference number only
+const REF_TAG_KEY: &str = "ref";
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
 pub enum ApprovedHighwayType {
@@ -67,6 +70,13 @@ pub(crate) fn filter_way_data(osm_way: &osmpbfreader::Way) -> Option<(&Way, Appr
         })
 }
 
+/// Name of the way, its reference number when it has no name
+pub(crate) fn way_name(osm_way: &Way) -> Option<&str> {
+    osm_way.tags.get(NAME_TAG_KEY)
+        .or_else(|| osm_way.tags.get(REF_TAG_KEY))
+        .map(|name| name.as_str())
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -108,6 +118,24 @@ mod tests {
         assert_eq!(filter_way_data(&osm_node), Some((&osm_node, ApprovedHighwayType::Primary)))
     }
 
+    #[test]
+    fn test_way_name_falls_back_to_ref() {
+        let named = OsmWay {
+            id: WayId(1),
+            tags: Tags::from_iter(vec![(String::from(NAME_TAG_KEY), String::from("Marymoncka")), (String::from(REF_TAG_KEY), String::from("S7"))]),
+            nodes: vec![],
+        };
+        let numbered = OsmWay {
+            id: WayId(2),
+            tags: Tags::from_iter(vec![(String::from(REF_TAG_KEY), String::from("S7"))]),
+            nodes: vec![],
+        };
+
+        assert_eq!(way_name(&named), Some("Marymoncka"));
+        assert_eq!(way_name(&numbered), Some("S7"));
+        assert_eq!(way_name(&OsmWay { id: WayId(3), tags: Default::default(), nodes: vec![] }), None);
+    }
+
     #[test]
     fn test_none_when_no_accepted_highway_tag() {
         let osm_node = OsmWay {
@@ -0,0 +1,28 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use rayon::ThreadPoolBuilder;
+
+use mgr_route_generator::{RouteDetails, RouteGenerator};
+use mgr_route_generator::lattice::{lattice_graph, lattice_node};
+use mgr_route_generator::providers::aco::RouteGeneratorACO;
+
+//Square lattice of roads, big enough for ants to spend most of the time walking
+const LATTICE_SIZE: usize = 30;
+
+fn aco_graph_route(c: &mut Criterion) {
+    let graph = lattice_graph(LATTICE_SIZE, LATTICE_SIZE);
+    let route_details = RouteDetails::new(lattice_node(LATTICE_SIZE, 0, 0), lattice_node(LATTICE_SIZE, LATTICE_SIZE - 1, LATTICE_SIZE - 1)).with_seed(1);
+    let single_thread = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+
+    let mut group = c.benchmark_group("aco_graph_route");
+    group.sample_size(10);
+    group.bench_function("single_thread", |b| b.iter(|| {
+        single_thread.install(|| RouteGeneratorACO.generate_route(&graph, &route_details))
+    }));
+    group.bench_function("parallel", |b| b.iter(|| {
+        RouteGeneratorACO.generate_route(&graph, &route_details)
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, aco_graph_route);
+criterion_main!(benches);
@@ -0,0 +1,167 @@
+use std::collections::HashSet;
+
+use log::info;
+use osmpbfreader::NodeId;
+
+use mgr_map_extractor::graph::Graph;
+
+use crate::{Route, RouteDetails};
+use crate::budget::BudgetTracker;
+use crate::cost::CostEvaluator;
+use crate::path::path_to_route;
+use crate::providers::astar::{shortest_path, shortest_path_avoiding};
+
+//Alternative sharing a bigger part of its length with any already chosen route is skipped, from 0.0 to 1.0
+pub const MAX_ROUTE_SIMILARITY: f32 = 0.7;
+
+//Maximum number of loopless paths examined while looking for the alternatives
+pub const ALTERNATIVES_SEARCH_LIMIT: usize = 100;
+
+/// Candidate path found by Yen's algorithm together with its weighted cost
+struct Candidate {
+    path: Vec<NodeId>,
+    cost: f32,
+}
+
+/// Up to `count` cheapest loopless routes (Yen's algorithm), each of them sharing at most `max_similarity`
+/// of its length with any cheaper route already chosen
+pub(crate) fn generate_alternatives(graph: &Graph, route_details: &RouteDetails, count: usize, max_similarity: f32) -> Vec<Route> {
+    let cost_evaluator = CostEvaluator::new(route_details);
+    let Some(shortest) = shortest_path(graph, route_details.starting_node, route_details.ending_node, &cost_evaluator) else {
+        return vec![];
+    };
+
+    let mut examined: Vec<Vec<NodeId>> = vec![];
+    let mut candidates = vec![Candidate { cost: cost_evaluator.weighted(&cost_evaluator.path_cost(graph, &shortest)), path: shortest.clone() }];
+    let mut seen: HashSet<Vec<NodeId>> = HashSet::from([shortest]);
+    let mut alternatives: Vec<Route> = vec![];
+
+    //Every examined path counts as one iteration of the budget
+    let budget = BudgetTracker::new(&route_details.budget);
+    while alternatives.len() < count && examined.len() < ALTERNATIVES_SEARCH_LIMIT {
+        if budget.is_exhausted(examined.len() as u32) {
+            info!("Budget exhausted while looking for the alternatives");
+            break;
+        }
+        let Some(best_idx) = (0..candidates.len()).min_by(|&a, &b| candidates[a].cost.total_cmp(&candidates[b].cost)) else {
+            break;
+        };
+        let path = candidates.swap_remove(best_idx).path;
+
+        let route = path_to_route(graph, &path, &cost_evaluator);
+        if alternatives.iter().all(|alternative| similarity(alternative, &route) <= max_similarity) {
+            alternatives.push(route);
+        }
+
+        for candidate in spur_paths(graph, route_details, &path, &examined) {
+            if seen.insert(candidate.clone()) {
+                candidates.push(Candidate { cost: cost_evaluator.weighted(&cost_evaluator.path_cost(graph, &candidate)), path: candidate });
+            }
+        }
+        examined.push(path);
+    }
+
+    info!("Found {} alternative routes after examining {} paths", alternatives.len(), examined.len());
+    alternatives
+}
+
+/// Paths deviating from the given one at each of its nodes, avoiding the deviations already examined
+fn spur_paths(graph: &Graph, route_details: &RouteDetails, path: &[NodeId], examined: &[Vec<NodeId>]) -> Vec<Vec<NodeId>> {
+    let cost_evaluator = CostEvaluator::new(route_details);
+    let root_times = path_to_route(graph, path, &cost_evaluator).node_times;
+
+    (0..path.len() - 1).filter_map(|spur_idx| {
+        let root = &path[..=spur_idx];
+        let mut excluded_edges: HashSet<(NodeId, NodeId)> = examined.iter()
+            .filter(|other| other.len() > spur_idx + 1 && other[..=spur_idx] == *root)
+            .map(|other| (other[spur_idx], other[spur_idx + 1]))
+            .collect();
+        excluded_edges.insert((path[spur_idx], path[spur_idx + 1]));
+        let excluded_nodes: HashSet<NodeId> = root[..spur_idx].iter().copied().collect();
+
+        //Spur is flown after the root, so it is evaluated from the time the drone reaches the spur node
+        let spur_details = RouteDetails {
+            departure_time: root_times.get(spur_idx).copied().unwrap_or(route_details.departure_time),
+            ..route_details.clone()
+        };
+        let spur = shortest_path_avoiding(graph, path[spur_idx], route_details.ending_node, &CostEvaluator::new(&spur_details), &excluded_nodes, &excluded_edges)?;
+        Some(root[..spur_idx].iter().chain(spur.iter()).copied().collect())
+    }).collect()
+}
+
+/// Part of the length of the shorter route that is shared with the other one
+fn similarity(first: &Route, second: &Route) -> f32 {
+    let first_edges: HashSet<(NodeId, NodeId)> = first.edges.iter().map(|edge| (edge.from, edge.to)).collect();
+    let shared: f64 = second.edges.iter()
+        .filter(|edge| first_edges.contains(&(edge.from, edge.to)))
+        .map(|edge| edge.length)
+        .sum();
+    let length = |route: &Route| route.edges.iter().map(|edge| edge.length).sum::<f64>();
+    let shorter = length(first).min(length(second));
+    if shorter > 0.0 { (shared / shorter) as f32 } else { 1.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lattice::{lattice_graph, lattice_node};
+
+    use super::*;
+
+    #[test]
+    fn test_alternatives_are_ordered_and_distinct() {
+        let graph = lattice_graph(4, 4);
+        let route_details = RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 3));
+
+        let alternatives = generate_alternatives(&graph, &route_details, 3, 1.0);
+
+        assert_eq!(alternatives.len(), 3);
+        for route in &alternatives {
+            assert_eq!(route.edges.first().map(|edge| edge.from), Some(lattice_node(4, 0, 0)));
+            assert_eq!(route.edges.last().map(|edge| edge.to), Some(lattice_node(4, 3, 3)));
+            assert!(route.cost().distance > 0.0);
+        }
+        assert!(alternatives.windows(2).all(|pair| pair[0].cost().distance <= pair[1].cost().distance + 0.01));
+        assert_ne!(alternatives[0].edges, alternatives[1].edges);
+    }
+
+    #[test]
+    fn test_similar_alternatives_are_skipped() {
+        let graph = lattice_graph(4, 4);
+        let route_details = RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 3));
+
+        let alternatives = generate_alternatives(&graph, &route_details, 3, 0.5);
+
+        assert!(alternatives.len() >= 2);
+        for (idx, route) in alternatives.iter().enumerate() {
+            for other in &alternatives[..idx] {
+                assert!(similarity(other, route) <= 0.5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_alternatives_in_single_corridor() {
+        let graph = lattice_graph(3, 1);
+        let route_details = RouteDetails::new(lattice_node(3, 0, 0), lattice_node(3, 2, 0));
+
+        assert_eq!(generate_alternatives(&graph, &route_details, 3, MAX_ROUTE_SIMILARITY).len(), 1);
+    }
+
+    #[test]
+    fn test_validated_alternatives_ignore_waypoints() {
+        let graph = lattice_graph(4, 4);
+        let route_details = RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 3))
+            .with_waypoints(vec![lattice_node(4, 3, 0)])
+            .with_validation();
+
+        assert_eq!(crate::generate_alternatives(&graph, &route_details, 3).len(), 3);
+    }
+
+    #[test]
+    fn test_alternatives_stop_when_budget_is_exhausted() {
+        let graph = lattice_graph(4, 4);
+        let route_details = RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 3)).with_max_iterations(1);
+
+        assert_eq!(generate_alternatives(&graph, &route_details, 3, 1.0).len(), 1);
+    }
+}
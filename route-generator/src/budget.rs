@@ -0,0 +1,169 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Flag shared between threads - provider stops as soon as it notices that generation was cancelled
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Limits of a single route generation. When any of them is reached, the provider returns the best route found so far marked as partial.
+/// Iterations are counted in the provider's own steps - simulations of ACO, swarm updates of PSO, moves of greedy and node expansions of A*
+#[derive(Debug, Clone, Default)]
+pub struct Budget {
+    pub time_limit: Option<Duration>,
+    pub max_iterations: Option<u32>,
+    pub cancellation: Option<CancellationToken>,
+}
+
+impl Budget {
+    pub const fn new() -> Self {
+        Budget {
+            time_limit: None,
+            max_iterations: None,
+            cancellation: None,
+        }
+    }
+
+    /// Budget left for the part of the generation starting now, when the whole generation started at `started`.
+    /// Parts share one deadline, iterations are counted by every part separately
+    pub(crate) fn remaining_since(&self, started: Instant) -> Budget {
+        Budget {
+            time_limit: self.time_limit.map(|time_limit| time_limit.saturating_sub(started.elapsed())),
+            ..self.clone()
+        }
+    }
+}
+
+/// Budget of the generation that has already started
+pub(crate) struct BudgetTracker<'a> {
+    budget: &'a Budget,
+    started: Instant,
+}
+
+impl<'a> BudgetTracker<'a> {
+    pub(crate) fn new(budget: &'a Budget) -> Self {
+        BudgetTracker {
+            budget,
+            started: Instant::now(),
+        }
+    }
+
+    pub(crate) fn is_exhausted(&self, iterations: u32) -> bool {
+        self.budget.max_iterations.is_some_and(|max_iterations| iterations >= max_iterations) ||
+            self.budget.time_limit.is_some_and(|time_limit| self.started.elapsed() >= time_limit) ||
+            self.budget.cancellation.as_ref().is_some_and(CancellationToken::is_cancelled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use mgr_weather::image_wrapper::{GRID_SIZE, Rgba};
+
+    use crate::{generate_route_rn_with, generate_route_with, GridPosition, RouteDetails, WxRouteDetails};
+    use crate::lattice::{lattice_graph, lattice_node};
+    use crate::registry::ProviderRegistry;
+
+    use super::*;
+
+    #[test]
+    fn test_budget_limits() {
+        let unlimited = Budget::new();
+        assert!(!BudgetTracker::new(&unlimited).is_exhausted(u32::MAX));
+
+        let iterations = Budget { max_iterations: Some(5), ..Budget::new() };
+        assert!(!BudgetTracker::new(&iterations).is_exhausted(4));
+        assert!(BudgetTracker::new(&iterations).is_exhausted(5));
+
+        let time = Budget { time_limit: Some(Duration::ZERO), ..Budget::new() };
+        assert!(BudgetTracker::new(&time).is_exhausted(0));
+
+        let token = CancellationToken::new();
+        let cancellation = Budget { cancellation: Some(token.clone()), ..Budget::new() };
+        let tracker = BudgetTracker::new(&cancellation);
+        assert!(!tracker.is_exhausted(0));
+        thread::spawn(move || token.cancel()).join().unwrap();
+        assert!(tracker.is_exhausted(0));
+    }
+
+    #[test]
+    fn test_remaining_budget_shares_deadline() {
+        let budget = Budget { time_limit: Some(Duration::from_secs(60)), max_iterations: Some(5), ..Budget::new() };
+        let started = Instant::now().checked_sub(Duration::from_secs(20)).unwrap();
+
+        let remaining = budget.remaining_since(started);
+        assert!(remaining.time_limit.unwrap() <= Duration::from_secs(40));
+        assert_eq!(remaining.max_iterations, Some(5));
+        let late = Instant::now().checked_sub(Duration::from_secs(90)).unwrap();
+        assert_eq!(budget.remaining_since(late).time_limit, Some(Duration::ZERO));
+        assert_eq!(Budget::new().remaining_since(late).time_limit, None);
+    }
+
+    #[test]
+    fn test_graph_providers_return_partial_route() {
+        let graph = lattice_graph(5, 5);
+        let route_details = RouteDetails::new(lattice_node(5, 0, 0), lattice_node(5, 4, 4)).with_seed(3);
+
+        let registry = ProviderRegistry::new();
+        for name in registry.names() {
+            let provider = registry.get(name).unwrap();
+            let complete = generate_route_with(&graph, provider, &route_details);
+            assert!(!complete.partial, "{}", name);
+
+            let partial = generate_route_with(&graph, provider, &route_details.clone().with_max_iterations(3));
+            assert!(partial.partial, "{}", name);
+            if let Some(first_edge) = partial.edges.first() {
+                assert_eq!(first_edge.from, route_details.starting_node, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cancelled_generation_stops() {
+        let graph = lattice_graph(5, 5);
+        let token = CancellationToken::new();
+        let route_details = RouteDetails::new(lattice_node(5, 0, 0), lattice_node(5, 4, 4))
+            .with_waypoints(vec![lattice_node(5, 4, 0)])
+            .with_cancellation(token.clone());
+        thread::spawn(move || token.cancel()).join().unwrap();
+
+        let registry = ProviderRegistry::new();
+        for name in registry.names() {
+            let provider = registry.get(name).unwrap();
+            let route = generate_route_with(&graph, provider, &route_details);
+            assert!(route.partial, "{}", name);
+            //Generation stops on the first leg
+            assert!(route.legs.len() <= 1, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_grid_providers_return_partial_route() {
+        let grid = vec![Rgba([0, 0, 0, 0]); GRID_SIZE * GRID_SIZE];
+        let route_details = WxRouteDetails::new(GridPosition { x: 10.0, y: 10.0 }, GridPosition { x: 30.0, y: 20.0 })
+            .with_seed(3)
+            .with_time_limit(Duration::ZERO);
+
+        let registry = ProviderRegistry::new();
+        for name in registry.names() {
+            let provider = registry.get(name).unwrap();
+            let route = generate_route_rn_with(provider, &route_details, &grid);
+            assert!(route.partial, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,569 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign};
+
+use osmpbfreader::NodeId;
+
+use mgr_map_extractor::graph::{Edge, Graph, Node};
+use mgr_weather::image_wrapper::{GRID_SIZE, PixelColor};
+use tcas_adapter::data_feed::{CongestionStats, TimeWindow};
+
+use crate::{GridPosition, Route, RouteDetails};
+use crate::drone::DroneProfile;
+use crate::path::{graph_edge, node_distance};
+use crate::wx_grid::echo_intensity;
+
+//Cruise speed of the drone in m/s
+pub const DEFAULT_CRUISE_SPEED: f32 = 15.0;
+
+//Energy used for flying one meter at cruise speed, in Wh
+pub const DEFAULT_ENERGY_PER_METER: f32 = 0.0133;
+
+//Weight given to the other components while sweeping the weights for the pareto front.
+//Small enough not to outweigh the favoured component, but it still breaks ties between routes equal in it
+const PARETO_TIE_BREAK_WEIGHT: f32 = 0.000_001;
+
+/// Components of the cost of flying along an edge or a whole route
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CostBreakdown {
+    //Meters
+    pub distance: f32,
+    //Seconds
+    pub time: f32,
+    //Watt-hours
+    pub energy: f32,
+    //Meters flown through the weather echo, weighted by its intensity
+    pub weather: f32,
+    //Number of other drones met on the way
+    pub congestion: f32,
+}
+
+/// Importance of each cost component, route with the lowest weighted sum is the best one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostWeights {
+    pub distance: f32,
+    pub time: f32,
+    pub energy: f32,
+    pub weather: f32,
+    pub congestion: f32,
+}
+
+/// Model evaluating the cost of flying along a single graph edge
+pub trait CostModel: Debug + Send + Sync {
+    fn edge_cost(&self, graph: &Graph, edge: &Edge) -> CostBreakdown;
+
+    /// Cost of entering the edge at given time, in seconds from the start of the plan.
+    /// Models with conditions changing in time (weather frames, congestion forecasts) override it
+    fn edge_cost_at(&self, graph: &Graph, edge: &Edge, _time: f32) -> CostBreakdown {
+        self.edge_cost(graph, edge)
+    }
+
+    /// Lowest possible weighted cost of covering given straight line distance, used as a search heuristic.
+    /// It must never be greater than the real cost, zero is always safe
+    fn lower_bound(&self, _distance: f32, _weights: &CostWeights) -> f32 {
+        0.0
+    }
+
+    /// Every edge is evaluated at the time the route enters it
+    fn route_cost(&self, graph: &Graph, route: &Route) -> CostBreakdown {
+        let mut time = route.departure_time;
+        route.edges().iter().map(|edge| {
+            let cost = self.edge_cost_at(graph, edge, time);
+            time += cost.time;
+            cost
+        }).sum()
+    }
+}
+
+/// Geographic area covered by the weather grid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoBounds {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+}
+
+#[derive(Debug, Clone)]
+struct WeatherLayer {
+    grid: Vec<PixelColor>,
+    bounds: GeoBounds,
+    //Time from which the radar frame is valid, until the next frame starts
+    valid_from: f32,
+}
+
+/// Cost model of a drone flying with cruise speed, unless other speed is set for the edge.
+/// Weather exposure and congestion are only counted when the weather frames or congestion stats are provided
+#[derive(Debug, Clone)]
+pub struct DefaultCostModel {
+    cruise_speed: f32,
+    energy_per_meter: f32,
+    edge_speeds: BTreeMap<(NodeId, NodeId), f32>,
+    //Sorted by the time they are valid from
+    weather: Vec<WeatherLayer>,
+    congestion: Option<CongestionStats>,
+}
+
+/// Cost model together with the weights and the departure time requested for the route
+pub(crate) struct CostEvaluator<'a> {
+    model: &'a dyn CostModel,
+    //Energy of the edges comes from the drone profile instead of the model, when the request has one
+    drone: Option<DroneProfile>,
+    weights: CostWeights,
+    departure_time: f32,
+    //Weighted costs of the edges changed since the route was planned, None for closed edges
+    overrides: Option<&'a HashMap<(NodeId, NodeId), Option<f32>>>,
+}
+
+static DEFAULT_COST_MODEL: DefaultCostModel = DefaultCostModel::new();
+
+impl CostBreakdown {
+    pub fn weighted(&self, weights: &CostWeights) -> f32 {
+        self.distance * weights.distance
+            + self.time * weights.time
+            + self.energy * weights.energy
+            + self.weather * weights.weather
+            + self.congestion * weights.congestion
+    }
+
+    fn scaled(&self, factor: f32) -> CostBreakdown {
+        CostBreakdown {
+            distance: self.distance * factor,
+            time: self.time * factor,
+            energy: self.energy * factor,
+            weather: self.weather * factor,
+            congestion: self.congestion * factor,
+        }
+    }
+
+    /// Cost is not worse in any component and better in at least one
+    pub fn dominates(&self, other: &CostBreakdown) -> bool {
+        let pairs = self.components().into_iter().zip(other.components());
+        pairs.clone().all(|(a, b)| a <= b) && pairs.clone().any(|(a, b)| a < b)
+    }
+
+    fn components(&self) -> [f32; 5] {
+        [self.distance, self.time, self.energy, self.weather, self.congestion]
+    }
+}
+
+impl Add for CostBreakdown {
+    type Output = CostBreakdown;
+
+    fn add(mut self, other: CostBreakdown) -> CostBreakdown {
+        self += other;
+        self
+    }
+}
+
+impl AddAssign for CostBreakdown {
+    fn add_assign(&mut self, other: CostBreakdown) {
+        self.distance += other.distance;
+        self.time += other.time;
+        self.energy += other.energy;
+        self.weather += other.weather;
+        self.congestion += other.congestion;
+    }
+}
+
+impl Sum for CostBreakdown {
+    fn sum<I: Iterator<Item=CostBreakdown>>(iter: I) -> CostBreakdown {
+        iter.fold(CostBreakdown::default(), Add::add)
+    }
+}
+
+impl CostWeights {
+    //Only the distance matters - behaviour of the providers before cost models were introduced
+    pub const DISTANCE: CostWeights = CostWeights {
+        distance: 1.0,
+        time: 0.0,
+        energy: 0.0,
+        weather: 0.0,
+        congestion: 0.0,
+    };
+
+    pub const ENERGY: CostWeights = CostWeights {
+        distance: 0.0,
+        time: 0.0,
+        energy: 1.0,
+        weather: 0.0,
+        congestion: 0.0,
+    };
+
+    pub const TIME: CostWeights = CostWeights {
+        distance: 0.0,
+        time: 1.0,
+        energy: 0.0,
+        weather: 0.0,
+        congestion: 0.0,
+    };
+
+    /// Weights used while looking for the pareto front - each component favoured in turn, then all of them equally
+    pub fn pareto_sweep() -> Vec<CostWeights> {
+        let favouring = |component: usize| {
+            let mut weights = [PARETO_TIE_BREAK_WEIGHT; 5];
+            weights[component] = 1.0;
+            CostWeights::from(weights)
+        };
+        (0..5).map(favouring).chain([CostWeights::from([1.0; 5])]).collect()
+    }
+}
+
+impl Default for CostWeights {
+    fn default() -> Self {
+        CostWeights::DISTANCE
+    }
+}
+
+impl From<[f32; 5]> for CostWeights {
+    fn from(weights: [f32; 5]) -> Self {
+        CostWeights {
+            distance: weights[0],
+            time: weights[1],
+            energy: weights[2],
+            weather: weights[3],
+            congestion: weights[4],
+        }
+    }
+}
+
+impl GeoBounds {
+    /// Position on the weather grid, north is at the top (row 0) of the grid
+    fn to_grid_position(self, node: &Node) -> GridPosition {
+        GridPosition {
+            x: ((node.lon() - self.min_lon) / (self.max_lon - self.min_lon) * GRID_SIZE as f64) as f32,
+            y: ((self.max_lat - node.lat()) / (self.max_lat - self.min_lat) * GRID_SIZE as f64) as f32,
+        }
+    }
+
+    /// Latitude and longitude of the position on the weather grid
+    pub fn to_geo_coordinates(self, position: &GridPosition) -> (f64, f64) {
+        (
+            self.max_lat - position.y as f64 / GRID_SIZE as f64 * (self.max_lat - self.min_lat),
+            self.min_lon + position.x as f64 / GRID_SIZE as f64 * (self.max_lon - self.min_lon),
+        )
+    }
+}
+
+impl WeatherLayer {
+    /// Edge length multiplied by the mean echo intensity along it, sampled twice per crossed grid cell
+    fn exposure(&self, from: &Node, to: &Node, length: f32) -> f32 {
+        let from_position = self.bounds.to_grid_position(from);
+        let to_position = self.bounds.to_grid_position(to);
+        let cells_crossed = (to_position.x - from_position.x).hypot(to_position.y - from_position.y);
+        let samples = (cells_crossed * 2.0).ceil().max(1.0) as usize;
+
+        let intensity_sum: f32 = (0..=samples).map(|sample| {
+            let t = sample as f32 / samples as f32;
+            let position = GridPosition {
+                x: from_position.x + (to_position.x - from_position.x) * t,
+                y: from_position.y + (to_position.y - from_position.y) * t,
+            };
+            echo_intensity(&self.grid, &position).unwrap_or(0.0)
+        }).sum();
+        length * intensity_sum / (samples + 1) as f32
+    }
+}
+
+impl DefaultCostModel {
+    pub const fn new() -> Self {
+        DefaultCostModel {
+            cruise_speed: DEFAULT_CRUISE_SPEED,
+            energy_per_meter: DEFAULT_ENERGY_PER_METER,
+            edge_speeds: BTreeMap::new(),
+            weather: Vec::new(),
+            congestion: None,
+        }
+    }
+
+    pub fn with_cruise_speed(mut self, cruise_speed: f32) -> Self {
+        self.cruise_speed = cruise_speed;
+        self
+    }
+
+    pub fn with_energy_per_meter(mut self, energy_per_meter: f32) -> Self {
+        self.energy_per_meter = energy_per_meter;
+        self
+    }
+
+    /// Speed of flying along the edge in the given direction, instead of the cruise speed
+    pub fn with_edge_speed(mut self, from: NodeId, to: NodeId, speed: f32) -> Self {
+        self.edge_speeds.insert((from, to), speed);
+        self
+    }
+
+    /// Weather that does not change in time
+    pub fn with_weather(self, grid: Vec<PixelColor>, bounds: GeoBounds) -> Self {
+        self.with_weather_frame(0.0, grid, bounds)
+    }
+
+    /// Radar frame valid from the given time until the start of the next frame. The earliest frame is also used before it starts
+    pub fn with_weather_frame(mut self, valid_from: f32, grid: Vec<PixelColor>, bounds: GeoBounds) -> Self {
+        self.weather.push(WeatherLayer { grid, bounds, valid_from });
+        self.weather.sort_by(|a, b| a.valid_from.total_cmp(&b.valid_from));
+        self
+    }
+
+    /// Drones currently on the edges and, through the reservations, the forecast of drones flying there later
+    pub fn with_congestion(mut self, congestion: CongestionStats) -> Self {
+        self.congestion = Some(congestion);
+        self
+    }
+
+    fn edge_speed(&self, edge: &Edge) -> f32 {
+        self.edge_speeds.get(&(edge.from, edge.to)).copied().unwrap_or(self.cruise_speed)
+    }
+
+    fn weather_frame(&self, time: f32) -> Option<&WeatherLayer> {
+        self.weather.iter().rev().find(|frame| frame.valid_from <= time).or(self.weather.first())
+    }
+}
+
+impl Default for DefaultCostModel {
+    fn default() -> Self {
+        DefaultCostModel::new()
+    }
+}
+
+impl CostModel for DefaultCostModel {
+    fn edge_cost(&self, graph: &Graph, edge: &Edge) -> CostBreakdown {
+        self.edge_cost_at(graph, edge, 0.0)
+    }
+
+    fn edge_cost_at(&self, graph: &Graph, edge: &Edge, time: f32) -> CostBreakdown {
+        let distance = edge.length as f32;
+        let travel_time = distance / self.edge_speed(edge);
+        let weather = self.weather_frame(time).map_or(0.0, |weather| {
+            match (graph.nodes().get(&Node::id(edge.from)), graph.nodes().get(&Node::id(edge.to))) {
+                (Some(from), Some(to)) => weather.exposure(from, to, distance),
+                _ => 0.0,
+            }
+        });
+        //Drones flying in both directions of the edge are in the way, now and while the edge is being crossed
+        let window = TimeWindow::new(time, time + travel_time);
+        let congestion = self.congestion.as_ref().map_or(0, |congestion| {
+            [(edge.from, edge.to), (edge.to, edge.from)].iter().map(|key| {
+                let current = congestion.congestion().get(key).map_or(0, Vec::len);
+                let forecast = congestion.reservations().get(key).into_iter().flatten()
+                    .filter(|(_, reserved)| reserved.overlaps(&window))
+                    .count();
+                current + forecast
+            }).sum()
+        });
+
+        CostBreakdown {
+            distance,
+            time: travel_time,
+            energy: distance * self.energy_per_meter,
+            weather,
+            congestion: congestion as f32,
+        }
+    }
+
+    fn lower_bound(&self, distance: f32, weights: &CostWeights) -> f32 {
+        let max_speed = self.edge_speeds.values().fold(self.cruise_speed, |max_speed, &speed| max_speed.max(speed));
+        CostBreakdown {
+            distance,
+            time: distance / max_speed,
+            energy: distance * self.energy_per_meter,
+            weather: 0.0,
+            congestion: 0.0,
+        }.weighted(weights)
+    }
+}
+
+impl<'a> CostEvaluator<'a> {
+    pub(crate) fn new(route_details: &'a RouteDetails) -> Self {
+        CostEvaluator {
+            model: route_details.cost_model(),
+            drone: route_details.drone,
+            weights: route_details.cost_weights,
+            departure_time: route_details.departure_time,
+            overrides: None,
+        }
+    }
+
+    pub(crate) fn with_overrides(mut self, overrides: &'a HashMap<(NodeId, NodeId), Option<f32>>) -> Self {
+        self.overrides = Some(overrides);
+        self
+    }
+
+    pub(crate) fn departure_time(&self) -> f32 {
+        self.departure_time
+    }
+
+    /// Weighted cost of the edge entered at the departure time, for providers that do not track the time
+    pub(crate) fn edge_cost(&self, graph: &Graph, from: NodeId, to: NodeId) -> f32 {
+        self.edge_cost_at(graph, from, to, self.departure_time).0
+    }
+
+    /// Weighted cost of the edge entered at given time, together with the time of flying along it
+    pub(crate) fn edge_cost_at(&self, graph: &Graph, from: NodeId, to: NodeId, time: f32) -> (f32, f32) {
+        let cost = self.model_cost_at(graph, &graph_edge(graph, from, to), time);
+        (cost.weighted(&self.weights), cost.time)
+    }
+
+    /// Lower bound of the cost of getting from one node to another
+    pub(crate) fn estimate(&self, graph: &Graph, from: NodeId, to: NodeId) -> f32 {
+        let distance = node_distance(graph, from, to);
+        match self.drone {
+            Some(drone) => {
+                let weights = CostWeights { energy: 0.0, ..self.weights };
+                self.model.lower_bound(distance, &weights) + drone.flight_energy(distance) * self.weights.energy
+            }
+            None => self.model.lower_bound(distance, &self.weights),
+        }
+    }
+
+    /// Lower bound of the time of flying from one node to another
+    pub(crate) fn time_estimate(&self, graph: &Graph, from: NodeId, to: NodeId) -> f32 {
+        self.model.lower_bound(node_distance(graph, from, to), &CostWeights::TIME)
+    }
+
+    /// Cost of the edge entered at given time, with the drone and the overrides applied
+    pub(crate) fn model_cost_at(&self, graph: &Graph, edge: &Edge, time: f32) -> CostBreakdown {
+        let mut cost = self.model.edge_cost_at(graph, edge, time);
+        if let Some(drone) = self.drone {
+            cost.energy = drone.flight_energy(cost.distance);
+        }
+        //Overridden edge keeps the proportions of its breakdown, scaled to the new weighted cost
+        if let Some(Some(overridden)) = self.overrides.and_then(|overrides| overrides.get(&(edge.from, edge.to))) {
+            let weighted = cost.weighted(&self.weights);
+            if weighted > 0.0 {
+                cost = cost.scaled(overridden / weighted);
+            }
+        }
+        cost
+    }
+
+    pub(crate) fn path_cost(&self, graph: &Graph, path: &[NodeId]) -> CostBreakdown {
+        self.timed_cost(graph, path.windows(2).map(|pair| graph_edge(graph, pair[0], pair[1]))).0
+    }
+
+    /// Route flown from the departure time, with its cost and the time of arrival at every node
+    pub(crate) fn timed_route(&self, graph: &Graph, mut route: Route) -> Route {
+        let (cost, node_times) = self.timed_cost(graph, route.edges.iter().copied());
+        route.departure_time = self.departure_time;
+        route.cost = cost;
+        route.node_times = node_times;
+        route
+    }
+
+    fn timed_cost(&self, graph: &Graph, edges: impl Iterator<Item=Edge>) -> (CostBreakdown, Vec<f32>) {
+        let mut node_times = vec![self.departure_time];
+        let mut time = self.departure_time;
+        let cost = edges.map(|edge| {
+            let cost = self.model_cost_at(graph, &edge, time);
+            time += cost.time;
+            node_times.push(time);
+            cost
+        }).sum();
+
+        if node_times.len() == 1 {
+            node_times.clear();
+        }
+        (cost, node_times)
+    }
+
+    pub(crate) fn weighted(&self, cost: &CostBreakdown) -> f32 {
+        cost.weighted(&self.weights)
+    }
+}
+
+/// Default model used when the request does not provide its own one
+pub(crate) fn default_cost_model() -> &'static DefaultCostModel {
+    &DEFAULT_COST_MODEL
+}
+
+/// Keep only routes that are not dominated by any other one, identical routes are kept once
+pub fn pareto_front(routes: Vec<Route>) -> Vec<Route> {
+    let mut front: Vec<Route> = vec![];
+    for route in routes.iter() {
+        let dominated = routes.iter().any(|other| other.cost.dominates(&route.cost));
+        let duplicate = front.iter().any(|kept| kept.edges == route.edges);
+        if !dominated && !duplicate && !route.edges.is_empty() {
+            front.push(route.clone());
+        }
+    }
+    front
+}
+
+#[cfg(test)]
+mod tests {
+    use mgr_weather::image_wrapper::Rgba;
+
+    use crate::{generate_pareto_front, RouteGeneratorStrategy};
+    use crate::lattice::{lattice_graph, lattice_node};
+
+    use super::*;
+
+    #[test]
+    fn test_dominates() {
+        let cheap = CostBreakdown { distance: 1.0, time: 1.0, ..Default::default() };
+        let expensive = CostBreakdown { distance: 2.0, time: 1.0, ..Default::default() };
+        let trade_off = CostBreakdown { distance: 0.5, time: 3.0, ..Default::default() };
+
+        assert!(cheap.dominates(&expensive));
+        assert!(!expensive.dominates(&cheap));
+        assert!(!cheap.dominates(&cheap));
+        assert!(!cheap.dominates(&trade_off));
+        assert!(!trade_off.dominates(&cheap));
+    }
+
+    #[test]
+    fn test_weather_exposure_only_inside_echo() {
+        let graph = lattice_graph(3, 3);
+        let mut grid = vec![Rgba([0, 0, 0, 0]); GRID_SIZE * GRID_SIZE];
+        //Echo over the whole top half of the grid, which is the northern row of the lattice
+        grid.iter_mut().take(GRID_SIZE * GRID_SIZE / 2 - GRID_SIZE).for_each(|pixel| *pixel = Rgba([255, 0, 0, 255]));
+        let bounds = GeoBounds { min_lat: 0.0, min_lon: 0.0, max_lat: 0.002, max_lon: 0.002 };
+        let model = DefaultCostModel::new().with_weather(grid, bounds);
+
+        let northern_edge = graph_edge(&graph, lattice_node(3, 0, 2), lattice_node(3, 1, 2));
+        let southern_edge = graph_edge(&graph, lattice_node(3, 0, 0), lattice_node(3, 1, 0));
+
+        let northern_cost = model.edge_cost(&graph, &northern_edge);
+        assert!((northern_cost.weather - northern_cost.distance).abs() < 0.001);
+        assert_eq!(model.edge_cost(&graph, &southern_edge).weather, 0.0);
+    }
+
+    #[test]
+    fn test_weather_frame_with_nan_start_does_not_panic() {
+        let grid = vec![Rgba([0, 0, 0, 0]); GRID_SIZE * GRID_SIZE];
+        let bounds = GeoBounds { min_lat: 0.0, min_lon: 0.0, max_lat: 0.002, max_lon: 0.002 };
+        let model = DefaultCostModel::new()
+            .with_weather_frame(10.0, grid.clone(), bounds)
+            .with_weather_frame(f32::NAN, grid, bounds);
+
+        assert!(model.weather_frame(20.0).is_some());
+    }
+
+    #[test]
+    fn test_congestion_counts_both_directions() {
+        let graph = lattice_graph(2, 1);
+        let edge = graph_edge(&graph, lattice_node(2, 0, 0), lattice_node(2, 1, 0));
+        let mut congestion = CongestionStats::new();
+        congestion.add_congestion(edge, 1);
+        congestion.add_congestion(Edge::new(edge.to, edge.from, edge.length, edge.highway_type), 2);
+
+        let model = DefaultCostModel::new().with_congestion(congestion);
+        assert_eq!(model.edge_cost(&graph, &edge).congestion, 2.0);
+    }
+
+    #[test]
+    fn test_pareto_front_keeps_trade_offs() {
+        let graph = lattice_graph(3, 2);
+        let mut congestion = CongestionStats::new();
+        congestion.add_congestion(graph_edge(&graph, lattice_node(3, 0, 0), lattice_node(3, 1, 0)), 1);
+        let route_details = RouteDetails::new(lattice_node(3, 0, 0), lattice_node(3, 2, 0))
+            .with_cost_model(DefaultCostModel::new().with_congestion(congestion));
+
+        let front = generate_pareto_front(&graph, RouteGeneratorStrategy::AStar, &route_details);
+
+        //Short route through the congested edge and the longer one around it
+        assert_eq!(front.len(), 2);
+        assert!(front.iter().any(|route| route.edges().len() == 2 && route.cost().congestion == 1.0));
+        assert!(front.iter().any(|route| route.edges().len() == 4 && route.cost().congestion == 0.0));
+    }
+}
@@ -0,0 +1,201 @@
+use mgr_map_extractor::graph::{Edge, Graph};
+
+use crate::{PixRoute, Route};
+use crate::cost::{CostBreakdown, CostModel, CostWeights, DEFAULT_CRUISE_SPEED, DEFAULT_ENERGY_PER_METER};
+use crate::wx_grid::{GRID_CELL_LENGTH, polyline_length};
+
+//Standard gravity in m/s^2
+const GRAVITY: f32 = 9.81;
+
+//Joules in a single watt-hour
+const JOULES_PER_WH: f32 = 3600.0;
+
+/// Physical parameters of the drone that decide how far it can fly on a single battery charge
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DroneProfile {
+    //Take-off mass with the payload, kg
+    pub mass: f32,
+    //m/s
+    pub cruise_speed: f32,
+    //Energy used for flying one meter at cruise speed, Wh
+    pub consumption_per_meter: f32,
+    //How many times more energy than the gained potential energy is used while climbing
+    pub climb_penalty: f32,
+    //Altitude the drone climbs to right after the take-off, m
+    pub cruise_altitude: f32,
+    //Wh
+    pub battery_capacity: f32,
+    //Part of the battery capacity that must be left after landing, from 0.0 to 1.0
+    pub reserve: f32,
+}
+
+/// Energy needed to fly the route compared with the energy the drone can use
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnergyReport {
+    pub required: f32,
+    pub usable: f32,
+}
+
+impl DroneProfile {
+    /// Small quadcopter carrying the rPI Zero W controller
+    pub const fn new() -> Self {
+        DroneProfile {
+            mass: 1.2,
+            cruise_speed: DEFAULT_CRUISE_SPEED,
+            consumption_per_meter: DEFAULT_ENERGY_PER_METER,
+            climb_penalty: 2.5,
+            cruise_altitude: 50.0,
+            battery_capacity: 60.0,
+            reserve: 0.2,
+        }
+    }
+
+    pub fn usable_energy(&self) -> f32 {
+        self.battery_capacity * (1.0 - self.reserve)
+    }
+
+    /// Energy of climbing to the cruise altitude, used once per flight
+    pub fn climb_energy(&self) -> f32 {
+        self.climb_penalty * self.mass * GRAVITY * self.cruise_altitude / JOULES_PER_WH
+    }
+
+    pub fn flight_energy(&self, distance: f32) -> f32 {
+        distance * self.consumption_per_meter
+    }
+
+    pub fn evaluate_route(&self, graph: &Graph, route: &Route) -> EnergyReport {
+        let distance: f32 = route.edges().iter().map(|edge| self.edge_cost(graph, edge).distance).sum();
+        self.evaluate_distance(distance)
+    }
+
+    /// Waypoints of the grid route are given in grid cells, each one is GRID_CELL_LENGTH metres long
+    pub fn evaluate_pix_route(&self, route: &PixRoute) -> EnergyReport {
+        self.evaluate_distance(polyline_length(route.waypoints()) * GRID_CELL_LENGTH)
+    }
+
+    fn evaluate_distance(&self, distance: f32) -> EnergyReport {
+        let required = if distance > 0.0 {
+            self.climb_energy() + self.flight_energy(distance)
+        } else {
+            0.0
+        };
+        EnergyReport {
+            required,
+            usable: self.usable_energy(),
+        }
+    }
+}
+
+impl Default for DroneProfile {
+    fn default() -> Self {
+        DroneProfile::new()
+    }
+}
+
+/// Drone profile is a cost model itself, so providers can optimise routes for the energy it uses
+impl CostModel for DroneProfile {
+    fn edge_cost(&self, _graph: &Graph, edge: &Edge) -> CostBreakdown {
+        let distance = edge.length as f32;
+        CostBreakdown {
+            distance,
+            time: distance / self.cruise_speed,
+            energy: self.flight_energy(distance),
+            ..Default::default()
+        }
+    }
+
+    fn lower_bound(&self, distance: f32, weights: &CostWeights) -> f32 {
+        CostBreakdown {
+            distance,
+            time: distance / self.cruise_speed,
+            energy: self.flight_energy(distance),
+            ..Default::default()
+        }.weighted(weights)
+    }
+}
+
+impl EnergyReport {
+    pub fn is_feasible(&self) -> bool {
+        self.required <= self.usable
+    }
+
+    /// Energy left above the reserve after landing, negative when the route cannot be flown
+    pub fn remaining(&self) -> f32 {
+        self.usable - self.required
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{generate_route, GridPosition, RouteDetails, RouteGenerator, RouteGeneratorStrategy, RouteRejection};
+    use crate::cost::DefaultCostModel;
+    use crate::lattice::{lattice_graph, lattice_node};
+    use crate::providers::astar::RouteGeneratorAStar;
+
+    use super::*;
+
+    #[test]
+    fn test_energy_includes_climb_once() {
+        let drone = DroneProfile::new();
+        let graph = lattice_graph(4, 1);
+        let route_details = RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 0));
+        let route = RouteGeneratorAStar.generate_route(&graph, &route_details);
+
+        let report = drone.evaluate_route(&graph, &route);
+        let expected = drone.climb_energy() + drone.flight_energy(route.cost().distance);
+        assert!((report.required - expected).abs() < 0.0001);
+        assert!(report.is_feasible());
+    }
+
+    #[test]
+    fn test_route_exceeding_battery_is_infeasible() {
+        let drone = DroneProfile { battery_capacity: 10.0, ..DroneProfile::new() };
+        //99 cells of 30 m take around 40 Wh, far more than 80% of the 10 Wh battery
+        let route = PixRoute::new(vec![GridPosition { x: 0.0, y: 0.0 }, GridPosition { x: 99.0, y: 0.0 }]);
+
+        let report = drone.evaluate_pix_route(&route);
+        assert!(!report.is_feasible());
+        assert!(report.remaining() < 0.0);
+        assert_eq!(drone.evaluate_pix_route(&PixRoute::default()).required, 0.0);
+    }
+
+    #[test]
+    fn test_generated_route_rejected_when_battery_too_small() {
+        let graph = lattice_graph(4, 4);
+        let route_details = RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 3))
+            .with_cost_weights(CostWeights::ENERGY);
+
+        let big_battery = route_details.clone().with_drone(DroneProfile::new());
+        let route = generate_route(&graph, RouteGeneratorStrategy::AStar, &big_battery);
+        assert_eq!(route.edges().len(), 6);
+        assert!(route.cost().energy > 0.0);
+
+        let small_battery = route_details.with_drone(DroneProfile { battery_capacity: 1.0, ..DroneProfile::new() });
+        let rejected = generate_route(&graph, RouteGeneratorStrategy::AStar, &small_battery);
+        assert!(rejected.edges().is_empty());
+        //Nothing derived from the rejected route is left, the energy report tells why it was rejected
+        assert_eq!(rejected.cost(), &CostBreakdown::default());
+        assert!(rejected.node_times.is_empty() && rejected.legs.is_empty());
+        let Some(RouteRejection::Energy(report)) = rejected.rejection else {
+            panic!("Route should be rejected for its energy");
+        };
+        assert!(report.required > report.usable);
+    }
+
+    #[test]
+    fn test_drone_keeps_custom_cost_model() {
+        let graph = lattice_graph(4, 4);
+        let drone = DroneProfile { consumption_per_meter: 0.02, ..DroneProfile::new() };
+        let cost_model = DefaultCostModel::new().with_cruise_speed(5.0);
+        let route_details = RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 3));
+
+        let drone_first = route_details.clone().with_drone(drone).with_cost_model(cost_model.clone());
+        let model_first = route_details.with_cost_model(cost_model).with_drone(drone);
+        let route = generate_route(&graph, RouteGeneratorStrategy::AStar, &drone_first);
+        assert_eq!(route.cost(), generate_route(&graph, RouteGeneratorStrategy::AStar, &model_first).cost());
+
+        //Time comes from the cost model, energy from the drone
+        assert!((route.cost().time - route.cost().distance / 5.0).abs() < 0.001);
+        assert!((route.cost().energy - drone.flight_energy(route.cost().distance)).abs() < 0.0001);
+    }
+}
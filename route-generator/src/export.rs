@@ -0,0 +1,198 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use mgr_map_extractor::graph::{Graph, Node};
+
+use crate::{PixRoute, Route};
+use crate::cost::GeoBounds;
+
+const DEFAULT_TRACK_NAME: &str = "Route";
+
+//LineString of every format needs at least two positions
+const MIN_TRACK_POINTS: usize = 2;
+
+/// File format of the exported route
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    //GeoJSON Feature with a LineString geometry
+    GeoJson,
+    //GPX track with a single segment
+    Gpx,
+    //KML Placemark with a LineString
+    Kml,
+}
+
+impl ExportFormat {
+    /// Format matching the file extension, None for unknown extensions
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "geojson" | "json" => Some(ExportFormat::GeoJson),
+            "gpx" => Some(ExportFormat::Gpx),
+            "kml" => Some(ExportFormat::Kml),
+            _ => None,
+        }
+    }
+}
+
+/// Route as a line of geographic coordinates, ready to be exported
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoTrack {
+    pub name: String,
+    //Latitude and longitude of every route point in order
+    pub points: Vec<(f64, f64)>,
+}
+
+impl GeoTrack {
+    /// Track going through all nodes of the route, nodes missing from the graph are skipped.
+    /// None when less than two nodes are left, such a track is not a valid line
+    pub fn from_route(graph: &Graph, route: &Route) -> Option<Self> {
+        let node_ids = route.edges.first().map(|edge| edge.from).into_iter()
+            .chain(route.edges.iter().map(|edge| edge.to));
+        GeoTrack::from_points(node_ids.filter_map(|node_id| graph.nodes().get(&Node::id(node_id)))
+            .map(Node::get_coordinates)
+            .collect())
+    }
+
+    /// Track going through the waypoints of the route, with the weather grid covering the given area.
+    /// None for routes with less than two waypoints
+    pub fn from_pix_route(route: &PixRoute, bounds: GeoBounds) -> Option<Self> {
+        GeoTrack::from_points(route.waypoints.iter().map(|position| bounds.to_geo_coordinates(position)).collect())
+    }
+
+    fn from_points(points: Vec<(f64, f64)>) -> Option<Self> {
+        (points.len() >= MIN_TRACK_POINTS).then(|| GeoTrack {
+            name: DEFAULT_TRACK_NAME.to_string(),
+            points,
+        })
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn to_geojson(&self) -> String {
+        let coordinates: Vec<String> = self.points.iter().map(|(lat, lon)| format!("[{},{}]", lon, lat)).collect();
+        format!("{{\"type\":\"Feature\",\"properties\":{{\"name\":{}}},\"geometry\":{{\"type\":\"LineString\",\"coordinates\":[{}]}}}}",
+                json_string(&self.name),
+                coordinates.join(","))
+    }
+
+    pub fn to_gpx(&self) -> String {
+        let mut gpx = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        gpx.push_str("<gpx version=\"1.1\" creator=\"mgr\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n");
+        let _ = writeln!(gpx, "  <trk>\n    <name>{}</name>\n    <trkseg>", xml_text(&self.name));
+        for (lat, lon) in &self.points {
+            let _ = writeln!(gpx, "      <trkpt lat=\"{}\" lon=\"{}\"/>", lat, lon);
+        }
+        gpx.push_str("    </trkseg>\n  </trk>\n</gpx>\n");
+        gpx
+    }
+
+    pub fn to_kml(&self) -> String {
+        let coordinates: Vec<String> = self.points.iter().map(|(lat, lon)| format!("{},{}", lon, lat)).collect();
+        let mut kml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        kml.push_str("<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n");
+        let _ = writeln!(kml, "  <Placemark>\n    <name>{}</name>", xml_text(&self.name));
+        let _ = writeln!(kml, "    <LineString>\n      <coordinates>{}</coordinates>\n    </LineString>", coordinates.join(" "));
+        kml.push_str("  </Placemark>\n</kml>\n");
+        kml
+    }
+
+    pub fn export(&self, format: ExportFormat) -> String {
+        match format {
+            ExportFormat::GeoJson => self.to_geojson(),
+            ExportFormat::Gpx => self.to_gpx(),
+            ExportFormat::Kml => self.to_kml(),
+        }
+    }
+
+    /// Write the track in the format matching the file extension
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let format = ExportFormat::from_path(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown export format of {:?}", path)))?;
+        fs::write(path, self.export(format))
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for character in value.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            character if character.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", character as u32);
+            }
+            character => escaped.push(character),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn xml_text(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use mgr_weather::image_wrapper::GRID_SIZE;
+
+    use crate::GridPosition;
+    use crate::lattice::{lattice_graph, lattice_node};
+
+    use super::*;
+
+    fn lattice_track() -> GeoTrack {
+        let graph = lattice_graph(3, 3);
+        let edges = graph.edge_by_node_id();
+        let route = Route::new(vec![
+            edges[&(lattice_node(3, 0, 0), lattice_node(3, 1, 0))],
+            edges[&(lattice_node(3, 1, 0), lattice_node(3, 1, 1))],
+        ]);
+        GeoTrack::from_route(&graph, &route).unwrap().with_name("Bielany & \"Marymont\"")
+    }
+
+    #[test]
+    fn test_track_from_routes() {
+        assert_eq!(lattice_track().points, vec![(0.0, 0.0), (0.0, 0.001), (0.001, 0.001)]);
+        assert_eq!(GeoTrack::from_route(&lattice_graph(3, 3), &Route::default()), None);
+
+        let bounds = GeoBounds { min_lat: 52.0, min_lon: 20.0, max_lat: 53.0, max_lon: 21.0 };
+        let half = GRID_SIZE as f32 / 2.0;
+        let route = PixRoute::new(vec![GridPosition { x: 0.0, y: 0.0 }, GridPosition { x: half, y: half }]);
+        assert_eq!(GeoTrack::from_pix_route(&route, bounds).unwrap().points, vec![(53.0, 20.0), (52.5, 20.5)]);
+        let single_point = PixRoute::new(vec![GridPosition { x: 0.0, y: 0.0 }]);
+        assert_eq!(GeoTrack::from_pix_route(&single_point, bounds), None);
+    }
+
+    #[test]
+    fn test_export_formats() {
+        let track = lattice_track();
+        assert_eq!(track.to_geojson(), "{\"type\":\"Feature\",\"properties\":{\"name\":\"Bielany & \\\"Marymont\\\"\"},\
+            \"geometry\":{\"type\":\"LineString\",\"coordinates\":[[0,0],[0.001,0],[0.001,0.001]]}}");
+
+        let gpx = track.to_gpx();
+        assert!(gpx.contains("<name>Bielany &amp; \"Marymont\"</name>"));
+        assert!(gpx.contains("<trkpt lat=\"0\" lon=\"0.001\"/>"));
+        assert_eq!(gpx.matches("<trkpt").count(), 3);
+
+        let kml = track.to_kml();
+        assert!(kml.contains("<coordinates>0,0 0.001,0 0.001,0.001</coordinates>"));
+        assert_eq!(track.export(ExportFormat::Kml), kml);
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(ExportFormat::from_path(Path::new("route.geojson")), Some(ExportFormat::GeoJson));
+        assert_eq!(ExportFormat::from_path(Path::new("out/route.GPX")), Some(ExportFormat::Gpx));
+        assert_eq!(ExportFormat::from_path(Path::new("route.kml")), Some(ExportFormat::Kml));
+        assert_eq!(ExportFormat::from_path(Path::new("route.csv")), None);
+        assert_eq!(lattice_track().write("route.csv").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
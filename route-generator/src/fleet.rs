@@ -0,0 +1,281 @@
+use std::collections::{BinaryHeap, HashSet};
+
+use log::{info, warn};
+use osmpbfreader::NodeId;
+
+use mgr_map_extractor::graph::Graph;
+use tcas_adapter::data_feed::{CongestionStats, DroneId, TimeWindow};
+
+use crate::{Route, RouteDetails, RouteLeg};
+use crate::budget::BudgetTracker;
+use crate::cost::{CostBreakdown, CostEvaluator};
+use crate::path::graph_edge;
+use crate::providers::astar::OpenEntry;
+
+//How long the drone hovers in place when it waits for the edge to become free, s
+pub const WAIT_STEP: f32 = 2.0;
+
+//Extra time kept free before and after every reserved edge traversal, s
+pub const SEPARATION_TIME: f32 = 1.0;
+
+//Maximum number of space-time states expanded while planning a single drone
+pub const FLEET_SEARCH_LIMIT: usize = 200_000;
+
+//Times closer than this are treated as the same moment while detecting already visited states, s
+const TIME_RESOLUTION: f32 = 0.5;
+
+/// Route of a single drone of the fleet together with its schedule
+#[derive(Debug, Clone, Default)]
+pub struct FleetRoute {
+    pub drone_id: DroneId,
+    pub route: Route,
+    //Time at which the drone enters every edge of the route, s
+    pub departure_times: Vec<f32>,
+    pub arrival_time: f32,
+}
+
+/// State of the space-time search - drone is at the node at the given time, with `stop` the index of the next stop to reach
+#[derive(Clone, Copy)]
+struct SearchState {
+    node: NodeId,
+    time: f32,
+    stop: usize,
+    parent: Option<usize>,
+}
+
+/// Plan routes of all drones so no two of them fly through the same edge at the same time.
+/// Drones are planned one after another in the given order (prioritised planning), every one of them
+/// avoids the edges already reserved in the congestion stats and then reserves its own
+pub fn plan_fleet(graph: &Graph, drones: &[(DroneId, RouteDetails)], congestion: &mut CongestionStats) -> Vec<FleetRoute> {
+    drones.iter().map(|(drone_id, route_details)| {
+        let fleet_route = plan_drone(graph, *drone_id, route_details, congestion);
+        //Drone leaves the edge when it arrives at its last node
+        for ((edge, &departure_time), &arrival_time) in fleet_route.route.edges.iter()
+            .zip(fleet_route.departure_times.iter())
+            .zip(fleet_route.route.node_times.iter().skip(1)) {
+            congestion.reserve(*edge, *drone_id, TimeWindow::new(departure_time - SEPARATION_TIME, arrival_time + SEPARATION_TIME));
+        }
+        fleet_route
+    }).collect()
+}
+
+/// Index of the next stop to reach after arriving at the node, stops.len() when all of them were reached
+fn next_stop(stops: &[NodeId], mut stop: usize, node: NodeId) -> usize {
+    while stop < stops.len() && stops[stop] == node {
+        stop += 1;
+    }
+    stop
+}
+
+/// Space-time A* - the drone either flies to a neighbouring node or hovers for WAIT_STEP, earliest arrival through all the waypoints
+/// is searched for. Travel times come from the cost model of the request, at the time the drone enters the edge
+fn plan_drone(graph: &Graph, drone_id: DroneId, route_details: &RouteDetails, congestion: &CongestionStats) -> FleetRoute {
+    let cost_evaluator = CostEvaluator::new(route_details);
+    let budget = BudgetTracker::new(&route_details.budget);
+    let mut stops = vec![route_details.starting_node];
+    stops.extend_from_slice(&route_details.waypoints);
+    stops.push(route_details.ending_node);
+
+    //Lowest possible time of flying from every stop through the following ones to the ending node
+    let mut remaining_times = vec![0.0; stops.len()];
+    for stop in (0..stops.len() - 1).rev() {
+        remaining_times[stop] = remaining_times[stop + 1] + cost_evaluator.time_estimate(graph, stops[stop], stops[stop + 1]);
+    }
+    let heuristic = |node: NodeId, stop: usize| match stops.get(stop) {
+        Some(&target) => cost_evaluator.time_estimate(graph, node, target) + remaining_times[stop],
+        None => 0.0,
+    };
+
+    let first_stop = next_stop(&stops, 1, route_details.starting_node);
+    let mut states = vec![SearchState { node: route_details.starting_node, time: route_details.departure_time, stop: first_stop, parent: None }];
+    let mut open = BinaryHeap::from([OpenEntry { estimated_cost: heuristic(route_details.starting_node, first_stop), item: 0 }]);
+    let mut closed: HashSet<(NodeId, usize, i64)> = HashSet::new();
+    //Expanded state with the lowest estimated time left, where the interrupted search ends its route
+    let mut closest = (f32::INFINITY, 0);
+
+    while let Some(OpenEntry { item: state_idx, .. }) = open.pop() {
+        let SearchState { node, time, stop, .. } = states[state_idx];
+        if stop == stops.len() {
+            return fleet_route(graph, drone_id, &cost_evaluator, &stops, &states, state_idx);
+        }
+        if budget.is_exhausted(closed.len() as u32) {
+            info!("Budget exhausted, returning route of drone {} to the state closest to the ending node", drone_id);
+            let fleet_route = fleet_route(graph, drone_id, &cost_evaluator, &stops, &states, closest.1);
+            return FleetRoute { route: Route { partial: true, ..fleet_route.route }, ..fleet_route };
+        }
+        if !closed.insert((node, stop, (time / TIME_RESOLUTION).round() as i64)) {
+            continue;
+        }
+        if states.len() >= FLEET_SEARCH_LIMIT {
+            break;
+        }
+        let time_left = heuristic(node, stop);
+        if time_left < closest.0 {
+            closest = (time_left, state_idx);
+        }
+
+        let mut push_state = |next: NodeId, next_time: f32| {
+            let next_stop = next_stop(&stops, stop, next);
+            states.push(SearchState { node: next, time: next_time, stop: next_stop, parent: Some(state_idx) });
+            open.push(OpenEntry { estimated_cost: next_time + heuristic(next, next_stop), item: states.len() - 1 });
+        };
+
+        for &next in graph.edge_connections().get(&node).into_iter().flatten() {
+            let (_, travel_time) = cost_evaluator.edge_cost_at(graph, node, next, time);
+            let arrival_time = time + travel_time;
+            let window = TimeWindow::new(time - SEPARATION_TIME, arrival_time + SEPARATION_TIME);
+            if !congestion.is_reserved(graph_edge(graph, node, next), window, drone_id) {
+                push_state(next, arrival_time);
+            }
+        }
+        push_state(node, time + WAIT_STEP);
+    }
+
+    warn!("Conflict free route for drone {} not found", drone_id);
+    FleetRoute { drone_id, ..Default::default() }
+}
+
+fn fleet_route(graph: &Graph, drone_id: DroneId, cost_evaluator: &CostEvaluator, stops: &[NodeId], states: &[SearchState], last_idx: usize) -> FleetRoute {
+    let mut visited = vec![states[last_idx]];
+    while let Some(parent) = visited.last().unwrap().parent {
+        visited.push(states[parent]);
+    }
+    visited.reverse();
+
+    //Hovering in place is not a part of the route, drone leaves the node when it has waited for the last time.
+    //Every edge is evaluated at the time the drone enters it
+    let mut route = Route { departure_time: visited[0].time, ..Default::default() };
+    let mut node_times = vec![visited[0].time];
+    let mut departure_times = vec![];
+    let mut leg = RouteLeg { from: stops[0], to: stops[0], edge_count: 0, cost: CostBreakdown::default() };
+    let mut reached = 1;
+    for (idx, state) in visited.iter().enumerate() {
+        if idx > 0 && visited[idx - 1].node != state.node {
+            let previous = visited[idx - 1];
+            let edge = graph_edge(graph, previous.node, state.node);
+            let cost = cost_evaluator.model_cost_at(graph, &edge, previous.time);
+            route.edges.push(edge);
+            route.cost += cost;
+            leg.edge_count += 1;
+            leg.cost += cost;
+            node_times.push(state.time);
+            departure_times.push(previous.time);
+        }
+        //Every waypoint reached at this node ends a leg, legs are kept only when the route has waypoints
+        while reached < state.stop {
+            leg.to = stops[reached];
+            if stops.len() > 2 {
+                route.legs.push(leg);
+            }
+            leg = RouteLeg { from: leg.to, to: leg.to, edge_count: 0, cost: CostBreakdown::default() };
+            reached += 1;
+        }
+    }
+
+    let arrival_time = states[last_idx].time;
+    info!("Drone {} arrives after {} s", drone_id, arrival_time);
+    if !route.edges.is_empty() {
+        route.node_times = node_times;
+    }
+    FleetRoute {
+        drone_id,
+        route,
+        departure_times,
+        arrival_time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cost::{DEFAULT_CRUISE_SPEED, DefaultCostModel};
+    use crate::lattice::{lattice_graph, lattice_node};
+
+    use super::*;
+
+    fn traversals(fleet_route: &FleetRoute) -> Vec<(NodeId, NodeId, TimeWindow)> {
+        fleet_route.route.edges.iter().zip(fleet_route.departure_times.iter()).zip(fleet_route.route.node_times.iter().skip(1))
+            .map(|((edge, &departure), &arrival)| (edge.from, edge.to, TimeWindow::new(departure, arrival)))
+            .collect()
+    }
+
+    #[test]
+    fn test_drones_flying_towards_each_other_do_not_conflict() {
+        let graph = lattice_graph(3, 2);
+        let drones = [
+            (1, RouteDetails::new(lattice_node(3, 0, 0), lattice_node(3, 2, 0))),
+            (2, RouteDetails::new(lattice_node(3, 2, 0), lattice_node(3, 0, 0))),
+        ];
+
+        let fleet_routes = plan_fleet(&graph, &drones, &mut CongestionStats::new());
+
+        //First drone has the priority and flies straight
+        assert_eq!(fleet_routes[0].route.edges.len(), 2);
+        assert_eq!(fleet_routes[1].route.edges.last().map(|edge| edge.to), Some(lattice_node(3, 0, 0)));
+        for (from, to, window) in traversals(&fleet_routes[0]) {
+            for (other_from, other_to, other_window) in traversals(&fleet_routes[1]) {
+                let same_edge = (from, to) == (other_from, other_to) || (from, to) == (other_to, other_from);
+                assert!(!(same_edge && window.overlaps(&other_window)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_drone_waits_for_reserved_corridor() {
+        //Single corridor, so the drone cannot go around the reserved edge
+        let graph = lattice_graph(2, 1);
+        let edge = graph_edge(&graph, lattice_node(2, 0, 0), lattice_node(2, 1, 0));
+        let mut congestion = CongestionStats::new();
+        congestion.reserve(edge, 7, TimeWindow::new(0.0, 30.0));
+
+        let fleet_routes = plan_fleet(&graph, &[(1, RouteDetails::new(lattice_node(2, 0, 0), lattice_node(2, 1, 0)))], &mut congestion);
+
+        assert_eq!(fleet_routes[0].route.edges.len(), 1);
+        assert!(fleet_routes[0].departure_times[0] >= 30.0 + SEPARATION_TIME);
+        assert!(congestion.is_reserved(edge, TimeWindow::new(fleet_routes[0].departure_times[0], fleet_routes[0].arrival_time), 7));
+    }
+
+    #[test]
+    fn test_travel_times_follow_edge_speeds_of_cost_model() {
+        let graph = lattice_graph(2, 1);
+        let (from, to) = (lattice_node(2, 0, 0), lattice_node(2, 1, 0));
+        let edge = graph_edge(&graph, from, to);
+        let route_details = RouteDetails::new(from, to)
+            .with_cost_model(DefaultCostModel::new().with_edge_speed(from, to, 2.0 * DEFAULT_CRUISE_SPEED));
+        let mut congestion = CongestionStats::new();
+
+        let fleet_routes = plan_fleet(&graph, &[(1, route_details)], &mut congestion);
+
+        let travel_time = edge.length as f32 / (2.0 * DEFAULT_CRUISE_SPEED);
+        assert!((fleet_routes[0].arrival_time - travel_time).abs() < 1e-3);
+        //Reservation ends when the drone leaves the faster edge, not after flying it at the cruise speed
+        assert!(!congestion.is_reserved(edge, TimeWindow::new(travel_time + SEPARATION_TIME + 1.0, travel_time * 2.0 + SEPARATION_TIME + 1.0), 7));
+    }
+
+    #[test]
+    fn test_drone_flies_through_waypoints() {
+        let graph = lattice_graph(3, 3);
+        let waypoint = lattice_node(3, 1, 2);
+        let route_details = RouteDetails::new(lattice_node(3, 0, 0), lattice_node(3, 2, 0)).with_waypoints(vec![waypoint]);
+
+        let fleet_routes = plan_fleet(&graph, &[(1, route_details)], &mut CongestionStats::new());
+
+        let route = &fleet_routes[0].route;
+        assert!(route.edges.iter().any(|edge| edge.to == waypoint));
+        assert_eq!(route.edges.last().map(|edge| edge.to), Some(lattice_node(3, 2, 0)));
+        assert_eq!(route.legs.iter().map(|leg| (leg.from, leg.to)).collect::<Vec<_>>(),
+                   vec![(lattice_node(3, 0, 0), waypoint), (waypoint, lattice_node(3, 2, 0))]);
+        assert_eq!(route.legs.iter().map(|leg| leg.edge_count).sum::<usize>(), route.edges.len());
+    }
+
+    #[test]
+    fn test_exhausted_budget_gives_partial_route() {
+        let graph = lattice_graph(5, 5);
+        let route_details = RouteDetails::new(lattice_node(5, 0, 0), lattice_node(5, 4, 4)).with_max_iterations(3);
+
+        let fleet_routes = plan_fleet(&graph, &[(1, route_details)], &mut CongestionStats::new());
+
+        let route = &fleet_routes[0].route;
+        assert!(route.partial);
+        assert_ne!(route.edges.last().map(|edge| edge.to), Some(lattice_node(5, 4, 4)));
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use osmpbfreader::NodeId;
+
+use mgr_map_extractor::ApprovedHighwayType;
+use mgr_map_extractor::graph::{Edge, Graph, Node};
+
+use crate::Route;
+use crate::cost::DEFAULT_CRUISE_SPEED;
+
+//Turns sharper than this (in degrees) end the instruction, smaller ones are treated as going straight
+const STRAIGHT_ANGLE: f64 = 20.0;
+const SLIGHT_TURN_ANGLE: f64 = 60.0;
+const TURN_ANGLE: f64 = 135.0;
+const SHARP_TURN_ANGLE: f64 = 170.0;
+
+/// Overview of the whole route
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteSummary {
+    //In meters
+    pub distance: f64,
+    //In seconds, taken from the route timing or estimated with the default cruise speed
+    pub duration: f32,
+    pub turn_count: usize,
+    //Distance in meters flown along each type of highway
+    pub highway_distances: HashMap<ApprovedHighwayType, f64>,
+}
+
+/// What to do at the end of the instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Maneuver {
+    //Way name changes, but the direction does not
+    Straight,
+    SlightLeft,
+    Left,
+    SharpLeft,
+    SlightRight,
+    Right,
+    SharpRight,
+    UTurn,
+    Arrive,
+}
+
+/// Single step of the route - follow the way for the given distance, then make the maneuver
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    //None for unnamed ways
+    pub way_name: Option<String>,
+    //In meters
+    pub distance: f64,
+    //Index of the first route edge covered by the instruction
+    pub first_edge: usize,
+    pub edge_count: usize,
+    pub maneuver: Maneuver,
+}
+
+impl Maneuver {
+    /// Maneuver for the change of direction in degrees, positive when turning left
+    fn from_angle(angle: f64) -> Self {
+        let left = angle > 0.0;
+        match angle.abs() {
+            angle if angle < STRAIGHT_ANGLE => Maneuver::Straight,
+            angle if angle < SLIGHT_TURN_ANGLE => if left { Maneuver::SlightLeft } else { Maneuver::SlightRight },
+            angle if angle < TURN_ANGLE => if left { Maneuver::Left } else { Maneuver::Right },
+            angle if angle < SHARP_TURN_ANGLE => if left { Maneuver::SharpLeft } else { Maneuver::SharpRight },
+            _ => Maneuver::UTurn,
+        }
+    }
+
+    pub fn is_turn(&self) -> bool {
+        !matches!(self, Maneuver::Straight | Maneuver::Arrive)
+    }
+}
+
+impl Display for Maneuver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Maneuver::Straight => "go straight",
+            Maneuver::SlightLeft => "bear left",
+            Maneuver::Left => "turn left",
+            Maneuver::SharpLeft => "turn sharp left",
+            Maneuver::SlightRight => "bear right",
+            Maneuver::Right => "turn right",
+            Maneuver::SharpRight => "turn sharp right",
+            Maneuver::UTurn => "make a U-turn",
+            Maneuver::Arrive => "arrive at the destination",
+        };
+        f.write_str(text)
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.way_name {
+            Some(way_name) => write!(f, "continue on {} for {:.0} m, {}", way_name, self.distance, self.maneuver),
+            None => write!(f, "continue for {:.0} m, {}", self.distance, self.maneuver),
+        }
+    }
+}
+
+pub fn route_summary(graph: &Graph, route: &Route) -> RouteSummary {
+    let distance = route.edges.iter().map(|edge| edge.length).sum();
+    let duration = match (route.node_times.first(), route.node_times.last()) {
+        (Some(departure), Some(arrival)) => arrival - departure,
+        _ => distance as f32 / DEFAULT_CRUISE_SPEED,
+    };
+
+    let mut highway_distances = HashMap::new();
+    for edge in &route.edges {
+        *highway_distances.entry(edge.highway_type).or_insert(0.0) += edge.length;
+    }
+
+    RouteSummary {
+        distance,
+        duration,
+        turn_count: turn_instructions(graph, route).iter().filter(|instruction| instruction.maneuver.is_turn()).count(),
+        highway_distances,
+    }
+}
+
+/// Split the route into instructions. Instruction ends where the way name changes or the route turns
+pub fn turn_instructions(graph: &Graph, route: &Route) -> Vec<Instruction> {
+    let mut instructions: Vec<Instruction> = Vec::new();
+    for (edge_idx, edge) in route.edges.iter().enumerate() {
+        let way_name = graph.way_name(edge.from, edge.to);
+        if let Some(current) = instructions.last_mut() {
+            let maneuver = Maneuver::from_angle(turn_angle(graph, &route.edges[edge_idx - 1], edge));
+            if maneuver == Maneuver::Straight && current.way_name.as_deref() == way_name {
+                current.distance += edge.length;
+                current.edge_count += 1;
+                continue;
+            }
+            current.maneuver = maneuver;
+        }
+        instructions.push(Instruction {
+            way_name: way_name.map(str::to_string),
+            distance: edge.length,
+            first_edge: edge_idx,
+            edge_count: 1,
+            maneuver: Maneuver::Arrive,
+        });
+    }
+    instructions
+}
+
+/// Change of direction between the edge and the next one in degrees, positive when turning left
+fn turn_angle(graph: &Graph, edge: &Edge, next_edge: &Edge) -> f64 {
+    let (Some(heading), Some(next_heading)) = (heading(graph, edge.from, edge.to), heading(graph, next_edge.from, next_edge.to)) else {
+        return 0.0;
+    };
+    let angle = (next_heading - heading).to_degrees();
+    //Normalise to (-180, 180]
+    if angle > 180.0 {
+        angle - 360.0
+    } else if angle <= -180.0 {
+        angle + 360.0
+    } else {
+        angle
+    }
+}
+
+/// Direction of the edge in radians, counterclockwise from the east.
+/// Degree of longitude gets shorter away from the equator, so it is scaled by the cosine of the latitude
+fn heading(graph: &Graph, from: NodeId, to: NodeId) -> Option<f64> {
+    let from = graph.nodes().get(&Node::id(from))?;
+    let to = graph.nodes().get(&Node::id(to))?;
+    let mean_lat = ((from.lat() + to.lat()) / 2.0).to_radians();
+    Some((to.lat() - from.lat()).atan2((to.lon() - from.lon()) * mean_lat.cos()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::lattice::{lattice_graph, lattice_node};
+
+    use super::*;
+
+    /// Route on a 4x4 lattice going east along the bottom row, then north along the last column
+    fn corner_route(graph: &Graph) -> Route {
+        let nodes = [(0, 0), (1, 0), (2, 0), (3, 0), (3, 1), (3, 2)];
+        Route::new(nodes.windows(2)
+            .map(|pair| graph.edge_by_node_id()[&(lattice_node(4, pair[0].0, pair[0].1), lattice_node(4, pair[1].0, pair[1].1))])
+            .collect())
+    }
+
+    #[test]
+    fn test_instructions_split_at_turns_and_name_changes() {
+        let mut graph = lattice_graph(4, 4);
+        let name: Arc<str> = Arc::from("Marymoncka");
+        for x in 0..2 {
+            graph.add_way_name(lattice_node(4, x, 0), lattice_node(4, x + 1, 0), name.clone());
+        }
+        let route = corner_route(&graph);
+
+        let instructions = turn_instructions(&graph, &route);
+        assert_eq!(instructions.len(), 3);
+        assert_eq!(instructions[0].way_name.as_deref(), Some("Marymoncka"));
+        assert_eq!(instructions[0].edge_count, 2);
+        assert_eq!(instructions[0].maneuver, Maneuver::Straight);
+        assert_eq!(instructions[1].way_name, None);
+        assert_eq!(instructions[1].first_edge, 2);
+        assert_eq!(instructions[1].maneuver, Maneuver::Left);
+        assert_eq!(instructions[2].edge_count, 2);
+        assert_eq!(instructions[2].maneuver, Maneuver::Arrive);
+
+        assert_eq!(instructions[0].to_string(), "continue on Marymoncka for 222 m, go straight");
+        assert_eq!(instructions[1].to_string(), "continue for 111 m, turn left");
+    }
+
+    #[test]
+    fn test_maneuver_from_angle() {
+        assert_eq!(Maneuver::from_angle(5.0), Maneuver::Straight);
+        assert_eq!(Maneuver::from_angle(-45.0), Maneuver::SlightRight);
+        assert_eq!(Maneuver::from_angle(-90.0), Maneuver::Right);
+        assert_eq!(Maneuver::from_angle(150.0), Maneuver::SharpLeft);
+        assert_eq!(Maneuver::from_angle(180.0), Maneuver::UTurn);
+    }
+
+    #[test]
+    fn test_heading_scales_longitude() {
+        let mut graph = Graph::new();
+        graph.add_node(Node::new(NodeId(1), 60.0, 20.0));
+        graph.add_node(Node::new(NodeId(2), 60.001, 20.002));
+
+        //At 60 degrees of latitude 0.002 degree of longitude is as long as 0.001 degree of latitude
+        let angle = heading(&graph, NodeId(1), NodeId(2)).unwrap().to_degrees();
+        assert!((angle - 45.0).abs() < 0.1);
+        assert_eq!(heading(&graph, NodeId(1), NodeId(3)), None);
+    }
+
+    #[test]
+    fn test_route_summary() {
+        let graph = lattice_graph(4, 4);
+        let route = corner_route(&graph);
+
+        let summary = route_summary(&graph, &route);
+        assert!((summary.distance - 5.0 * 111.1).abs() < 1e-6);
+        assert!((summary.duration - 5.0 * 111.1 / DEFAULT_CRUISE_SPEED).abs() < 1e-3);
+        assert_eq!(summary.turn_count, 1);
+        assert_eq!(summary.highway_distances.len(), 1);
+        assert!((summary.highway_distances[&ApprovedHighwayType::Residential] - summary.distance).abs() < 1e-6);
+
+        let empty = route_summary(&graph, &Route::default());
+        assert_eq!(empty.distance, 0.0);
+        assert_eq!(empty.turn_count, 0);
+    }
+}
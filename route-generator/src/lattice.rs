@@ -0,0 +1,47 @@
+use osmpbfreader::NodeId;
+
+use mgr_map_extractor::ApprovedHighwayType;
+use mgr_map_extractor::graph::{Edge, Graph, Node};
+
+//Distance between neighbouring lattice nodes in degrees, around 111 m
+pub const LATTICE_STEP: f64 = 0.001;
+
+/// Id of the lattice node at given column and row
+pub fn lattice_node(width: usize, x: usize, y: usize) -> NodeId {
+    NodeId((y * width + x + 1) as i64)
+}
+
+/// Build a width x height lattice with bidirectional edges between horizontal and vertical neighbours,
+/// node ids grow row by row starting with 1. Synthetic map used by the tests and benchmarks
+pub fn lattice_graph(width: usize, height: usize) -> Graph {
+    let mut graph = Graph::new();
+    for y in 0..height {
+        for x in 0..width {
+            graph.add_node(Node::new(lattice_node(width, x, y), y as f64 * LATTICE_STEP, x as f64 * LATTICE_STEP));
+        }
+    }
+
+    for y in 0..height {
+        for x in 0..width {
+            if x + 1 < width {
+                connect(&mut graph, lattice_node(width, x, y), lattice_node(width, x + 1, y));
+            }
+            if y + 1 < height {
+                connect(&mut graph, lattice_node(width, x, y), lattice_node(width, x, y + 1));
+            }
+        }
+    }
+    graph
+}
+
+/// Add bidirectional edge between two existing nodes, the same way extractor does
+pub fn connect(graph: &mut Graph, from: NodeId, to: NodeId) {
+    let length = Edge::length(
+        graph.nodes().get(&Node::id(from)).unwrap(),
+        graph.nodes().get(&Node::id(to)).unwrap(),
+    );
+    graph.add_edge(Edge::new(from, to, length, ApprovedHighwayType::Residential));
+    graph.add_edge(Edge::new(to, from, length, ApprovedHighwayType::Residential));
+    graph.add_edge_connection(from, to);
+    graph.add_edge_connection(to, from);
+}
@@ -1,27 +1,153 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 use log::warn;
 use osmpbfreader::NodeId;
 
 use mgr_map_extractor::graph::{Edge, Graph};
 use mgr_weather::image_wrapper::PixelColor;
 
+use crate::budget::{Budget, CancellationToken};
+use crate::cost::{CostBreakdown, CostModel, CostWeights, default_cost_model, pareto_front};
+use crate::drone::{DroneProfile, EnergyReport};
+use crate::providers::aco::AcoOptions;
+use crate::providers::greedy::GreedyOptions;
+use crate::telemetry::Telemetry;
+use crate::validation::{PixRouteIssue, RouteIssue};
+
+mod alternatives;
+pub mod budget;
+pub mod cost;
+pub mod drone;
+pub mod export;
+pub mod fleet;
+pub mod guidance;
+pub mod lattice;
+pub mod postprocess;
 pub mod providers;
+pub mod registry;
+pub mod replan;
+pub mod telemetry;
+pub mod validation;
+mod path;
+mod rng;
+mod tour;
+mod wx_grid;
+
+pub use crate::alternatives::{ALTERNATIVES_SEARCH_LIMIT, MAX_ROUTE_SIMILARITY};
+pub use crate::wx_grid::GRID_CELL_LENGTH;
 
 #[derive(Debug, Clone)]
 pub struct RouteDetails {
     pub starting_node: NodeId,
     pub ending_node: NodeId,
+    //Nodes visited in the given order between the starting and the ending node, every leg is generated separately
+    pub waypoints: Vec<NodeId>,
+    //Seconds from the start of the plan, conditions changing in time are evaluated from this moment.
+    //ACO ants and the greedy walk choose edges by their costs at the departure time, only the returned route is costed at its ETAs
+    pub departure_time: f32,
+    //Seed for the stochastic providers, random one is drawn when not given
+    pub seed: Option<u64>,
+    //Model evaluating graph edges, default one is used when not given.
+    //Greedy provider with its default goal distance metric does not use it, other graph providers do
+    pub cost_model: Option<Arc<dyn CostModel>>,
+    pub cost_weights: CostWeights,
+    //Routes that this drone cannot fly on a single charge are rejected
+    pub drone: Option<DroneProfile>,
+    //Routes with any validation issue are rejected
+    pub validate: bool,
+    //Settings of the greedy provider
+    pub greedy: GreedyOptions,
+    //Settings of the ACO provider
+    pub aco: AcoOptions,
+    //Metaheuristics record the state of every iteration in the route
+    pub telemetry: bool,
+    //Limits of the generation. Time limit is shared by all the legs, tours, alternatives and pareto routes of the request,
+    //iterations are counted separately by every generated leg
+    pub budget: Budget,
 }
 
-#[derive(Debug, Clone)]
-pub struct Route(pub Vec<Edge>);
+#[derive(Debug, Clone, Default)]
+pub struct Route {
+    pub edges: Vec<Edge>,
+    //Seed that stochastic provider used, generating route again with it gives identical result
+    pub seed: Option<u64>,
+    //Cost of the whole route evaluated with the cost model of the request
+    pub cost: CostBreakdown,
+    //Parts of the route between consecutive waypoints, empty when the route has no waypoints
+    pub legs: Vec<RouteLeg>,
+    pub departure_time: f32,
+    //Time of arrival at every node of the route, starting with the departure from the first one
+    pub node_times: Vec<f32>,
+    //Per-iteration trace of the metaheuristic, when the request asked for it
+    pub telemetry: Option<Telemetry>,
+    //Budget ran out before the provider finished, route is the best one found until then and may not reach the ending node
+    pub partial: bool,
+    //Why the generated route was replaced with an empty one, None when it was kept
+    pub rejection: Option<RouteRejection>,
+}
+
+/// Reason for replacing the generated route with an empty one
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteRejection {
+    //Drone of the request cannot fly the route on a single charge
+    Energy(EnergyReport),
+    //Route did not pass the validation that the request asked for
+    Validation(Vec<RouteIssue>),
+}
+
+/// Part of the route between two consecutive waypoints
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RouteLeg {
+    pub from: NodeId,
+    pub to: NodeId,
+    //Number of route edges belonging to this leg, legs are stored in the same order as edges
+    pub edge_count: usize,
+    pub cost: CostBreakdown,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PixRoute {
+    pub waypoints: Vec<GridPosition>,
+    pub seed: Option<u64>,
+    pub telemetry: Option<Telemetry>,
+    pub partial: bool,
+    //Why the generated route was replaced with an empty one, None when it was kept
+    pub rejection: Option<PixRouteRejection>,
+}
 
-#[derive(Debug)]
-pub struct PixRoute(pub Vec<GridPosition>);
+/// Reason for replacing the generated weather grid route with an empty one
+#[derive(Debug, Clone, PartialEq)]
+pub enum PixRouteRejection {
+    //Drone of the request cannot fly the route on a single charge
+    Energy(EnergyReport),
+    //Route did not pass the validation that the request asked for
+    Validation(Vec<PixRouteIssue>),
+}
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct WxRouteDetails {
     pub starting_position: GridPosition,
     pub ending_position: GridPosition,
+    pub seed: Option<u64>,
+    pub drone: Option<DroneProfile>,
+    pub validate: bool,
+    pub greedy: GreedyOptions,
+    pub aco: AcoOptions,
+    pub telemetry: bool,
+    pub budget: Budget,
+    //Whether weather echo blocks the cells or only makes crossing them more expensive
+    pub obstacles: ObstacleHandling,
+}
+
+/// How the grid providers treat cells with weather echo. PSO always penalises crossing the echo
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObstacleHandling {
+    //Cells with weather echo cannot be entered
+    #[default]
+    Blocked,
+    //Cells with weather echo can be crossed, but it is more expensive the stronger the echo is
+    Costly,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -30,57 +156,438 @@ pub struct GridPosition {
     pub y: f32,
 }
 
+impl RouteDetails {
+    pub const fn new(starting_node: NodeId, ending_node: NodeId) -> Self {
+        RouteDetails {
+            starting_node,
+            ending_node,
+            waypoints: Vec::new(),
+            departure_time: 0.0,
+            seed: None,
+            cost_model: None,
+            cost_weights: CostWeights::DISTANCE,
+            drone: None,
+            validate: false,
+            greedy: GreedyOptions::new(),
+            aco: AcoOptions::new(),
+            telemetry: false,
+            budget: Budget::new(),
+        }
+    }
+
+    pub fn with_waypoints(mut self, waypoints: Vec<NodeId>) -> Self {
+        self.waypoints = waypoints;
+        self
+    }
+
+    pub fn with_departure_time(mut self, departure_time: f32) -> Self {
+        self.departure_time = departure_time;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_cost_model(mut self, cost_model: impl CostModel + 'static) -> Self {
+        self.cost_model = Some(Arc::new(cost_model));
+        self
+    }
+
+    pub fn with_cost_weights(mut self, cost_weights: CostWeights) -> Self {
+        self.cost_weights = cost_weights;
+        self
+    }
+
+    /// Energy of the edges is evaluated with the drone profile, other cost components still come from the cost model
+    pub fn with_drone(mut self, drone: DroneProfile) -> Self {
+        self.drone = Some(drone);
+        self
+    }
+
+    pub fn with_validation(mut self) -> Self {
+        self.validate = true;
+        self
+    }
+
+    pub fn with_greedy(mut self, greedy: GreedyOptions) -> Self {
+        self.greedy = greedy;
+        self
+    }
+
+    pub fn with_aco(mut self, aco: AcoOptions) -> Self {
+        self.aco = aco;
+        self
+    }
+
+    pub fn with_telemetry(mut self) -> Self {
+        self.telemetry = true;
+        self
+    }
+
+    pub fn with_time_limit(mut self, time_limit: Duration) -> Self {
+        self.budget.time_limit = Some(time_limit);
+        self
+    }
+
+    pub fn with_max_iterations(mut self, max_iterations: u32) -> Self {
+        self.budget.max_iterations = Some(max_iterations);
+        self
+    }
+
+    /// Generation stops with the best route found so far once the token is cancelled, possibly from another thread
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.budget.cancellation = Some(cancellation);
+        self
+    }
+
+    pub fn cost_model(&self) -> &dyn CostModel {
+        match &self.cost_model {
+            Some(cost_model) => cost_model.as_ref(),
+            None => default_cost_model(),
+        }
+    }
+}
+
+impl WxRouteDetails {
+    pub const fn new(starting_position: GridPosition, ending_position: GridPosition) -> Self {
+        WxRouteDetails {
+            starting_position,
+            ending_position,
+            seed: None,
+            drone: None,
+            validate: false,
+            greedy: GreedyOptions::new(),
+            aco: AcoOptions::new(),
+            telemetry: false,
+            budget: Budget::new(),
+            obstacles: ObstacleHandling::Blocked,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_drone(mut self, drone: DroneProfile) -> Self {
+        self.drone = Some(drone);
+        self
+    }
+
+    pub fn with_validation(mut self) -> Self {
+        self.validate = true;
+        self
+    }
+
+    pub fn with_greedy(mut self, greedy: GreedyOptions) -> Self {
+        self.greedy = greedy;
+        self
+    }
+
+    pub fn with_aco(mut self, aco: AcoOptions) -> Self {
+        self.aco = aco;
+        self
+    }
+
+    pub fn with_telemetry(mut self) -> Self {
+        self.telemetry = true;
+        self
+    }
+
+    pub fn with_time_limit(mut self, time_limit: Duration) -> Self {
+        self.budget.time_limit = Some(time_limit);
+        self
+    }
+
+    pub fn with_max_iterations(mut self, max_iterations: u32) -> Self {
+        self.budget.max_iterations = Some(max_iterations);
+        self
+    }
+
+    /// Generation stops with the best route found so far once the token is cancelled, possibly from another thread
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.budget.cancellation = Some(cancellation);
+        self
+    }
+
+    pub fn with_obstacles(mut self, obstacles: ObstacleHandling) -> Self {
+        self.obstacles = obstacles;
+        self
+    }
+}
+
 impl Route {
+    pub fn new(edges: Vec<Edge>) -> Self {
+        Route {
+            edges,
+            seed: None,
+            cost: CostBreakdown::default(),
+            legs: vec![],
+            departure_time: 0.0,
+            node_times: vec![],
+            telemetry: None,
+            partial: false,
+            rejection: None,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_cost(mut self, cost: CostBreakdown) -> Self {
+        self.cost = cost;
+        self
+    }
+
     pub fn edges(&self) -> &Vec<Edge> {
-        &self.0
+        &self.edges
+    }
+
+    pub fn cost(&self) -> &CostBreakdown {
+        &self.cost
+    }
+
+    /// Estimated time of arrival at the last node, None for an empty route
+    pub fn arrival_time(&self) -> Option<f32> {
+        self.node_times.last().copied()
+    }
+
+    /// Empty route replacing the rejected one, with the seed and the telemetry of its generation
+    fn rejected(route: Route, rejection: RouteRejection) -> Self {
+        Route {
+            seed: route.seed,
+            telemetry: route.telemetry,
+            rejection: Some(rejection),
+            ..Route::default()
+        }
     }
 }
 
-pub trait RouteGenerator {
-    fn generate_route(graph: &Graph, generation_strategy: RouteGeneratorStrategy, route_details: &RouteDetails, mode: GenerationMode) -> Route;
-    fn generate_route_rn(_generation_strategy: RouteGeneratorStrategy, _route_details: &WxRouteDetails) -> PixRoute {
-        warn!("Real number mode not supported for this route generator");
-        PixRoute(vec![])
+impl PixRoute {
+    pub fn new(waypoints: Vec<GridPosition>) -> Self {
+        PixRoute {
+            waypoints,
+            seed: None,
+            telemetry: None,
+            partial: false,
+            rejection: None,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
     }
 
-    fn real_mode_supported() -> bool {
+    pub fn waypoints(&self) -> &Vec<GridPosition> {
+        &self.waypoints
+    }
+
+    /// Empty route replacing the rejected one, with the seed and the telemetry of its generation
+    fn rejected(route: PixRoute, rejection: PixRouteRejection) -> Self {
+        PixRoute {
+            seed: route.seed,
+            telemetry: route.telemetry,
+            rejection: Some(rejection),
+            ..PixRoute::default()
+        }
+    }
+}
+
+/// Route generation provider. Providers are used as trait objects, so they can hold their own configuration
+/// and third-party ones can be added to the `ProviderRegistry`
+pub trait RouteGenerator: Send + Sync {
+    /// Name under which the provider is registered
+    fn name(&self) -> &str;
+
+    fn generate_route(&self, graph: &Graph, route_details: &RouteDetails) -> Route;
+
+    fn real_mode_supported(&self) -> bool {
         false
     }
 
-    fn generate_route_real_num(_route_details: &WxRouteDetails, _grid: &Vec<PixelColor>) -> PixRoute {
+    fn generate_route_real_num(&self, _route_details: &WxRouteDetails, _grid: &[PixelColor]) -> PixRoute {
         warn!("Real mode not supported for this route generator");
-        PixRoute(vec![])
+        PixRoute::default()
     }
 }
 
+/// Not used by the generation anymore - graph and weather grid routes have their own functions.
+/// Kept only because it is a parameter of the route generation exported by the connector, so the C ABI stays unchanged
 #[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GenerationMode{
     WX,
     Graph
 }
 
 #[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RouteGeneratorStrategy {
     Empty,
     Greedy,
     ACO,
     PSO,
+    AStar,
+    ThetaStar,
+    GA,
+    SA,
+}
+
+impl RouteGeneratorStrategy {
+    /// Name of the built-in provider implementing the strategy, None for the empty one
+    pub const fn provider_name(&self) -> Option<&'static str> {
+        match self {
+            RouteGeneratorStrategy::Empty => None,
+            RouteGeneratorStrategy::Greedy => Some("Greedy"),
+            RouteGeneratorStrategy::ACO => Some("ACO"),
+            RouteGeneratorStrategy::PSO => Some("PSO"),
+            RouteGeneratorStrategy::AStar => Some("AStar"),
+            RouteGeneratorStrategy::ThetaStar => Some("ThetaStar"),
+            RouteGeneratorStrategy::GA => Some("GA"),
+            RouteGeneratorStrategy::SA => Some("SA"),
+        }
+    }
+
+    /// Built-in provider implementing the strategy
+    pub fn provider(&self) -> Option<&'static dyn RouteGenerator> {
+        self.provider_name().and_then(|name| registry::builtin_providers().get(name))
+    }
 }
 
-pub fn generate_route_rn(generation_strategy: RouteGeneratorStrategy, route_details: &WxRouteDetails, grid: &Vec<PixelColor>) -> PixRoute {
-    match generation_strategy {
-        RouteGeneratorStrategy::Greedy => providers::greedy::RouteGeneratorGreedy::generate_route_real_num(route_details, grid),
-        RouteGeneratorStrategy::ACO => providers::aco::RouteGeneratorACO::generate_route_real_num(route_details, grid),
-        RouteGeneratorStrategy::PSO => providers::pso::RouteGeneratorPSO::generate_route_real_num(route_details, grid),
-        RouteGeneratorStrategy::Empty => PixRoute(vec![]),
+pub fn generate_route_rn(generation_strategy: RouteGeneratorStrategy, route_details: &WxRouteDetails, grid: &[PixelColor]) -> PixRoute {
+    match generation_strategy.provider() {
+        Some(provider) => generate_route_rn_with(provider, route_details, grid),
+        None => PixRoute::default(),
     }
 }
 
+/// Generate the route on the weather grid with the given provider, possibly a third-party one
+pub fn generate_route_rn_with(provider: &dyn RouteGenerator, route_details: &WxRouteDetails, grid: &[PixelColor]) -> PixRoute {
+    let route = provider.generate_route_real_num(route_details, grid);
+    let route = enforce_energy_limit_rn(route_details, route);
+    enforce_validation_rn(grid, route_details, route)
+}
+
 pub fn generate_route(graph: &Graph, generation_strategy: RouteGeneratorStrategy, route_details: &RouteDetails) -> Route {
-    match generation_strategy {
-        RouteGeneratorStrategy::Greedy => providers::greedy::RouteGeneratorGreedy::generate_route(graph, generation_strategy, route_details, GenerationMode::Graph),
-        RouteGeneratorStrategy::ACO => providers::aco::RouteGeneratorACO::generate_route(graph, generation_strategy, route_details, GenerationMode::Graph),
-        RouteGeneratorStrategy::PSO => providers::pso::RouteGeneratorPSO::generate_route(graph, generation_strategy, route_details, GenerationMode::Graph),
-        RouteGeneratorStrategy::Empty => Route(vec![]),
+    match generation_strategy.provider() {
+        Some(provider) => generate_route_with(graph, provider, route_details),
+        None => Route::default(),
     }
 }
+
+/// Generate the route with the given provider, possibly a third-party one, leg by leg when the request has waypoints
+pub fn generate_route_with(graph: &Graph, provider: &dyn RouteGenerator, route_details: &RouteDetails) -> Route {
+    let generate_leg = |leg_details: &RouteDetails| provider.generate_route(graph, leg_details);
+    let route = if route_details.waypoints.is_empty() {
+        generate_leg(route_details)
+    } else {
+        generate_route_through_waypoints(route_details, &generate_leg)
+    };
+    let route = enforce_energy_limit(graph, route_details, route);
+    enforce_validation(graph, route_details, route)
+}
+
+/// Generate the route leg by leg through the waypoints of the request, with the given generator of a single leg
+pub fn generate_route_through_waypoints(route_details: &RouteDetails, generate_leg: &dyn Fn(&RouteDetails) -> Route) -> Route {
+    tour::generate_through_waypoints(route_details, generate_leg)
+}
+
+/// Find the cheapest order of visiting the delivery points and generate the route through them.
+/// Waypoints of the request are ignored, delivery points take their place
+pub fn generate_delivery_tour(graph: &Graph, generation_strategy: RouteGeneratorStrategy, route_details: &RouteDetails, deliveries: &[NodeId]) -> Route {
+    let generate = |ordered_details: &RouteDetails| generate_route(graph, generation_strategy, ordered_details);
+    tour::generate_delivery_tour(graph, route_details, deliveries, &generate)
+}
+
+/// Split delivery points between at most `drones_count` drones, every drone flies from the starting to the ending node
+pub fn generate_delivery_routes(graph: &Graph, generation_strategy: RouteGeneratorStrategy, route_details: &RouteDetails, deliveries: &[NodeId], drones_count: usize) -> Vec<Route> {
+    let generate = |part_details: &RouteDetails| generate_route(graph, generation_strategy, part_details);
+    tour::generate_delivery_routes(graph, route_details, deliveries, drones_count, &generate)
+}
+
+/// Up to `count` diverse routes in the order of increasing cost, each one annotated with its cost breakdown.
+/// Alternatives sharing more than MAX_ROUTE_SIMILARITY of their length with a cheaper one are skipped, waypoints are ignored
+pub fn generate_alternatives(graph: &Graph, route_details: &RouteDetails, count: usize) -> Vec<Route> {
+    generate_alternatives_with_similarity(graph, route_details, count, MAX_ROUTE_SIMILARITY)
+}
+
+pub fn generate_alternatives_with_similarity(graph: &Graph, route_details: &RouteDetails, count: usize, max_similarity: f32) -> Vec<Route> {
+    //Alternatives do not go through the waypoints, so they are not expected by the validation either
+    let alternative_details = RouteDetails { waypoints: vec![], ..route_details.clone() };
+    alternatives::generate_alternatives(graph, &alternative_details, count, max_similarity).into_iter()
+        .map(|route| enforce_energy_limit(graph, &alternative_details, route))
+        .map(|route| enforce_validation(graph, &alternative_details, route))
+        .filter(|route| !route.edges.is_empty())
+        .collect()
+}
+
+/// Route that the drone of the request cannot fly on a single charge is replaced with an empty one.
+/// Only the seed and the telemetry of the generation are kept, the energy report is given as the rejection
+pub fn enforce_energy_limit(graph: &Graph, route_details: &RouteDetails, route: Route) -> Route {
+    match route_details.drone.map(|drone| drone.evaluate_route(graph, &route)) {
+        Some(report) if !report.is_feasible() => {
+            warn!("Route rejected, it needs {} Wh while only {} Wh can be used", report.required, report.usable);
+            Route::rejected(route, RouteRejection::Energy(report))
+        }
+        _ => route,
+    }
+}
+
+pub fn enforce_energy_limit_rn(route_details: &WxRouteDetails, route: PixRoute) -> PixRoute {
+    match route_details.drone.map(|drone| drone.evaluate_pix_route(&route)) {
+        Some(report) if !report.is_feasible() => {
+            warn!("Route rejected, it needs {} Wh while only {} Wh can be used", report.required, report.usable);
+            PixRoute::rejected(route, PixRouteRejection::Energy(report))
+        }
+        _ => route,
+    }
+}
+
+/// Route with any validation issue is replaced with an empty one, when the request asks for validation.
+/// Only the seed and the telemetry of the generation are kept, the issues are given as the rejection
+pub fn enforce_validation(graph: &Graph, route_details: &RouteDetails, route: Route) -> Route {
+    //Route rejected for its energy is empty on purpose, validating it again would hide the reason
+    if !route_details.validate || route.rejection.is_some() {
+        return route;
+    }
+    match validation::validate_route(graph, route_details, &route) {
+        issues if !issues.is_empty() => {
+            warn!("Route rejected, validation issues: {:?}", issues);
+            Route::rejected(route, RouteRejection::Validation(issues))
+        }
+        _ => route,
+    }
+}
+
+pub fn enforce_validation_rn(grid: &[PixelColor], route_details: &WxRouteDetails, route: PixRoute) -> PixRoute {
+    if !route_details.validate || route.rejection.is_some() {
+        return route;
+    }
+    match validation::validate_pix_route(grid, route_details, &route) {
+        issues if !issues.is_empty() => {
+            warn!("Route rejected, validation issues: {:?}", issues);
+            PixRoute::rejected(route, PixRouteRejection::Validation(issues))
+        }
+        _ => route,
+    }
+}
+
+/// Generate routes with every set of weights from the pareto sweep and return the ones that are not dominated by any other
+pub fn generate_pareto_front(graph: &Graph, generation_strategy: RouteGeneratorStrategy, route_details: &RouteDetails) -> Vec<Route> {
+    let started = Instant::now();
+    let routes = CostWeights::pareto_sweep().into_iter()
+        .map(|cost_weights| {
+            let weighted_details = RouteDetails {
+                budget: route_details.budget.remaining_since(started),
+                ..route_details.clone()
+            }.with_cost_weights(cost_weights);
+            generate_route(graph, generation_strategy, &weighted_details)
+        })
+        .collect();
+    pareto_front(routes)
+}
@@ -0,0 +1,182 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use osmpbfreader::NodeId;
+use rand::Rng;
+
+use mgr_map_extractor::graph::{Edge, Graph, Node};
+
+use crate::Route;
+use crate::cost::CostEvaluator;
+use crate::telemetry::Telemetry;
+
+/// Best path found by the metaheuristic with the telemetry when it was asked for
+pub(crate) struct PathSearchResult<P> {
+    pub(crate) path: Option<Vec<P>>,
+    pub(crate) telemetry: Option<Telemetry>,
+    //Budget ran out before all iterations were run
+    pub(crate) partial: bool,
+}
+
+/// Road graph or weather grid lattice seen as positions connected with their neighbours, for the providers working on whole paths
+pub(crate) trait PathSpace {
+    type Position: Copy + Eq + Hash;
+
+    fn neighbours(&self, position: Self::Position) -> Vec<Self::Position>;
+
+    fn distance(&self, from: Self::Position, to: Self::Position) -> f32;
+}
+
+impl PathSpace for Graph {
+    type Position = NodeId;
+
+    fn neighbours(&self, node_id: NodeId) -> Vec<NodeId> {
+        self.edge_connections().get(&node_id).cloned().unwrap_or_default()
+    }
+
+    fn distance(&self, from: NodeId, to: NodeId) -> f32 {
+        node_distance(self, from, to)
+    }
+}
+
+pub(crate) fn node_distance(graph: &Graph, from: NodeId, to: NodeId) -> f32 {
+    Edge::length(
+        graph.nodes().get(&Node::id(from)).unwrap(),
+        graph.nodes().get(&Node::id(to)).unwrap(),
+    ) as f32
+}
+
+/// Edge known to the graph, keeping its highway type, or a new one created from node positions
+pub(crate) fn graph_edge(graph: &Graph, from: NodeId, to: NodeId) -> Edge {
+    graph.edge_by_node_id().get(&(from, to))
+        .copied()
+        .unwrap_or_else(|| Edge::create(graph, from, to))
+}
+
+/// Convert sequence of nodes into a route, evaluating its cost and the time of arrival at every node
+pub(crate) fn path_to_route(graph: &Graph, path: &[NodeId], cost_evaluator: &CostEvaluator) -> Route {
+    let route = Route::new(path.windows(2).map(|pair| graph_edge(graph, pair[0], pair[1])).collect());
+    cost_evaluator.timed_route(graph, route)
+}
+
+/// Cut out every cycle from the path, so each node is visited at most once
+pub(crate) fn remove_loops<T: Copy + Eq + Hash>(path: &[T]) -> Vec<T> {
+    let mut result: Vec<T> = Vec::with_capacity(path.len());
+    let mut positions: HashMap<T, usize> = HashMap::new();
+
+    for &node_id in path {
+        if let Some(&position) = positions.get(&node_id) {
+            for removed in result.drain(position + 1..) {
+                positions.remove(&removed);
+            }
+        } else {
+            positions.insert(node_id, result.len());
+            result.push(node_id);
+        }
+    }
+    result
+}
+
+/// Randomised depth first search biased towards the target, `noise` is the share of randomness added to the distance to the target.
+/// Positions from `banned` are never entered. Returns None if target was not reached within `expansion_limit` moves
+pub(crate) fn random_walk<S: PathSpace, R: Rng>(space: &S, from: S::Position, to: S::Position, banned: &HashSet<S::Position>,
+                                               expansion_limit: usize, noise: f32, rng: &mut R) -> Option<Vec<S::Position>> {
+    let ordered_neighbours = |position: S::Position, rng: &mut R| {
+        let mut neighbours: Vec<(S::Position, f32)> = space.neighbours(position).into_iter()
+            .map(|next| (next, space.distance(next, to) * rng.gen_range(1.0..=1.0 + noise)))
+            .collect();
+        //Best candidate is popped first, so it has to be at the end
+        neighbours.sort_by(|a, b| b.1.total_cmp(&a.1));
+        neighbours.into_iter().map(|(next, _)| next).collect::<Vec<S::Position>>()
+    };
+
+    if from == to {
+        return Some(vec![from]);
+    }
+
+    let mut visited: HashSet<S::Position> = HashSet::from([from]);
+    let mut path = vec![from];
+    let mut frontier = vec![ordered_neighbours(from, rng)];
+    let mut expansions: usize = 0;
+
+    while let Some(candidates) = frontier.last_mut() {
+        match candidates.pop() {
+            Some(next) => {
+                if visited.contains(&next) || banned.contains(&next) {
+                    continue;
+                }
+                visited.insert(next);
+                path.push(next);
+                if next == to {
+                    return Some(path);
+                }
+
+                expansions += 1;
+                if expansions >= expansion_limit {
+                    return None;
+                }
+                frontier.push(ordered_neighbours(next, rng));
+            }
+            None => {
+                frontier.pop();
+                path.pop();
+            }
+        }
+    }
+    None
+}
+
+/// Move current path towards the guide path.
+/// Both paths share the same ending position, so current path is cut at a randomly chosen common position
+/// and the rest of it is replaced by the corresponding part of the guide
+pub(crate) fn relink<T: Copy + Eq + Hash, R: Rng>(current: &[T], guide: &[T], rng: &mut R) -> Vec<T> {
+    let guide_positions: HashMap<T, usize> = guide.iter().enumerate().map(|(idx, &position)| (position, idx)).collect();
+
+    let common_positions: Vec<(usize, usize)> = current.iter().enumerate()
+        .filter_map(|(idx, position)| guide_positions.get(position).map(|&guide_idx| (idx, guide_idx)))
+        .filter(|&(idx, guide_idx)| idx + 1 < current.len() && guide_idx + 1 < guide.len())
+        .collect();
+
+    if common_positions.is_empty() {
+        return current.to_vec();
+    }
+
+    let (cut_idx, guide_idx) = common_positions[rng.gen_range(0..common_positions.len())];
+    let mut new_path = current[..=cut_idx].to_vec();
+    new_path.extend_from_slice(&guide[guide_idx + 1..]);
+    remove_loops(&new_path)
+}
+
+/// Replace random part of the path with a different, randomly found one
+pub(crate) fn detour<S: PathSpace, R: Rng>(space: &S, path: &[S::Position], search_limit: usize, noise: f32, rng: &mut R) -> Vec<S::Position> {
+    if path.len() < 3 {
+        return path.to_vec();
+    }
+
+    let from_idx = rng.gen_range(0..path.len() - 2);
+    let to_idx = rng.gen_range(from_idx + 2..path.len());
+
+    let banned: HashSet<S::Position> = path[..from_idx].iter().chain(path[to_idx + 1..].iter()).copied().collect();
+    match random_walk(space, path[from_idx], path[to_idx], &banned, search_limit, noise, rng) {
+        Some(sub_path) => {
+            let mut new_path = path[..from_idx].to_vec();
+            new_path.extend(sub_path);
+            new_path.extend_from_slice(&path[to_idx + 1..]);
+            remove_loops(&new_path)
+        }
+        None => path.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_loops() {
+        let path = [1, 2, 3, 4, 2, 5, 6, 5, 7].map(NodeId);
+
+        let result = remove_loops(&path);
+        assert_eq!(result, [1, 2, 5, 7].map(NodeId).to_vec());
+    }
+}
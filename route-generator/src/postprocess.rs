@@ -0,0 +1,211 @@
+use log::info;
+use osmpbfreader::NodeId;
+
+use mgr_map_extractor::graph::Graph;
+
+use crate::{Route, RouteDetails, RouteLeg};
+use crate::cost::CostEvaluator;
+use crate::path::{path_to_route, remove_loops};
+use crate::providers::astar::shortest_path;
+
+//Maximum number of nodes between the two ends of the segment replaced with a shortcut
+pub const SHORTCUT_WINDOW: usize = 8;
+
+//Passes of shortcuts and 2-opt are repeated until nothing improves or this many of them were done
+pub const POST_PROCESS_MAX_PASSES: usize = 10;
+
+//Improvements smaller than this are ignored, so rounding errors cannot make the passes go on forever
+const MIN_IMPROVEMENT: f32 = 0.001;
+
+/// Length of the route before and after the post-processing, m
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PostProcessReport {
+    pub length_before: f32,
+    pub length_after: f32,
+}
+
+impl PostProcessReport {
+    pub fn length_saved(&self) -> f32 {
+        self.length_before - self.length_after
+    }
+}
+
+/// Remove cycles from the route, replace its detours with shortcuts through existing edges and apply 2-opt moves.
+/// Every leg of the route is optimised separately, so the waypoints are still visited in the same order
+pub fn optimise_route(graph: &Graph, route_details: &RouteDetails, route: &Route) -> (Route, PostProcessReport) {
+    let length_before = route_length(route);
+    if route.edges.is_empty() {
+        return (route.clone(), PostProcessReport { length_before, length_after: length_before });
+    }
+
+    let leg_edge_counts: Vec<usize> = match route.legs.is_empty() {
+        true => vec![route.edges.len()],
+        false => route.legs.iter().map(|leg| leg.edge_count).collect(),
+    };
+
+    let mut optimised = Route {
+        seed: route.seed,
+        departure_time: route.departure_time,
+        telemetry: route.telemetry.clone(),
+        partial: route.partial,
+        ..Default::default()
+    };
+    let mut first_edge = 0;
+    for edge_count in leg_edge_counts {
+        let leg_edges = &route.edges[first_edge..first_edge + edge_count];
+        first_edge += edge_count;
+        let Some(first) = leg_edges.first() else {
+            continue;
+        };
+
+        //Leg starts when the previous one has finished
+        let leg_details = RouteDetails {
+            departure_time: optimised.arrival_time().unwrap_or(route.departure_time),
+            ..route_details.clone()
+        };
+        let cost_evaluator = CostEvaluator::new(&leg_details);
+        let mut path = vec![first.from];
+        path.extend(leg_edges.iter().map(|edge| edge.to));
+        let leg_route = path_to_route(graph, &optimise_path(graph, &path, &cost_evaluator), &cost_evaluator);
+
+        if !route.legs.is_empty() {
+            optimised.legs.push(RouteLeg {
+                from: first.from,
+                to: leg_edges.last().unwrap().to,
+                edge_count: leg_route.edges.len(),
+                cost: leg_route.cost,
+            });
+        }
+        optimised.cost += leg_route.cost;
+        optimised.edges.extend(leg_route.edges);
+        let skipped_times = if optimised.node_times.is_empty() { 0 } else { 1 };
+        optimised.node_times.extend(leg_route.node_times.into_iter().skip(skipped_times));
+    }
+
+    let report = PostProcessReport { length_before, length_after: route_length(&optimised) };
+    info!("Post-processing saved {} m of the route", report.length_saved());
+    (optimised, report)
+}
+
+fn route_length(route: &Route) -> f32 {
+    route.edges.iter().map(|edge| edge.length as f32).sum()
+}
+
+fn optimise_path(graph: &Graph, path: &[NodeId], cost_evaluator: &CostEvaluator) -> Vec<NodeId> {
+    let mut path = remove_loops(path);
+    for _ in 0..POST_PROCESS_MAX_PASSES {
+        let shortened = apply_shortcut(graph, &mut path, cost_evaluator);
+        let swapped = apply_two_opt(graph, &mut path, cost_evaluator);
+        if !shortened && !swapped {
+            break;
+        }
+        path = remove_loops(&path);
+    }
+    path
+}
+
+fn segment_cost(graph: &Graph, segment: &[NodeId], cost_evaluator: &CostEvaluator) -> f32 {
+    cost_evaluator.weighted(&cost_evaluator.path_cost(graph, segment))
+}
+
+fn has_edge(graph: &Graph, from: NodeId, to: NodeId) -> bool {
+    graph.edge_connections().get(&from).is_some_and(|connections| connections.contains(&to))
+}
+
+/// Replace the first segment, of at most SHORTCUT_WINDOW nodes, that has a cheaper path between its ends
+fn apply_shortcut(graph: &Graph, path: &mut Vec<NodeId>, cost_evaluator: &CostEvaluator) -> bool {
+    for start in 0..path.len() {
+        for end in (start + 2..path.len().min(start + SHORTCUT_WINDOW + 1)).rev() {
+            let Some(shortcut) = shortest_path(graph, path[start], path[end], cost_evaluator) else {
+                continue;
+            };
+            if segment_cost(graph, &shortcut, cost_evaluator) + MIN_IMPROVEMENT < segment_cost(graph, &path[start..=end], cost_evaluator) {
+                path.splice(start..=end, shortcut);
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Apply the first 2-opt move that makes the path cheaper - segment of the path is flown in the opposite direction
+fn apply_two_opt(graph: &Graph, path: &mut [NodeId], cost_evaluator: &CostEvaluator) -> bool {
+    for first in 0..path.len().saturating_sub(3) {
+        for last in first + 2..path.len() - 1 {
+            let mut swapped = path[first..=last + 1].to_vec();
+            swapped[1..=last - first].reverse();
+            if !swapped.windows(2).all(|pair| has_edge(graph, pair[0], pair[1])) {
+                continue;
+            }
+            if segment_cost(graph, &swapped, cost_evaluator) + MIN_IMPROVEMENT < segment_cost(graph, &path[first..=last + 1], cost_evaluator) {
+                path[first..=last + 1].copy_from_slice(&swapped);
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{generate_route, RouteGeneratorStrategy};
+    use crate::lattice::{lattice_graph, lattice_node};
+    use crate::telemetry::Telemetry;
+
+    use super::*;
+
+    fn lattice_route(graph: &Graph, route_details: &RouteDetails, cells: &[(usize, usize)]) -> Route {
+        let path: Vec<NodeId> = cells.iter().map(|&(x, y)| lattice_node(4, x, y)).collect();
+        path_to_route(graph, &path, &CostEvaluator::new(route_details))
+    }
+
+    #[test]
+    fn test_loops_and_backtracking_are_removed() {
+        let graph = lattice_graph(4, 3);
+        let route_details = RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 0));
+        let route = lattice_route(&graph, &route_details, &[(0, 0), (1, 0), (1, 1), (0, 1), (0, 0), (1, 0), (2, 0), (2, 1), (2, 0), (3, 0)]);
+
+        let (optimised, report) = optimise_route(&graph, &route_details, &route);
+
+        assert_eq!(optimised.edges.len(), 3);
+        assert_eq!(optimised.edges.last().map(|edge| edge.to), Some(lattice_node(4, 3, 0)));
+        assert!((report.length_saved() - 6.0 * graph_step(&graph)).abs() < 0.1);
+        assert!(optimised.cost().distance < route.cost().distance);
+    }
+
+    #[test]
+    fn test_detour_is_shortened() {
+        let graph = lattice_graph(4, 3);
+        let route_details = RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 2, 0));
+        let mut route = lattice_route(&graph, &route_details, &[(0, 0), (0, 1), (0, 2), (1, 2), (2, 2), (2, 1), (2, 0)]);
+        route.partial = true;
+        route.telemetry = Some(Telemetry::default());
+
+        let (optimised, report) = optimise_route(&graph, &route_details, &route);
+
+        assert_eq!(optimised.edges.len(), 2);
+        assert!(report.length_saved() > 0.0);
+        assert_eq!(optimised.node_times.len(), 3);
+        assert!(optimised.partial);
+        assert_eq!(optimised.telemetry, Some(Telemetry::default()));
+    }
+
+    #[test]
+    fn test_waypoints_are_kept() {
+        let graph = lattice_graph(4, 3);
+        let waypoint = lattice_node(4, 0, 2);
+        let route_details = RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 0))
+            .with_waypoints(vec![waypoint]);
+        let route = generate_route(&graph, RouteGeneratorStrategy::AStar, &route_details);
+
+        let (optimised, report) = optimise_route(&graph, &route_details, &route);
+
+        assert_eq!(report.length_saved(), 0.0);
+        assert_eq!(optimised.legs.len(), 2);
+        assert!(optimised.edges.iter().any(|edge| edge.to == waypoint));
+    }
+
+    fn graph_step(graph: &Graph) -> f32 {
+        graph.edge_by_node_id()[&(lattice_node(4, 0, 0), lattice_node(4, 1, 0))].length as f32
+    }
+}
@@ -1,14 +1,26 @@
 use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::hash::Hash;
 
-use log::{debug, info};
+use log::{debug, info, warn};
 use osmpbfreader::NodeId;
 use rand::distributions::{Distribution, WeightedIndex};
-use rand::prelude::SliceRandom;
-use rand::thread_rng;
+use rand::Rng;
+use rand::rngs::StdRng;
+use rayon::prelude::*;
 
 use mgr_map_extractor::graph::{Edge, Graph, Node};
-
-use crate::{GenerationMode, Route, RouteDetails, RouteGenerator, RouteGeneratorStrategy};
+use mgr_weather::image_wrapper::{GRID_SIZE, PixelColor};
+
+use crate::{ObstacleHandling, PixRoute, Route, RouteDetails, RouteGenerator, WxRouteDetails};
+use crate::budget::{Budget, BudgetTracker};
+use crate::cost::CostEvaluator;
+use crate::path::{path_to_route, PathSearchResult, remove_loops};
+use crate::providers::astar::{graph_path, grid_path, SearchOutcome};
+use crate::providers::greedy;
+use crate::rng::{derived_rng, resolve_seed};
+use crate::telemetry::{mean, normalised_entropy, TelemetryRecorder};
+use crate::wx_grid::{cell_distance, cell_to_position, GRID_CELL_LENGTH, GridCell, neighbours, position_to_cell};
 
 //We don't want to use f64 types because they are not supported on the target platform anyway (rPI Zero W)
 
@@ -34,59 +46,348 @@ pub const BETA_COEFF: f32 = 1.37;
 /// Initial level of the pheromone. Must be more than zero otherwise ants will never choose given edge
 pub const PH_INITIAL_LEVEL: f32 = 100.0;
 
+//Pheromone level along the seeded route as a multiple of the initial level
+pub const PH_SEED_MULTIPLIER: f32 = 3.0;
+
+//Bounds of the pheromone levels in the Max-Min Ant System, trails start at the upper one
+pub const MMAS_MAX_LEVEL: f32 = PH_INITIAL_LEVEL;
+
+pub const MMAS_MIN_LEVEL: f32 = PH_INITIAL_LEVEL * 0.01;
+
+//How many times stronger the best route deposit is in the elitist Ant System
+pub const ELITIST_WEIGHT: f32 = 5.0;
+
+//Number of ranks in the rank-based Ant System - best ants of the iteration and the best route so far deposit pheromone
+pub const RANK_COUNT: usize = 6;
+
+//Probability that the ACS ant takes the best move instead of sampling one
+pub const ACS_EXPLOITATION_PROBABILITY: f32 = 0.9;
+
+//How much of the pheromone the ACS ant takes away from the passed edge, pulling it back towards the initial level
+pub const ACS_LOCAL_EVAPORATION_RATE: f32 = 0.1;
 
-type EdgeId = (NodeId, NodeId);//FromId and ToId
+//Edge costs below it are raised to it when the pheromone is deposited, zero cost edges would get infinite deposits
+pub const MIN_DEPOSIT_EDGE_COST: f32 = 0.001;
+
+
+type EdgeId<P> = (P, P);//FromId and ToId
+
+
+/// How the pheromone trails are initialised before the first simulation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PheromoneSeeding {
+    //Same initial level on every edge
+    Uniform,
+    //Extra pheromone along the route walked by the greedy provider
+    Greedy,
+    //Extra pheromone along the cheapest route found by A*
+    AStar,
+}
+
+/// Rule updating the pheromone levels after every simulation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcoVariant {
+    //Every ant deposits pheromone and the best route gets a bonus
+    AntSystem,
+    //Ant System with the bonus of the best route ELITIST_WEIGHT times stronger
+    Elitist,
+    //Only the best ants of the iteration deposit pheromone, weighted by their rank, together with the best route so far
+    RankBased,
+    //Ant Colony System - ants mostly take the best move and weaken the edges they pass, only the best route deposits pheromone
+    ColonySystem,
+    //Only the best route deposits pheromone and levels are kept between MMAS_MIN_LEVEL and MMAS_MAX_LEVEL
+    MaxMin,
+}
+
+/// Settings of the ACO provider, chosen per request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AcoOptions {
+    pub seeding: PheromoneSeeding,
+    pub variant: AcoVariant,
+}
+
+impl AcoOptions {
+    pub const fn new() -> Self {
+        AcoOptions {
+            seeding: PheromoneSeeding::Uniform,
+            variant: AcoVariant::AntSystem,
+        }
+    }
+
+    pub fn with_seeding(mut self, seeding: PheromoneSeeding) -> Self {
+        self.seeding = seeding;
+        self
+    }
+
+    pub fn with_variant(mut self, variant: AcoVariant) -> Self {
+        self.variant = variant;
+        self
+    }
+}
+
+impl Default for AcoOptions {
+    fn default() -> Self {
+        AcoOptions::new()
+    }
+}
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug)]
 enum OptimizerMode {
     Target,
     Distance,
 }
 
-struct ACOState<'a> {
-    ph_levels: HashMap<EdgeId, f32>,
+/// Space that ants are moving in - road graph or 8-connected weather grid lattice.
+/// Ants of one simulation are moving in parallel, so the space is shared between threads
+trait AntSpace: Sync {
+    type Position: Copy + Eq + Hash + Debug + Send + Sync;
+
+    fn starting_position(&self) -> Self::Position;
+
+    fn ending_position(&self) -> Self::Position;
+
+    /// Positions reachable with a single move, together with the length of that move
+    fn moves(&self, position: Self::Position) -> &[(Self::Position, f32)];
+
+    fn distance_to_target(&self, position: Self::Position) -> f32;
+}
+
+/// Road graph with moves and distances to the target calculated once, instead of on every ant step
+struct GraphSpace<'a> {
+    route_details: &'a RouteDetails,
+    moves: HashMap<NodeId, Vec<(NodeId, f32)>>,
+    target_distances: HashMap<NodeId, f32>,
+}
+
+impl<'a> GraphSpace<'a> {
+    fn new(graph: &Graph, route_details: &'a RouteDetails, cost_evaluator: &CostEvaluator) -> Self {
+        //Ants measure their routes with the weighted cost instead of the plain length.
+        //Costs are evaluated once at the departure time, not at the time the ant would reach the edge
+        let moves = graph.edge_connections().iter()
+            .map(|(&node_id, connections)| {
+                (node_id, connections.iter().map(|&next| (next, cost_evaluator.edge_cost(graph, node_id, next))).collect())
+            })
+            .collect();
+        let ending_node = graph.nodes().get(&Node::id(route_details.ending_node)).unwrap();
+        let target_distances = graph.nodes().iter()
+            .map(|node| (node.get_id(), Edge::length(node, ending_node) as f32))
+            .collect();
+
+        Self {
+            route_details,
+            moves,
+            target_distances,
+        }
+    }
+}
+
+impl AntSpace for GraphSpace<'_> {
+    type Position = NodeId;
+
+    fn starting_position(&self) -> NodeId {
+        self.route_details.starting_node
+    }
+
+    fn ending_position(&self) -> NodeId {
+        self.route_details.ending_node
+    }
+
+    fn moves(&self, position: NodeId) -> &[(NodeId, f32)] {
+        self.moves.get(&position).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    fn distance_to_target(&self, position: NodeId) -> f32 {
+        self.target_distances[&position]
+    }
+}
+
+/// Weather grid lattice with moves of every cell calculated once
+struct GridSpace {
+    starting_cell: GridCell,
+    ending_cell: GridCell,
+    moves: Vec<Vec<(GridCell, f32)>>,
+}
+
+impl GridSpace {
+    fn new(grid: &[PixelColor], obstacles: ObstacleHandling, starting_cell: GridCell, ending_cell: GridCell) -> Self {
+        let moves = (0..GRID_SIZE * GRID_SIZE)
+            .map(|idx| {
+                neighbours(grid, obstacles, (idx % GRID_SIZE, idx / GRID_SIZE)).into_iter()
+                    .map(|(next, cost)| (next, cost * GRID_CELL_LENGTH))
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            starting_cell,
+            ending_cell,
+            moves,
+        }
+    }
+}
+
+impl AntSpace for GridSpace {
+    type Position = GridCell;
+
+    fn starting_position(&self) -> GridCell {
+        self.starting_cell
+    }
+
+    fn ending_position(&self) -> GridCell {
+        self.ending_cell
+    }
+
+    fn moves(&self, position: GridCell) -> &[(GridCell, f32)] {
+        &self.moves[position.1 * GRID_SIZE + position.0]
+    }
+
+    fn distance_to_target(&self, position: GridCell) -> f32 {
+        cell_distance(position, self.ending_cell) * GRID_CELL_LENGTH
+    }
+}
+
+/// Route walked by a single ant - every step is an edge with its length
+#[derive(Clone)]
+struct AntRoute<P> {
+    steps: Vec<(EdgeId<P>, f32)>,
+    length: f32,
+}
+
+impl<P: Copy> AntRoute<P> {
+    fn last_position(&self, starting_position: P) -> P {
+        self.steps.last().map(|((_, to), _)| *to).unwrap_or(starting_position)
+    }
+
+    fn positions(&self, starting_position: P) -> Vec<P> {
+        let mut positions = vec![starting_position];
+        positions.extend(self.steps.iter().map(|((_, to), _)| *to));
+        positions
+    }
+}
+
+struct ACOState<'a, S: AntSpace> {
+    ph_levels: HashMap<EdgeId<S::Position>, f32>,
     // pheromone levels that ants had left in previous runs
-    best_route: Option<Route>,
+    default_level: f32,
+    // level of the edges that no ant has passed yet
+    acs_initial_level_set: bool,
+    // whether the ACS levels are already scaled to the initial level derived from the first best route
+    options: AcoOptions,
+    best_route: Option<AntRoute<S::Position>>,
     best_route_length: f32,
     best_distance_to_target: f32,
     // best route found so far
-    graph: &'a Graph,
-    route_details: &'a RouteDetails,
+    space: &'a S,
     iteration_number: i32,
     optimizer_mode: OptimizerMode,
 }
 
-impl<'a> ACOState<'a> {
-    fn new(graph: &'a Graph, route_details: &'a RouteDetails) -> Self {
+impl<'a, S: AntSpace> ACOState<'a, S> {
+    fn new(space: &'a S, options: AcoOptions) -> Self {
         Self {
             ph_levels: HashMap::new(),
+            default_level: PH_INITIAL_LEVEL,
+            acs_initial_level_set: false,
+            options,
             best_route: None,
             best_route_length: f32::MAX,
             best_distance_to_target: f32::MAX,
-            graph,
-            route_details,
+            space,
             iteration_number: 0,
             optimizer_mode: OptimizerMode::Target,
         }
     }
 
-    fn get_best_route(&self) -> &Option<Route> {
+    fn get_best_route(&self) -> &Option<AntRoute<S::Position>> {
         &self.best_route
     }
 
     fn increase_iteration_number(&mut self) {
         self.iteration_number += 1;
     }
+
+    fn ph_level(&self, edge_id: &EdgeId<S::Position>) -> f32 {
+        self.ph_levels.get(edge_id).copied().unwrap_or(self.default_level)
+    }
+
+    /// ACS local update of the edges that the ant has just passed, so the following ants are more likely to explore other ones
+    fn local_update(&mut self, ant_route: &AntRoute<S::Position>) {
+        for (edge_id, _) in ant_route.steps.iter() {
+            let ph_level = self.ph_levels.entry(*edge_id).or_insert(self.default_level);
+            *ph_level = (1.0 - ACS_LOCAL_EVAPORATION_RATE) * *ph_level + ACS_LOCAL_EVAPORATION_RATE * self.default_level;
+        }
+    }
+
+    /// Routes reaching the target come first ordered by length, then the others ordered by the distance of their end to the target
+    fn compare_routes(&self, first: &AntRoute<S::Position>, second: &AntRoute<S::Position>) -> std::cmp::Ordering {
+        let key = |route: &AntRoute<S::Position>| {
+            let route_end = route.last_position(self.space.starting_position());
+            match route_end == self.space.ending_position() {
+                true => (0, route.length),
+                false => (1, self.space.distance_to_target(route_end)),
+            }
+        };
+        let (first_key, second_key) = (key(first), key(second));
+        first_key.0.cmp(&second_key.0).then(first_key.1.total_cmp(&second_key.1))
+    }
+
+    /// Lay extra pheromone along the path found by another provider and take it as the best route so far
+    fn seed_trail(&mut self, path: &[S::Position]) {
+        let mut route = AntRoute { steps: vec![], length: 0.0 };
+        for pair in path.windows(2) {
+            let Some(&(_, edge_length)) = self.space.moves(pair[0]).iter().find(|(position, _)| *position == pair[1]) else {
+                warn!("Seeded path moves from {:?} to {:?} which ants cannot do, pheromones stay uniform", pair[0], pair[1]);
+                return;
+            };
+            route.steps.push(((pair[0], pair[1]), edge_length));
+            route.length += edge_length;
+        }
+
+        for (edge_id, _) in &route.steps {
+            self.ph_levels.insert(*edge_id, self.default_level * PH_SEED_MULTIPLIER);
+        }
+        self.consider_route(&route);
+    }
+
+    /// Remember the route if it is better than the best one so far.
+    /// Until the target is reached, routes ending closer to it are better. Afterwards only shorter routes reaching the target are accepted
+    fn consider_route(&mut self, ant_route: &AntRoute<S::Position>) {
+        let route_end = ant_route.last_position(self.space.starting_position());
+        let target_reached = route_end == self.space.ending_position();
+
+        //If we reach end of the route, change optimizer mode
+        if target_reached && self.optimizer_mode == OptimizerMode::Target {
+            debug!("Switching to distance optimizer mode");
+            self.optimizer_mode = OptimizerMode::Distance;
+        }
+
+        if OptimizerMode::Distance == self.optimizer_mode {
+            if target_reached && ant_route.length < self.best_route_length {
+                self.best_route_length = ant_route.length;
+                self.best_route = Some(ant_route.clone());
+            }
+        } else {
+            //Calculate distance to the destination
+            let distance_to_destination = self.space.distance_to_target(route_end);
+            if distance_to_destination < self.best_distance_to_target {
+                self.best_distance_to_target = distance_to_destination;
+                self.best_route = Some(ant_route.clone());
+            }
+        }
+    }
 }
 
-struct Ant {
-    nodes_visited: HashSet<NodeId>,
+struct Ant<P> {
+    nodes_visited: HashSet<P>,
+    //Every ant has its own generator, so routes do not depend on the order in which ants are run
+    rng: StdRng,
 }
 
-impl Ant {
-    fn new() -> Self {
+impl<P: Copy + Eq + Hash + Debug> Ant<P> {
+    fn new(rng: StdRng) -> Self {
         Self {
-            nodes_visited: HashSet::new()
+            nodes_visited: HashSet::new(),
+            rng,
         }
     }
 
@@ -97,26 +398,23 @@ impl Ant {
 
     //Select next node to visit based on the pheromone level
     //Ant cannot select given edge twice
-    fn select_next_node(&self, graph: &Graph, current_node: &Node, end_node: &Node, ph_levels: &HashMap<EdgeId, f32>) -> Option<NodeId> {
-        let mut rng = thread_rng();
+    fn select_next_node<S: AntSpace<Position=P>>(&mut self, state: &ACOState<S>, current_node: P) -> Option<(P, f32)> {
+        let space = state.space;
         //Read all possible edges from the current node
-        let mut edge_candidates: Vec<Edge> = vec![];
-        graph.edge_connections().get(&current_node.get_id()).unwrap().iter().for_each(|&node_id| {
-            let new_edge = Edge::create(graph, current_node.get_id(), node_id);
-            if !self.nodes_visited.contains(&node_id) {
-                edge_candidates.push(new_edge);
-            }
-        });
+        let edge_candidates: Vec<(P, f32)> = space.moves(current_node).iter()
+            .filter(|(node_id, _)| !self.nodes_visited.contains(node_id))
+            .copied()
+            .collect();
 
         if edge_candidates.is_empty() {
             return None;
         }
 
         //Calculate weights for each edge
-        let edge_candidates_chances = edge_candidates.iter().map(|edge| {
-            let nom_1 = ph_levels.get(&(edge.from, edge.to)).unwrap_or(&PH_INITIAL_LEVEL);
-            let distance_to_target_before = Edge::length(graph.nodes().get(&Node::id(edge.from)).unwrap(), end_node) as f32 + 0.0001;
-            let distance_to_target_after = Edge::length(graph.nodes().get(&Node::id(edge.to)).unwrap(), end_node) as f32 + 0.0001;
+        let distance_to_target_before = space.distance_to_target(current_node) + 0.0001;
+        let rng_weights = edge_candidates.iter().map(|&(node_id, _)| {
+            let nom_1 = state.ph_level(&(current_node, node_id));
+            let distance_to_target_after = space.distance_to_target(node_id) + 0.0001;
 
             //@TODO optimizer select
             let delta_to_target = (distance_to_target_before - distance_to_target_after).max(1.0);
@@ -124,20 +422,8 @@ impl Ant {
             // let nom_2 = ((1.0 / edge.length as f32) + (1.0/distance_to_target)).powf(BETA_COEFF);
             let nom_2 = (delta_to_target).powf(BETA_COEFF);
 
-            ((edge.from, edge.to), nom_1 * nom_2)
-        }).collect::<HashMap<EdgeId, f32>>();
-
-        let total_choice_chance = edge_candidates_chances.values().sum::<f32>();
-
-        let mut rng_weights = Vec::<f32>::with_capacity(edge_candidates.len());
-        for ec in &edge_candidates {
-            //Log edge candidate chances
-            let e_id: EdgeId = (ec.from, ec.to);
-            let e_chance = edge_candidates_chances.get(&e_id).unwrap_or(&0.0) / total_choice_chance;
-
-            rng_weights.push(e_chance);
-            // debug!("Edge from {:?} to {:?} has chance: {}", ec.from, ec.to, e_chance);
-        }
+            nom_1 * nom_2
+        }).collect::<Vec<f32>>();
 
         let node_distribution = match WeightedIndex::new(rng_weights.clone()) {
             Ok(v) => v,
@@ -149,59 +435,61 @@ impl Ant {
             }
         };
 
-        let next_node = *edge_candidates
-            .get(node_distribution.sample(&mut rng))
-            .unwrap();
+        //ACS pseudo-random proportional rule
+        if state.options.variant == AcoVariant::ColonySystem && self.rng.gen::<f32>() < ACS_EXPLOITATION_PROBABILITY {
+            let best_idx = rng_weights.iter().enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(idx, _)| idx)?;
+            return Some(edge_candidates[best_idx]);
+        }
 
-        Some(next_node.to)
+        Some(edge_candidates[node_distribution.sample(&mut self.rng)])
     }
 
-    fn generate_route(&mut self, state: &ACOState) -> (Route, f32) {
-        let mut route = Vec::<Edge>::new();
-        let mut total_length: f32 = 0.0;
-        let mut current_node = state.graph.nodes().get(&Node::id(state.route_details.starting_node)).unwrap();
-        self.nodes_visited.insert(current_node.get_id());
+    fn generate_route<S: AntSpace<Position=P>>(&mut self, state: &ACOState<S>) -> AntRoute<P> {
+        let mut route = AntRoute { steps: vec![], length: 0.0 };
+        let mut current_node = state.space.starting_position();
+        let end_node = state.space.ending_position();
+        self.nodes_visited.insert(current_node);
         let mut actions_counter: i32 = 0;
 
         while actions_counter < ANT_MAX_MOVES_COUNT {
             //If end of route reached stop
-            if current_node.get_id() == state.route_details.ending_node {
+            if current_node == end_node {
                 break;
             }
-            let end_node = state.graph.nodes().get(&Node::id(state.route_details.ending_node)).unwrap();
-            let next_node = self.select_next_node(state.graph, current_node, end_node, &state.ph_levels);
+            let next_node = self.select_next_node(state, current_node);
 
             match next_node {
                 None => {
                     // info!("Cannot select next best node");
                     break;
                 }
-                Some(selected_node) => {
-                    let edge = Edge::create(state.graph, current_node.get_id(), selected_node);
+                Some((selected_node, edge_length)) => {
                     self.nodes_visited.insert(selected_node);
-                    route.push(edge);
-                    total_length += edge.length as f32;
-                    current_node = state.graph.nodes().get(&Node::id(selected_node)).unwrap();
+                    route.steps.push(((current_node, selected_node), edge_length));
+                    route.length += edge_length;
+                    current_node = selected_node;
                 }
             }
 
             actions_counter += 1;
         }
 
-        (Route(route), total_length)
+        route
     }
 }
 
-struct AntSwarm {
-    ants: Vec<Ant>,
-    route_candidates: Vec<Route>,
+struct AntSwarm<P> {
+    ants: Vec<Ant<P>>,
+    route_candidates: Vec<AntRoute<P>>,
 }
 
-impl AntSwarm {
-    fn init() -> AntSwarm {
-        let mut ants = Vec::<Ant>::new();
-        for _ in 0..ANT_COUNT {
-            ants.push(Ant::new());
+impl<P: Copy + Eq + Hash + Debug> AntSwarm<P> {
+    fn init(seed: u64) -> AntSwarm<P> {
+        let mut ants = Vec::<Ant<P>>::new();
+        for ant_number in 0..ANT_COUNT {
+            ants.push(Ant::new(derived_rng(seed, ant_number as u64)));
         }
 
         Self {
@@ -217,7 +505,17 @@ impl AntSwarm {
         self.route_candidates.clear();
     }
 
-    fn update_pheromone_levels(&self, state: &mut ACOState) {
+    fn update_pheromone_levels<S: AntSpace<Position=P>>(&self, state: &mut ACOState<S>) {
+        match state.options.variant {
+            AcoVariant::AntSystem => self.ant_system_update(state, 1.0),
+            AcoVariant::Elitist => self.ant_system_update(state, ELITIST_WEIGHT),
+            AcoVariant::RankBased => self.rank_based_update(state),
+            AcoVariant::ColonySystem => Self::colony_system_update(state),
+            AcoVariant::MaxMin => Self::max_min_update(state),
+        }
+    }
+
+    fn ant_system_update<S: AntSpace<Position=P>>(&self, state: &mut ACOState<S>, best_route_weight: f32) {
         //Evaporate pheromone levels
         for (_, ph_level) in state.ph_levels.iter_mut() {
             *ph_level *= 1.0 - PH_EVAPORATION_RATE;
@@ -225,62 +523,480 @@ impl AntSwarm {
 
         //Update pheromone levels
         for route in self.route_candidates.iter() {
-            route.0.iter().for_each(|edge| {
-                let ph_level = state.ph_levels.entry((edge.from, edge.to)).or_insert(PH_INITIAL_LEVEL);
-                *ph_level += PH_TRACE_DELTA / (edge.length as f32 * 50.0);
+            route.steps.iter().for_each(|&(edge_id, edge_length)| {
+                let ph_level = state.ph_levels.entry(edge_id).or_insert(PH_INITIAL_LEVEL);
+                *ph_level += trail_deposit(edge_length * 50.0);
             });
         }
 
         //Add bonus pheromone levels to the best route
         if let Some(best_route) = state.get_best_route().clone() {
-            for edge in best_route.0.iter() {
-                let ph_level = state.ph_levels.entry((edge.from, edge.to)).or_insert(PH_INITIAL_LEVEL);
-                *ph_level += PH_TRACE_DELTA / edge.length as f32;
+            for (edge_id, edge_length) in best_route.steps.iter() {
+                let ph_level = state.ph_levels.entry(*edge_id).or_insert(PH_INITIAL_LEVEL);
+                *ph_level += best_route_weight * trail_deposit(*edge_length);
+            }
+        }
+    }
+
+    /// Ant of rank r deposits (RANK_COUNT - r) times the bonus of the Ant System, the best route so far RANK_COUNT times
+    fn rank_based_update<S: AntSpace<Position=P>>(&self, state: &mut ACOState<S>) {
+        for (_, ph_level) in state.ph_levels.iter_mut() {
+            *ph_level *= 1.0 - PH_EVAPORATION_RATE;
+        }
+
+        let mut ranked: Vec<&AntRoute<P>> = self.route_candidates.iter().collect();
+        ranked.sort_by(|a, b| state.compare_routes(a, b));
+        let best_route = state.get_best_route().clone();
+        let deposits = ranked.into_iter().take(RANK_COUNT - 1).enumerate()
+            .map(|(rank, route)| ((RANK_COUNT - 1 - rank) as f32, route))
+            .chain(best_route.iter().map(|route| (RANK_COUNT as f32, route)));
+        for (weight, route) in deposits {
+            for (edge_id, edge_length) in route.steps.iter() {
+                let ph_level = state.ph_levels.entry(*edge_id).or_insert(PH_INITIAL_LEVEL);
+                *ph_level += weight * trail_deposit(*edge_length);
+            }
+        }
+    }
+
+    /// ACS global update - only the edges of the best route evaporate and get the deposit of 1/L_best.
+    /// Initial level 1/(n * L) comes from the first best route of n edges, the levels are scaled to it keeping their proportions
+    fn colony_system_update<S: AntSpace<Position=P>>(state: &mut ACOState<S>) {
+        let Some(best_route) = state.get_best_route().clone() else {
+            return;
+        };
+        if best_route.steps.is_empty() {
+            return;
+        }
+        let best_route_cost = best_route.length.max(MIN_DEPOSIT_EDGE_COST);
+
+        if !state.acs_initial_level_set {
+            let initial_level = 1.0 / (best_route.steps.len() as f32 * best_route_cost);
+            let scale = initial_level / state.default_level;
+            for (_, ph_level) in state.ph_levels.iter_mut() {
+                *ph_level *= scale;
+            }
+            state.default_level = initial_level;
+            state.acs_initial_level_set = true;
+        }
+
+        let default_level = state.default_level;
+        for (edge_id, _) in best_route.steps.iter() {
+            let ph_level = state.ph_levels.entry(*edge_id).or_insert(default_level);
+            *ph_level = (1.0 - PH_EVAPORATION_RATE) * *ph_level + PH_EVAPORATION_RATE / best_route_cost;
+        }
+    }
+
+    /// Deposit on the best route balances the evaporation at the upper bound, so its edges converge to MMAS_MAX_LEVEL
+    /// while the rest fades to MMAS_MIN_LEVEL and still can be chosen
+    fn max_min_update<S: AntSpace<Position=P>>(state: &mut ACOState<S>) {
+        //Edges without any deposit evaporate as well
+        state.default_level = (state.default_level * (1.0 - PH_EVAPORATION_RATE)).clamp(MMAS_MIN_LEVEL, MMAS_MAX_LEVEL);
+        for (_, ph_level) in state.ph_levels.iter_mut() {
+            *ph_level *= 1.0 - PH_EVAPORATION_RATE;
+        }
+
+        if let Some(best_route) = state.get_best_route().clone() {
+            let default_level = state.default_level;
+            for (edge_id, _) in best_route.steps.iter() {
+                *state.ph_levels.entry(*edge_id).or_insert(default_level) += MMAS_MAX_LEVEL * PH_EVAPORATION_RATE;
             }
         }
+
+        for (_, ph_level) in state.ph_levels.iter_mut() {
+            *ph_level = ph_level.clamp(MMAS_MIN_LEVEL, MMAS_MAX_LEVEL);
+        }
+    }
+}
+
+/// Pheromone left on the edge by a single ant, the cheaper the edge the more
+fn trail_deposit(edge_cost: f32) -> f32 {
+    PH_TRACE_DELTA / edge_cost.max(MIN_DEPOSIT_EDGE_COST)
+}
+
+/// Steps of the seeding search are not simulations of the colony, so only the time limit and the cancellation of the request apply to it
+fn seeding_budget(budget: &Budget) -> Budget {
+    Budget { max_iterations: None, ..budget.clone() }
+}
+
+/// Run simulations of the colony until all of them are done or the budget runs out and return positions of the best route found
+fn run_colony<S: AntSpace>(space: &S, seed: u64, options: AcoOptions, initial_path: Option<Vec<S::Position>>,
+                           record_telemetry: bool, budget: &BudgetTracker) -> PathSearchResult<S::Position> {
+    let mut state = ACOState::new(space, options);
+    if let Some(initial_path) = initial_path {
+        state.seed_trail(&initial_path);
+    }
+    let mut ant_swarm = AntSwarm::init(seed);
+    let mut recorder = record_telemetry.then(TelemetryRecorder::new);
+    let mut partial = false;
+
+    for simulation_number in 0..SIMULATION_COUNT {
+        if budget.is_exhausted(simulation_number as u32) {
+            info!("Budget exhausted after {} simulations, returning the best route found so far", simulation_number);
+            partial = true;
+            break;
+        }
+        ant_swarm.reset();
+        let ant_routes: Vec<AntRoute<S::Position>> = match options.variant {
+            //Local update changes the trails between ants, so they have to walk one after another
+            AcoVariant::ColonySystem => ant_swarm.ants.iter_mut()
+                .map(|ant| {
+                    let ant_route = ant.generate_route(&state);
+                    state.local_update(&ant_route);
+                    ant_route
+                })
+                .collect(),
+            _ => ant_swarm.ants.par_iter_mut()
+                .map(|ant| ant.generate_route(&state))
+                .collect(),
+        };
+
+        //Routes are merged in the order of ants, so the result does not depend on the number of threads
+        for ant_route in ant_routes {
+            state.consider_route(&ant_route);
+            ant_swarm.route_candidates.push(ant_route);
+        }
+        ant_swarm.update_pheromone_levels(&mut state);
+        state.increase_iteration_number();
+
+        if let Some(recorder) = recorder.as_mut() {
+            let best_cost = match state.optimizer_mode {
+                OptimizerMode::Distance => state.best_route_length,
+                OptimizerMode::Target => f32::INFINITY,
+            };
+            let mean_cost = mean(ant_swarm.route_candidates.iter()
+                .filter(|route| route.last_position(space.starting_position()) == space.ending_position())
+                .map(|route| route.length));
+            recorder.record(best_cost, mean_cost, Some(normalised_entropy(state.ph_levels.values().copied())), None);
+        }
+    }
+
+    info!("ACO route generation finished after {} simulations", state.iteration_number);
+    if state.optimizer_mode == OptimizerMode::Target {
+        warn!("Ants have not reached the target, returning route ending closest to it");
+    }
+    PathSearchResult {
+        path: state.best_route.map(|route| route.positions(space.starting_position())),
+        telemetry: recorder.map(TelemetryRecorder::finish),
+        partial,
     }
 }
 
 
 impl RouteGenerator for RouteGeneratorACO {
-    fn generate_route(graph: &Graph, _generation_strategy: RouteGeneratorStrategy, route_details: &RouteDetails, _mode: GenerationMode) -> Route {
-        let mut state = ACOState::new(graph, route_details);
-        let mut ant_swarm = AntSwarm::init();
-        info!("Starting ACO route generation");
-
-        for _simulation_number in 0..SIMULATION_COUNT {
-            ant_swarm.reset();
-            ant_swarm.ants.iter_mut().for_each(|ant| {
-                let (ant_route, ant_route_length) = ant.generate_route(&state);
-
-                //If we reach end of the route, change optimizer mode
-                if ant_route.0.last().unwrap().to == route_details.ending_node && state.optimizer_mode == OptimizerMode::Target{
-                    debug!("Switching to distance optimizer mode");
-                    state.optimizer_mode = OptimizerMode::Distance;
-                }
+    fn name(&self) -> &str {
+        "ACO"
+    }
 
-                //Calculate distance to the destination
-                let distance_to_destination = Edge::length(
-                    graph.nodes().get(&Node::id(ant_route.0.last().unwrap().to)).unwrap(),
-                    graph.nodes().get(&Node::id(route_details.ending_node)).unwrap(),
-                ) as f32;
-
-                if OptimizerMode::Distance == state.optimizer_mode {
-                    if distance_to_destination < state.best_distance_to_target {
-                        state.best_distance_to_target = distance_to_destination;
-                        state.best_route = Some(ant_route.clone());
-                    }
-                } else if ant_route_length < state.best_route_length {
-                    state.best_route_length = ant_route_length;
-                    state.best_route = Some(ant_route.clone());
-                }
+    fn generate_route(&self, graph: &Graph, route_details: &RouteDetails) -> Route {
+        let seed = resolve_seed(route_details.seed);
+        info!("Starting ACO route generation with seed {}", seed);
+        let budget = BudgetTracker::new(&route_details.budget);
+        let seeding_budget = seeding_budget(&route_details.budget);
+        let seeding_tracker = BudgetTracker::new(&seeding_budget);
+        let cost_evaluator = CostEvaluator::new(route_details);
+        let space = GraphSpace::new(graph, route_details, &cost_evaluator);
+        let initial_path = match route_details.aco.seeding {
+            PheromoneSeeding::Uniform => None,
+            PheromoneSeeding::Greedy => Some(remove_loops(&greedy::graph_walk(graph, route_details, &cost_evaluator, &seeding_tracker).0)),
+            PheromoneSeeding::AStar => match graph_path(graph, route_details.starting_node, route_details.ending_node, &cost_evaluator, &seeding_tracker) {
+                SearchOutcome::Found(path) | SearchOutcome::Interrupted(path) => Some(path),
+                SearchOutcome::Unreachable => None,
+            },
+        };
 
-                ant_swarm.route_candidates.push(ant_route);
-            });
-            ant_swarm.update_pheromone_levels(&mut state);
+        let result = run_colony(&space, seed, route_details.aco, initial_path, route_details.telemetry, &budget);
+        let route = match result.path {
+            Some(path) => path_to_route(graph, &path, &cost_evaluator),
+            None => Route::default(),
+        };
+        Route { telemetry: result.telemetry, partial: result.partial, ..route }.with_seed(seed)
+    }
+
+    fn real_mode_supported(&self) -> bool {
+        true
+    }
+
+    fn generate_route_real_num(&self, route_details: &WxRouteDetails, grid: &[PixelColor]) -> PixRoute {
+        let seed = resolve_seed(route_details.seed);
+        info!("Starting ACO real number generation with seed {}", seed);
+        let budget = BudgetTracker::new(&route_details.budget);
+        let seeding_budget = seeding_budget(&route_details.budget);
+        let seeding_tracker = BudgetTracker::new(&seeding_budget);
+        let (Some(starting_cell), Some(ending_cell)) = (position_to_cell(&route_details.starting_position), position_to_cell(&route_details.ending_position)) else {
+            warn!("Route ends are outside of the weather grid");
+            return PixRoute::default().with_seed(seed);
+        };
+        let space = GridSpace::new(grid, route_details.obstacles, starting_cell, ending_cell);
+        let initial_path = match route_details.aco.seeding {
+            PheromoneSeeding::Uniform => None,
+            PheromoneSeeding::Greedy => Some(greedy::grid_walk(route_details, grid, starting_cell, ending_cell, &seeding_tracker).0),
+            PheromoneSeeding::AStar => match grid_path(grid, route_details.obstacles, starting_cell, ending_cell, false, &seeding_tracker) {
+                SearchOutcome::Found(cells) | SearchOutcome::Interrupted(cells) => Some(cells),
+                SearchOutcome::Unreachable => None,
+            },
+        };
+
+        let result = run_colony(&space, seed, route_details.aco, initial_path, route_details.telemetry, &budget);
+        let route = match result.path {
+            Some(cells) => PixRoute::new(cells.into_iter().map(cell_to_position).collect()),
+            None => PixRoute::default(),
+        };
+        PixRoute { telemetry: result.telemetry, partial: result.partial, ..route }.with_seed(seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mgr_weather::image_wrapper::{GRID_SIZE, Rgba};
+
+    use crate::GridPosition;
+    use crate::budget::CancellationToken;
+    use crate::cost::CostWeights;
+    use crate::lattice::{lattice_graph, lattice_node};
+    use crate::providers::astar::shortest_path;
+    use crate::wx_grid::line_cost;
+
+    use super::*;
+
+    #[test]
+    fn test_graph_route_reaches_target() {
+        let graph = lattice_graph(6, 6);
+        let route_details = RouteDetails::new(lattice_node(6, 0, 0), lattice_node(6, 5, 3));
+
+        let route = RouteGeneratorACO.generate_route(&graph, &route_details);
+        let edges = route.edges();
+        assert_eq!(edges.first().unwrap().from, route_details.starting_node);
+        assert_eq!(edges.last().unwrap().to, route_details.ending_node);
+        for pair in edges.windows(2) {
+            assert_eq!(pair[0].to, pair[1].from);
         }
+    }
 
-        info!("ACO route generation finished");
-        state.best_route.unwrap_or(Route(Vec::<Edge>::new()))
+    #[test]
+    fn test_grid_route_avoids_echo() {
+        let mut grid = vec![Rgba([0, 0, 0, 0]); GRID_SIZE * GRID_SIZE];
+        for y in 40..60 {
+            grid[y * GRID_SIZE + 50] = Rgba([0, 200, 0, 255]);
+        }
+        let route_details = WxRouteDetails::new(GridPosition { x: 40.0, y: 50.0 }, GridPosition { x: 60.0, y: 50.0 }).with_seed(7);
+
+        let route = RouteGeneratorACO.generate_route_real_num(&route_details, &grid);
+        assert_eq!(route.waypoints.first(), Some(&route_details.starting_position));
+        assert_eq!(route.waypoints.last(), Some(&route_details.ending_position));
+        for pair in route.waypoints.windows(2) {
+            let from = position_to_cell(&pair[0]).unwrap();
+            let to = position_to_cell(&pair[1]).unwrap();
+            assert!(line_cost(&grid, route_details.obstacles, from, to).is_some());
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_same_seed_gives_identical_route() {
+        let graph = lattice_graph(6, 6);
+        let route_details = RouteDetails::new(lattice_node(6, 0, 0), lattice_node(6, 5, 5)).with_seed(1234);
+
+        let first_route = RouteGeneratorACO.generate_route(&graph, &route_details);
+        let second_route = RouteGeneratorACO.generate_route(&graph, &route_details);
+
+        let edge_ids = |route: &Route| route.edges().iter().map(|edge| (edge.from, edge.to)).collect::<Vec<_>>();
+        assert_eq!(edge_ids(&first_route), edge_ids(&second_route));
+        assert_eq!(first_route.seed, Some(1234));
+        assert_eq!(second_route.seed, Some(1234));
+    }
+
+    #[test]
+    fn test_telemetry_recorded_for_every_simulation() {
+        let graph = lattice_graph(6, 6);
+        let route_details = RouteDetails::new(lattice_node(6, 0, 0), lattice_node(6, 5, 5)).with_seed(5);
+
+        let route = RouteGeneratorACO.generate_route(&graph, &route_details.clone().with_telemetry());
+        let records = route.telemetry.unwrap().records;
+
+        assert_eq!(records.len(), SIMULATION_COUNT as usize);
+        assert!(records.windows(2).all(|pair| pair[1].best_cost <= pair[0].best_cost && pair[1].elapsed >= pair[0].elapsed));
+        assert!(records.iter().all(|record| record.pheromone_entropy.is_some_and(|entropy| (0.0..=1.0).contains(&entropy))));
+        assert!(RouteGeneratorACO.generate_route(&graph, &route_details).telemetry.is_none());
+    }
+
+    #[test]
+    fn test_seeded_trail_is_the_starting_best_route() {
+        let graph = lattice_graph(6, 6);
+        let unlimited_details = RouteDetails::new(lattice_node(6, 0, 0), lattice_node(6, 5, 3)).with_seed(9);
+        let optimal = crate::providers::astar::RouteGeneratorAStar.generate_route(&graph, &unlimited_details);
+        let length = |route: &Route| route.edges().iter().map(|edge| edge.length).sum::<f64>();
+        //No simulation is run, so the route is the seeded one
+        let route_details = unlimited_details.with_max_iterations(0);
+
+        let uniform = RouteGeneratorACO.generate_route(&graph, &route_details);
+        assert!(uniform.edges().is_empty());
+
+        for seeding in [PheromoneSeeding::Greedy, PheromoneSeeding::AStar] {
+            let seeded_details = route_details.clone().with_aco(AcoOptions::new().with_seeding(seeding));
+            let route = RouteGeneratorACO.generate_route(&graph, &seeded_details);
+            assert_eq!(route.edges().last().map(|edge| edge.to), Some(route_details.ending_node), "{:?}", seeding);
+            //Both seeds go straight towards the target, so they are as short as the A* route
+            assert!((length(&route) - length(&optimal)).abs() < 0.1, "{:?}", seeding);
+        }
+    }
+
+    #[test]
+    fn test_cancelled_seeding_stops() {
+        let graph = lattice_graph(6, 6);
+        let token = CancellationToken::new();
+        token.cancel();
+        let route_details = RouteDetails::new(lattice_node(6, 0, 0), lattice_node(6, 5, 3)).with_cancellation(token);
+
+        for seeding in [PheromoneSeeding::Greedy, PheromoneSeeding::AStar] {
+            let seeded_details = route_details.clone().with_aco(AcoOptions::new().with_seeding(seeding));
+            let route = RouteGeneratorACO.generate_route(&graph, &seeded_details);
+            //Seeding search is interrupted before its first step, so there is no trail to return
+            assert!(route.partial, "{:?}", seeding);
+            assert!(route.edges().is_empty(), "{:?}", seeding);
+        }
+    }
+
+    #[test]
+    fn test_max_min_levels_stay_within_bounds() {
+        let graph = lattice_graph(6, 6);
+        let route_details = RouteDetails::new(lattice_node(6, 0, 0), lattice_node(6, 5, 5));
+        let cost_evaluator = CostEvaluator::new(&route_details);
+        let space = GraphSpace::new(&graph, &route_details, &cost_evaluator);
+        let mut state = ACOState::new(&space, AcoOptions::new().with_variant(AcoVariant::MaxMin));
+        let path = shortest_path(&graph, route_details.starting_node, route_details.ending_node, &cost_evaluator).unwrap();
+        state.seed_trail(&path);
+
+        for _ in 0..30 {
+            AntSwarm::max_min_update(&mut state);
+        }
+
+        assert_eq!(state.default_level, MMAS_MIN_LEVEL);
+        assert!(state.ph_levels.values().all(|level| (MMAS_MIN_LEVEL..=MMAS_MAX_LEVEL).contains(level)));
+        for pair in path.windows(2) {
+            assert!((state.ph_level(&(pair[0], pair[1])) - MMAS_MAX_LEVEL).abs() < 0.01);
+        }
+
+        let max_min_details = route_details.clone().with_seed(4).with_aco(AcoOptions::new().with_variant(AcoVariant::MaxMin));
+        let route = RouteGeneratorACO.generate_route(&graph, &max_min_details);
+        assert_eq!(route.edges().last().map(|edge| edge.to), Some(route_details.ending_node));
+    }
+
+    #[test]
+    fn test_every_variant_reaches_target() {
+        let graph = lattice_graph(6, 6);
+        let route_details = RouteDetails::new(lattice_node(6, 0, 5), lattice_node(6, 5, 1)).with_seed(21);
+
+        for variant in [AcoVariant::AntSystem, AcoVariant::Elitist, AcoVariant::RankBased, AcoVariant::ColonySystem, AcoVariant::MaxMin] {
+            let variant_details = route_details.clone().with_aco(AcoOptions::new().with_variant(variant));
+            let first_route = RouteGeneratorACO.generate_route(&graph, &variant_details);
+            let second_route = RouteGeneratorACO.generate_route(&graph, &variant_details);
+
+            assert_eq!(first_route.edges().first().map(|edge| edge.from), Some(route_details.starting_node), "{:?}", variant);
+            assert_eq!(first_route.edges().last().map(|edge| edge.to), Some(route_details.ending_node), "{:?}", variant);
+            let edge_ids = |route: &Route| route.edges().iter().map(|edge| (edge.from, edge.to)).collect::<Vec<_>>();
+            assert_eq!(edge_ids(&first_route), edge_ids(&second_route), "{:?}", variant);
+        }
+    }
+
+    #[test]
+    fn test_local_update_and_route_ranking() {
+        let graph = lattice_graph(3, 3);
+        let route_details = RouteDetails::new(lattice_node(3, 0, 0), lattice_node(3, 2, 0));
+        let cost_evaluator = CostEvaluator::new(&route_details);
+        let space = GraphSpace::new(&graph, &route_details, &cost_evaluator);
+        let mut state = ACOState::new(&space, AcoOptions::new().with_variant(AcoVariant::ColonySystem));
+        let (a, b, c, d) = (lattice_node(3, 0, 0), lattice_node(3, 1, 0), lattice_node(3, 2, 0), lattice_node(3, 1, 1));
+
+        let short = AntRoute { steps: vec![((a, b), 1.0), ((b, c), 1.0)], length: 2.0 };
+        let long = AntRoute { steps: vec![((a, d), 1.0), ((d, b), 1.0), ((b, c), 1.0)], length: 3.0 };
+        let stuck = AntRoute { steps: vec![((a, b), 1.0)], length: 1.0 };
+        assert_eq!(state.compare_routes(&short, &long), std::cmp::Ordering::Less);
+        assert_eq!(state.compare_routes(&stuck, &long), std::cmp::Ordering::Greater);
+
+        state.ph_levels.insert((a, b), 5.0 * PH_INITIAL_LEVEL);
+        state.local_update(&short);
+        assert_eq!(state.ph_level(&(a, b)), 4.6 * PH_INITIAL_LEVEL);
+        assert_eq!(state.ph_level(&(b, c)), PH_INITIAL_LEVEL);
+    }
+
+    #[test]
+    fn test_colony_system_deposits_inverse_best_route_cost() {
+        let graph = lattice_graph(3, 3);
+        let route_details = RouteDetails::new(lattice_node(3, 0, 0), lattice_node(3, 2, 0));
+        let cost_evaluator = CostEvaluator::new(&route_details);
+        let space = GraphSpace::new(&graph, &route_details, &cost_evaluator);
+        let mut state = ACOState::new(&space, AcoOptions::new().with_variant(AcoVariant::ColonySystem));
+        let (a, b, c, d) = (lattice_node(3, 0, 0), lattice_node(3, 1, 0), lattice_node(3, 2, 0), lattice_node(3, 1, 1));
+
+        state.ph_levels.insert((a, d), 2.0 * PH_INITIAL_LEVEL);
+        state.consider_route(&AntRoute { steps: vec![((a, b), 4.0), ((b, c), 6.0)], length: 10.0 });
+        AntSwarm::<NodeId>::colony_system_update(&mut state);
+
+        //Initial level is 1/(n * L) of the first best route and the other trails keep their proportion to it
+        let initial_level = 1.0 / (2.0 * 10.0);
+        assert_eq!(state.default_level, initial_level);
+        assert!((state.ph_level(&(a, d)) - 2.0 * initial_level).abs() < 1e-6);
+        let best_level = (1.0 - PH_EVAPORATION_RATE) * initial_level + PH_EVAPORATION_RATE / 10.0;
+        assert_eq!(state.ph_level(&(a, b)), best_level);
+        assert_eq!(state.ph_level(&(b, c)), best_level);
+
+        //Later updates deposit 1/L_best without scaling the levels again
+        AntSwarm::<NodeId>::colony_system_update(&mut state);
+        assert_eq!(state.ph_level(&(a, b)), (1.0 - PH_EVAPORATION_RATE) * best_level + PH_EVAPORATION_RATE / 10.0);
+        assert!((state.ph_level(&(a, d)) - 2.0 * initial_level).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_best_route_closest_until_target_reached_then_shortest() {
+        let graph = lattice_graph(3, 3);
+        let route_details = RouteDetails::new(lattice_node(3, 0, 0), lattice_node(3, 2, 0));
+        let cost_evaluator = CostEvaluator::new(&route_details);
+        let space = GraphSpace::new(&graph, &route_details, &cost_evaluator);
+        let mut state = ACOState::new(&space, AcoOptions::new());
+        let (a, b, c, d, e) = (lattice_node(3, 0, 0), lattice_node(3, 1, 0), lattice_node(3, 2, 0), lattice_node(3, 0, 1), lattice_node(3, 1, 1));
+
+        //Before the target is reached, ending closer to it wins over being shorter
+        let away = AntRoute { steps: vec![((a, d), 1.0)], length: 1.0 };
+        let closer = AntRoute { steps: vec![((a, d), 1.0), ((d, e), 1.0), ((e, b), 1.0)], length: 3.0 };
+        state.consider_route(&away);
+        state.consider_route(&closer);
+        assert_eq!(state.optimizer_mode, OptimizerMode::Target);
+        assert_eq!(state.best_route.as_ref().unwrap().length, 3.0);
+
+        //Once the target is reached, only shorter routes reaching it replace the best one
+        let long = AntRoute { steps: vec![((a, d), 1.0), ((d, e), 1.0), ((e, b), 1.0), ((b, c), 1.0)], length: 4.0 };
+        let short = AntRoute { steps: vec![((a, b), 1.0), ((b, c), 1.0)], length: 2.0 };
+        state.consider_route(&long);
+        assert_eq!(state.optimizer_mode, OptimizerMode::Distance);
+        assert_eq!(state.best_route.as_ref().unwrap().length, 4.0);
+        state.consider_route(&closer);
+        assert_eq!(state.best_route.as_ref().unwrap().length, 4.0);
+        state.consider_route(&short);
+        assert_eq!(state.best_route.as_ref().unwrap().length, 2.0);
+    }
+
+    #[test]
+    fn test_zero_cost_edges_get_finite_deposit() {
+        let graph = lattice_graph(5, 5);
+        //Without the congestion stats every edge costs nothing
+        let route_details = RouteDetails::new(lattice_node(5, 0, 0), lattice_node(5, 4, 4))
+            .with_cost_weights(CostWeights::from([0.0, 0.0, 0.0, 0.0, 1.0]))
+            .with_seed(5);
+
+        assert!(trail_deposit(0.0).is_finite());
+        let route = RouteGeneratorACO.generate_route(&graph, &route_details);
+        assert_eq!(route.edges().last().unwrap().to, route_details.ending_node);
+    }
+
+    #[test]
+    fn test_route_does_not_depend_on_thread_count() {
+        let graph = lattice_graph(6, 6);
+        let route_details = RouteDetails::new(lattice_node(6, 0, 5), lattice_node(6, 5, 0)).with_seed(77);
+        let generate_with_threads = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let route = pool.install(|| RouteGeneratorACO.generate_route(&graph, &route_details));
+            route.edges().iter().map(|edge| (edge.from, edge.to)).collect::<Vec<_>>()
+        };
+
+        assert_eq!(generate_with_threads(1), generate_with_threads(4));
+    }
+}
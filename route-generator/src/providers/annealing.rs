@@ -0,0 +1,157 @@
+use std::collections::HashSet;
+
+use log::{info, warn};
+use osmpbfreader::NodeId;
+use rand::Rng;
+
+use mgr_map_extractor::graph::Graph;
+use mgr_weather::image_wrapper::PixelColor;
+
+use crate::{PixRoute, Route, RouteDetails, RouteGenerator, WxRouteDetails};
+use crate::budget::BudgetTracker;
+use crate::cost::CostEvaluator;
+use crate::path::{detour, path_to_route, PathSearchResult, PathSpace, random_walk};
+use crate::rng::{resolve_seed, seeded_rng};
+use crate::telemetry::TelemetryRecorder;
+use crate::wx_grid::{cell_path_cost, cell_to_position, GridCell, position_to_cell, WxLattice};
+
+/// Simulated annealing of a single path, neighbouring solutions differ with a random detour
+pub struct RouteGeneratorSA;
+
+const MAX_ITERATIONS: u32 = 3000;
+
+//Initial temperature as a share of the cost of the initial path, so the acceptance does not depend on the units of the cost
+const INITIAL_TEMPERATURE_RATIO: f32 = 0.1;
+
+//Temperature is multiplied by it after every iteration
+const COOLING_RATE: f32 = 0.995;
+
+//Maximum number of positions expanded while searching for the detour
+const DETOUR_SEARCH_LIMIT: usize = 400;
+
+//How much randomness is added to the goal distance while walking randomly
+const WALK_NOISE: f32 = 0.6;
+
+//Maximum number of positions expanded by the random walk creating the initial path
+const INIT_WALK_LIMIT: usize = 20_000;
+
+/// Worse path is accepted with the probability falling with the temperature, the best one seen is returned.
+/// Lower cost is better
+fn anneal<S: PathSpace>(space: &S, start: S::Position, end: S::Position, cost: &dyn Fn(&[S::Position]) -> f32,
+                        seed: u64, record_telemetry: bool, budget: &BudgetTracker) -> PathSearchResult<S::Position> {
+    let mut rng = seeded_rng(seed);
+    if budget.is_exhausted(0) {
+        info!("Budget exhausted before the initial path was found");
+        return PathSearchResult { path: None, telemetry: None, partial: true };
+    }
+    let Some(mut current) = random_walk(space, start, end, &HashSet::new(), INIT_WALK_LIMIT, WALK_NOISE, &mut rng) else {
+        warn!("Ending position was not reached from the starting one within {} expansions", INIT_WALK_LIMIT);
+        return PathSearchResult { path: None, telemetry: None, partial: false };
+    };
+    let mut current_cost = cost(&current);
+    let mut best = current.clone();
+    let mut best_cost = current_cost;
+    let mut temperature = (INITIAL_TEMPERATURE_RATIO * current_cost).max(f32::EPSILON);
+
+    let mut recorder = record_telemetry.then(TelemetryRecorder::new);
+    let mut partial = false;
+    for iteration in 0..MAX_ITERATIONS {
+        if budget.is_exhausted(iteration) {
+            info!("Budget exhausted after {} iterations, returning the best route found so far", iteration);
+            partial = true;
+            break;
+        }
+
+        let candidate = detour(space, &current, DETOUR_SEARCH_LIMIT, WALK_NOISE, &mut rng);
+        let candidate_cost = cost(&candidate);
+        if candidate_cost <= current_cost || rng.gen::<f32>() < ((current_cost - candidate_cost) / temperature).exp() {
+            current = candidate;
+            current_cost = candidate_cost;
+            if current_cost < best_cost {
+                best = current.clone();
+                best_cost = current_cost;
+            }
+        }
+        temperature *= COOLING_RATE;
+
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.record(best_cost, current_cost, None, None);
+        }
+    }
+
+    info!("Best path cost: {} with {} positions", best_cost, best.len());
+    PathSearchResult {
+        path: Some(best),
+        telemetry: recorder.map(TelemetryRecorder::finish),
+        partial,
+    }
+}
+
+impl RouteGenerator for RouteGeneratorSA {
+    fn name(&self) -> &str {
+        "SA"
+    }
+
+    fn generate_route(&self, graph: &Graph, route_details: &RouteDetails) -> Route {
+        let seed = resolve_seed(route_details.seed);
+        info!("Starting SA route generation with seed {}", seed);
+        let budget = BudgetTracker::new(&route_details.budget);
+        let cost_evaluator = CostEvaluator::new(route_details);
+        let cost = |path: &[NodeId]| cost_evaluator.weighted(&cost_evaluator.path_cost(graph, path));
+
+        let result = anneal(graph, route_details.starting_node, route_details.ending_node, &cost, seed, route_details.telemetry, &budget);
+        let route = match result.path {
+            Some(path) => path_to_route(graph, &path, &cost_evaluator),
+            None => Route::default(),
+        };
+        Route { telemetry: result.telemetry, partial: result.partial, ..route }.with_seed(seed)
+    }
+
+    fn real_mode_supported(&self) -> bool {
+        true
+    }
+
+    fn generate_route_real_num(&self, route_details: &WxRouteDetails, grid: &[PixelColor]) -> PixRoute {
+        let seed = resolve_seed(route_details.seed);
+        info!("Starting SA real number generation with seed {}", seed);
+        let budget = BudgetTracker::new(&route_details.budget);
+        let (Some(starting_cell), Some(ending_cell)) = (position_to_cell(&route_details.starting_position), position_to_cell(&route_details.ending_position)) else {
+            warn!("Route ends are outside of the weather grid");
+            return PixRoute::default().with_seed(seed);
+        };
+        let cost = |cells: &[GridCell]| cell_path_cost(grid, route_details.obstacles, cells);
+
+        let result = anneal(&WxLattice(grid, route_details.obstacles), starting_cell, ending_cell, &cost, seed, route_details.telemetry, &budget);
+        let route = match result.path {
+            Some(cells) => PixRoute::new(cells.into_iter().map(cell_to_position).collect()),
+            None => PixRoute::default(),
+        };
+        PixRoute { telemetry: result.telemetry, partial: result.partial, ..route }.with_seed(seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lattice::{lattice_graph, lattice_node};
+
+    use super::*;
+
+    #[test]
+    fn test_graph_route_improves_while_cooling() {
+        let graph = lattice_graph(8, 8);
+        let route_details = RouteDetails::new(lattice_node(8, 0, 0), lattice_node(8, 7, 5)).with_seed(23).with_telemetry();
+
+        let route = RouteGeneratorSA.generate_route(&graph, &route_details);
+        let edges = route.edges();
+        assert_eq!(edges.first().unwrap().from, route_details.starting_node);
+        assert_eq!(edges.last().unwrap().to, route_details.ending_node);
+        for pair in edges.windows(2) {
+            assert_eq!(pair[0].to, pair[1].from);
+        }
+
+        let records = route.telemetry.unwrap().records;
+        assert_eq!(records.len(), MAX_ITERATIONS as usize);
+        assert!(records.windows(2).all(|pair| pair[1].best_cost <= pair[0].best_cost));
+        assert!(records.last().unwrap().best_cost <= records.first().unwrap().mean_cost);
+    }
+}
@@ -0,0 +1,392 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::hash::Hash;
+
+use log::{info, warn};
+use osmpbfreader::NodeId;
+
+use mgr_map_extractor::graph::Graph;
+use mgr_weather::image_wrapper::PixelColor;
+
+use crate::{ObstacleHandling, PixRoute, Route, RouteDetails, RouteGenerator, WxRouteDetails};
+use crate::budget::BudgetTracker;
+use crate::cost::CostEvaluator;
+use crate::path::path_to_route;
+use crate::wx_grid::{cell_distance, cell_to_position, GridCell, line_cost, neighbours, position_to_cell};
+
+/// A* search - on the graph and on the 8-connected weather grid
+pub struct RouteGeneratorAStar;
+
+/// Any-angle Theta* search on the weather grid.
+/// Road graph has no shortcuts between nodes, so in graph mode it behaves exactly like A*
+pub struct RouteGeneratorThetaStar;
+
+/// Entry of the open list, ordered so the one with the lowest estimated cost is popped first from the max-heap
+pub(crate) struct OpenEntry<T> {
+    pub(crate) estimated_cost: f32,
+    pub(crate) item: T,
+}
+
+impl<T> PartialEq for OpenEntry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.estimated_cost == other.estimated_cost
+    }
+}
+
+impl<T> Eq for OpenEntry<T> {}
+
+impl<T> PartialOrd for OpenEntry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for OpenEntry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimated_cost.partial_cmp(&self.estimated_cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Result of the search that can be interrupted when the budget runs out
+pub(crate) enum SearchOutcome<T> {
+    Found(Vec<T>),
+    Unreachable,
+    //Path leading to the expanded position closest to the target
+    Interrupted(Vec<T>),
+}
+
+/// Expanded position with the lowest estimated cost of reaching the target, where the interrupted search ends its path
+struct ClosestPosition<T> {
+    estimate: f32,
+    position: T,
+}
+
+impl<T: Copy> ClosestPosition<T> {
+    fn update(&mut self, estimate: f32, position: T) {
+        if estimate < self.estimate {
+            self.estimate = estimate;
+            self.position = position;
+        }
+    }
+}
+
+fn reconstruct_path<T: Copy + Eq + Hash>(parents: &HashMap<T, T>, end: T) -> Vec<T> {
+    let mut path = vec![end];
+    let mut current = end;
+    while let Some(&parent) = parents.get(&current) {
+        if parent == current {
+            break;
+        }
+        path.push(parent);
+        current = parent;
+    }
+    path.reverse();
+    path
+}
+
+/// Cheapest path between two graph nodes, with lower bound of the cost of flying straight to the ending node as the heuristic.
+/// Every edge is evaluated at the time it is reached, counted from the departure time
+pub(crate) fn shortest_path(graph: &Graph, starting_node: NodeId, ending_node: NodeId, cost_evaluator: &CostEvaluator) -> Option<Vec<NodeId>> {
+    shortest_path_avoiding(graph, starting_node, ending_node, cost_evaluator, &HashSet::new(), &HashSet::new())
+}
+
+/// Cheapest path between two graph nodes, search stops when the budget runs out
+pub(crate) fn graph_path(graph: &Graph, starting_node: NodeId, ending_node: NodeId, cost_evaluator: &CostEvaluator, budget: &BudgetTracker) -> SearchOutcome<NodeId> {
+    graph_search(graph, starting_node, ending_node, cost_evaluator, &HashSet::new(), &HashSet::new(), Some(budget))
+}
+
+/// Cheapest path that does not go through the excluded nodes and edges
+pub(crate) fn shortest_path_avoiding(graph: &Graph, starting_node: NodeId, ending_node: NodeId, cost_evaluator: &CostEvaluator,
+                                     excluded_nodes: &HashSet<NodeId>, excluded_edges: &HashSet<(NodeId, NodeId)>) -> Option<Vec<NodeId>> {
+    match graph_search(graph, starting_node, ending_node, cost_evaluator, excluded_nodes, excluded_edges, None) {
+        SearchOutcome::Found(path) => Some(path),
+        _ => None,
+    }
+}
+
+/// Every node expansion counts as an iteration of the budget
+fn graph_search(graph: &Graph, starting_node: NodeId, ending_node: NodeId, cost_evaluator: &CostEvaluator,
+                excluded_nodes: &HashSet<NodeId>, excluded_edges: &HashSet<(NodeId, NodeId)>, budget: Option<&BudgetTracker>) -> SearchOutcome<NodeId> {
+    let mut costs: HashMap<NodeId, f32> = HashMap::from([(starting_node, 0.0)]);
+    let mut times: HashMap<NodeId, f32> = HashMap::from([(starting_node, cost_evaluator.departure_time())]);
+    let mut parents: HashMap<NodeId, NodeId> = HashMap::from([(starting_node, starting_node)]);
+    let mut closed: HashSet<NodeId> = HashSet::new();
+    let starting_estimate = cost_evaluator.estimate(graph, starting_node, ending_node);
+    let mut open = BinaryHeap::from([OpenEntry { estimated_cost: starting_estimate, item: starting_node }]);
+    let mut closest = ClosestPosition { estimate: starting_estimate, position: starting_node };
+
+    while let Some(OpenEntry { item: current, .. }) = open.pop() {
+        if current == ending_node {
+            return SearchOutcome::Found(reconstruct_path(&parents, ending_node));
+        }
+        if budget.is_some_and(|budget| budget.is_exhausted(closed.len() as u32)) {
+            return SearchOutcome::Interrupted(reconstruct_path(&parents, closest.position));
+        }
+        if !closed.insert(current) {
+            continue;
+        }
+        closest.update(cost_evaluator.estimate(graph, current, ending_node), current);
+
+        let current_cost = costs[&current];
+        let current_time = times[&current];
+        for &next in graph.edge_connections().get(&current).into_iter().flatten() {
+            if closed.contains(&next) || excluded_nodes.contains(&next) || excluded_edges.contains(&(current, next)) {
+                continue;
+            }
+            let (edge_cost, travel_time) = cost_evaluator.edge_cost_at(graph, current, next, current_time);
+            let next_cost = current_cost + edge_cost;
+            if next_cost < *costs.get(&next).unwrap_or(&f32::MAX) {
+                costs.insert(next, next_cost);
+                times.insert(next, current_time + travel_time);
+                parents.insert(next, current);
+                open.push(OpenEntry { estimated_cost: next_cost + cost_evaluator.estimate(graph, next, ending_node), item: next });
+            }
+        }
+    }
+    SearchOutcome::Unreachable
+}
+
+/// Cheapest path between two grid cells. With `any_angle` enabled it is Theta* -
+/// cell can be connected directly with the parent of its predecessor when the straight line between them is clear.
+/// Every cell expansion counts as an iteration of the budget
+pub(crate) fn grid_path(grid: &[PixelColor], obstacles: ObstacleHandling, start: GridCell, end: GridCell, any_angle: bool, budget: &BudgetTracker) -> SearchOutcome<GridCell> {
+    let mut costs: HashMap<GridCell, f32> = HashMap::from([(start, 0.0)]);
+    let mut parents: HashMap<GridCell, GridCell> = HashMap::from([(start, start)]);
+    let mut closed: HashSet<GridCell> = HashSet::new();
+    let mut open = BinaryHeap::from([OpenEntry { estimated_cost: cell_distance(start, end), item: start }]);
+    let mut closest = ClosestPosition { estimate: cell_distance(start, end), position: start };
+
+    while let Some(OpenEntry { item: current, .. }) = open.pop() {
+        if current == end {
+            return SearchOutcome::Found(reconstruct_path(&parents, end));
+        }
+        if budget.is_exhausted(closed.len() as u32) {
+            return SearchOutcome::Interrupted(reconstruct_path(&parents, closest.position));
+        }
+        if !closed.insert(current) {
+            continue;
+        }
+        closest.update(cell_distance(current, end), current);
+
+        let current_parent = parents[&current];
+        for (next, step_cost) in neighbours(grid, obstacles, current) {
+            if closed.contains(&next) {
+                continue;
+            }
+
+            let shortcut = if any_angle && current_parent != current {
+                line_cost(grid, obstacles, current_parent, next).map(|cost| (current_parent, costs[&current_parent] + cost))
+            } else {
+                None
+            };
+            let (parent, next_cost) = shortcut.unwrap_or((current, costs[&current] + step_cost));
+
+            if next_cost < *costs.get(&next).unwrap_or(&f32::MAX) {
+                costs.insert(next, next_cost);
+                parents.insert(next, parent);
+                open.push(OpenEntry { estimated_cost: next_cost + cell_distance(next, end), item: next });
+            }
+        }
+    }
+    SearchOutcome::Unreachable
+}
+
+fn generate_graph_route(graph: &Graph, route_details: &RouteDetails) -> Route {
+    let budget = BudgetTracker::new(&route_details.budget);
+    let cost_evaluator = CostEvaluator::new(route_details);
+    match graph_search(graph, route_details.starting_node, route_details.ending_node, &cost_evaluator, &HashSet::new(), &HashSet::new(), Some(&budget)) {
+        SearchOutcome::Found(path) => path_to_route(graph, &path, &cost_evaluator),
+        SearchOutcome::Interrupted(path) => {
+            info!("Budget exhausted, returning path to the node closest to the ending node");
+            Route { partial: true, ..path_to_route(graph, &path, &cost_evaluator) }
+        }
+        SearchOutcome::Unreachable => {
+            warn!("Ending node is not reachable from the starting node");
+            Route::default()
+        }
+    }
+}
+
+fn generate_grid_route(route_details: &WxRouteDetails, grid: &[PixelColor], any_angle: bool) -> PixRoute {
+    let budget = BudgetTracker::new(&route_details.budget);
+    let (Some(start), Some(end)) = (position_to_cell(&route_details.starting_position), position_to_cell(&route_details.ending_position)) else {
+        warn!("Route ends are outside of the weather grid");
+        return PixRoute::default();
+    };
+
+    match grid_path(grid, route_details.obstacles, start, end, any_angle, &budget) {
+        SearchOutcome::Found(cells) => {
+            info!("Grid route found with {} waypoints", cells.len());
+            PixRoute::new(cells.into_iter().map(cell_to_position).collect())
+        }
+        SearchOutcome::Interrupted(cells) => {
+            info!("Budget exhausted, returning path to the cell closest to the ending position");
+            PixRoute { partial: true, ..PixRoute::new(cells.into_iter().map(cell_to_position).collect()) }
+        }
+        SearchOutcome::Unreachable => {
+            warn!("Ending position is not reachable from the starting position");
+            PixRoute::default()
+        }
+    }
+}
+
+impl RouteGenerator for RouteGeneratorAStar {
+    fn name(&self) -> &str {
+        "AStar"
+    }
+
+    fn generate_route(&self, graph: &Graph, route_details: &RouteDetails) -> Route {
+        generate_graph_route(graph, route_details)
+    }
+
+    fn real_mode_supported(&self) -> bool {
+        true
+    }
+
+    fn generate_route_real_num(&self, route_details: &WxRouteDetails, grid: &[PixelColor]) -> PixRoute {
+        generate_grid_route(route_details, grid, false)
+    }
+}
+
+impl RouteGenerator for RouteGeneratorThetaStar {
+    fn name(&self) -> &str {
+        "ThetaStar"
+    }
+
+    fn generate_route(&self, graph: &Graph, route_details: &RouteDetails) -> Route {
+        generate_graph_route(graph, route_details)
+    }
+
+    fn real_mode_supported(&self) -> bool {
+        true
+    }
+
+    fn generate_route_real_num(&self, route_details: &WxRouteDetails, grid: &[PixelColor]) -> PixRoute {
+        generate_grid_route(route_details, grid, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mgr_weather::image_wrapper::{GRID_SIZE, Rgba};
+
+    use tcas_adapter::data_feed::CongestionStats;
+
+    use crate::cost::{CostWeights, DEFAULT_CRUISE_SPEED, DefaultCostModel, GeoBounds};
+    use crate::GridPosition;
+    use crate::lattice::{lattice_graph, lattice_node};
+    use crate::wx_grid::polyline_length;
+
+    use super::*;
+
+    fn grid_with_wall() -> Vec<PixelColor> {
+        let mut grid = vec![Rgba([0, 0, 0, 0]); GRID_SIZE * GRID_SIZE];
+        for y in 10..90 {
+            grid[y * GRID_SIZE + 50] = Rgba([0, 200, 0, 255]);
+        }
+        grid
+    }
+
+    #[test]
+    fn test_graph_shortest_path() {
+        let graph = lattice_graph(5, 5);
+        let route_details = RouteDetails::new(lattice_node(5, 0, 0), lattice_node(5, 4, 2));
+        let path = shortest_path(&graph, route_details.starting_node, route_details.ending_node, &CostEvaluator::new(&route_details)).unwrap();
+
+        //Every shortest path on the lattice makes exactly as many moves as the manhattan distance
+        assert_eq!(path.len(), 7);
+        assert_eq!(path.first(), Some(&lattice_node(5, 0, 0)));
+        assert_eq!(path.last(), Some(&lattice_node(5, 4, 2)));
+    }
+
+    #[test]
+    fn test_graph_route_avoids_congested_edges() {
+        let graph = lattice_graph(3, 2);
+        let mut congestion = CongestionStats::new();
+        //Direct way along the bottom row is congested, so route has to go around it
+        congestion.add_congestion(graph.edge_by_node_id()[&(lattice_node(3, 0, 0), lattice_node(3, 1, 0))], 1);
+        let route_details = RouteDetails::new(lattice_node(3, 0, 0), lattice_node(3, 2, 0))
+            .with_cost_model(DefaultCostModel::new().with_congestion(congestion))
+            .with_cost_weights(CostWeights { congestion: 1000.0, ..CostWeights::DISTANCE });
+
+        let route = generate_graph_route(&graph, &route_details);
+        assert_eq!(route.edges().len(), 4);
+        assert_eq!(route.cost().congestion, 0.0);
+        assert!((route.cost().distance - route.edges().iter().map(|edge| edge.length as f32).sum::<f32>()).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_graph_route_depends_on_departure_time() {
+        let graph = lattice_graph(3, 2);
+        let bounds = GeoBounds { min_lat: -0.001, min_lon: -0.001, max_lat: 0.002, max_lon: 0.003 };
+        let clear = vec![Rgba([0, 0, 0, 0]); GRID_SIZE * GRID_SIZE];
+        //Storm over the southern half of the grid, where the bottom row of the lattice lies
+        let mut storm = clear.clone();
+        storm.iter_mut().skip(GRID_SIZE * GRID_SIZE / 2).for_each(|pixel| *pixel = Rgba([255, 0, 0, 255]));
+        let cost_model = DefaultCostModel::new()
+            .with_weather_frame(0.0, clear, bounds)
+            .with_weather_frame(100.0, storm, bounds);
+        let route_details = RouteDetails::new(lattice_node(3, 0, 0), lattice_node(3, 2, 0))
+            .with_cost_model(cost_model)
+            .with_cost_weights(CostWeights { weather: 10.0, ..CostWeights::DISTANCE });
+
+        let before_storm = generate_graph_route(&graph, &route_details.clone().with_departure_time(10.0));
+        let during_storm = generate_graph_route(&graph, &route_details.with_departure_time(200.0));
+
+        assert_eq!(before_storm.edges().len(), 2);
+        assert_eq!(during_storm.edges().len(), 4);
+        assert_eq!(before_storm.node_times.len(), 3);
+        assert_eq!(before_storm.node_times[0], 10.0);
+        let edge_time = before_storm.edges()[0].length as f32 / DEFAULT_CRUISE_SPEED;
+        assert!((before_storm.arrival_time().unwrap() - (10.0 + 2.0 * edge_time)).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_graph_route_unreachable() {
+        let mut graph = lattice_graph(3, 3);
+        graph.add_node(mgr_map_extractor::graph::Node::new(NodeId(100), 1.0, 1.0));
+        let route_details = RouteDetails::new(lattice_node(3, 0, 0), NodeId(100));
+
+        assert!(generate_graph_route(&graph, &route_details).edges().is_empty());
+    }
+
+    #[test]
+    fn test_grid_routes_avoid_wall() {
+        let grid = grid_with_wall();
+        let route_details = WxRouteDetails::new(GridPosition { x: 30.0, y: 50.0 }, GridPosition { x: 70.0, y: 50.0 });
+
+        let a_star_route = RouteGeneratorAStar.generate_route_real_num(&route_details, &grid);
+        let theta_star_route = RouteGeneratorThetaStar.generate_route_real_num(&route_details, &grid);
+
+        for route in [&a_star_route, &theta_star_route] {
+            assert_eq!(route.waypoints.first(), Some(&route_details.starting_position));
+            assert_eq!(route.waypoints.last(), Some(&route_details.ending_position));
+            for pair in route.waypoints.windows(2) {
+                let from = position_to_cell(&pair[0]).unwrap();
+                let to = position_to_cell(&pair[1]).unwrap();
+                assert!(line_cost(&grid, route_details.obstacles, from, to).is_some());
+            }
+        }
+
+        //Any-angle path has far fewer waypoints and is not longer than the lattice one
+        assert!(theta_star_route.waypoints.len() < a_star_route.waypoints.len());
+        assert!(polyline_length(&theta_star_route.waypoints) <= polyline_length(&a_star_route.waypoints) + 0.001);
+    }
+
+    #[test]
+    fn test_grid_route_crosses_costly_echo() {
+        let mut grid = vec![Rgba([0, 0, 0, 0]); GRID_SIZE * GRID_SIZE];
+        for y in 0..GRID_SIZE {
+            grid[y * GRID_SIZE + 50] = Rgba([0, 200, 0, 255]);
+        }
+        let blocked_details = WxRouteDetails::new(GridPosition { x: 30.0, y: 50.0 }, GridPosition { x: 70.0, y: 50.0 });
+        let costly_details = blocked_details.clone().with_obstacles(ObstacleHandling::Costly);
+
+        //Wall spans the whole grid, so it can only be crossed when echo is costly instead of blocked
+        assert!(RouteGeneratorAStar.generate_route_real_num(&blocked_details, &grid).waypoints.is_empty());
+        let route = RouteGeneratorAStar.generate_route_real_num(&costly_details, &grid);
+        assert_eq!(route.waypoints.first(), Some(&costly_details.starting_position));
+        assert_eq!(route.waypoints.last(), Some(&costly_details.ending_position));
+        assert!(route.waypoints.iter().any(|waypoint| waypoint.x == 50.0));
+    }
+}
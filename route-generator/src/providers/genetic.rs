@@ -0,0 +1,236 @@
+use std::collections::HashSet;
+
+use log::{info, warn};
+use osmpbfreader::NodeId;
+use rand::Rng;
+use rand::rngs::StdRng;
+
+use mgr_map_extractor::graph::Graph;
+use mgr_weather::image_wrapper::PixelColor;
+
+use crate::{PixRoute, Route, RouteDetails, RouteGenerator, WxRouteDetails};
+use crate::budget::BudgetTracker;
+use crate::cost::CostEvaluator;
+use crate::path::{detour, path_to_route, PathSearchResult, PathSpace, random_walk, relink};
+use crate::rng::{resolve_seed, seeded_rng};
+use crate::telemetry::{mean, TelemetryRecorder};
+use crate::wx_grid::{cell_path_cost, cell_to_position, GridCell, position_to_cell, WxLattice};
+
+/// Genetic algorithm - chromosomes are whole paths from the starting to the ending position
+pub struct RouteGeneratorGA;
+
+const POPULATION_SIZE: usize = 40;
+const MAX_GENERATIONS: u32 = 120;
+
+//Best chromosomes copied unchanged to the next generation
+const ELITE_COUNT: usize = 2;
+
+//Number of chromosomes competing for being a parent
+const TOURNAMENT_SIZE: usize = 3;
+
+const CROSSOVER_RATE: f32 = 0.9;
+const MUTATION_RATE: f32 = 0.2;
+
+//Number of generations without improvement of the best chromosome after which evolution is stopped
+const STAGNATION_LIMIT: u32 = 30;
+
+//Maximum number of positions expanded while searching for the mutated part of the path
+const MUTATION_SEARCH_LIMIT: usize = 400;
+
+//How much randomness is added to the goal distance while walking randomly
+const WALK_NOISE: f32 = 0.6;
+
+//Maximum number of positions expanded by the random walk creating a single chromosome of the initial population
+const INIT_WALK_LIMIT: usize = 20_000;
+
+#[derive(Clone)]
+struct Chromosome<P> {
+    path: Vec<P>,
+    fitness: f32,
+}
+
+/// Best of the randomly drawn chromosomes
+fn tournament<'a, P>(population: &'a [Chromosome<P>], rng: &mut StdRng) -> &'a Chromosome<P> {
+    (0..TOURNAMENT_SIZE)
+        .map(|_| &population[rng.gen_range(0..population.len())])
+        .min_by(|a, b| a.fitness.total_cmp(&b.fitness))
+        .unwrap()
+}
+
+/// Evolve population of random paths, with crossover at a common position of both parents and mutation replacing part of the path with a random detour.
+/// Lower fitness is better
+fn evolve<S: PathSpace>(space: &S, start: S::Position, end: S::Position, fitness: &dyn Fn(&[S::Position]) -> f32,
+                        seed: u64, record_telemetry: bool, budget: &BudgetTracker) -> PathSearchResult<S::Position> {
+    let mut rng = seeded_rng(seed);
+    let evaluate = |path: Vec<S::Position>| Chromosome { fitness: fitness(&path), path };
+
+    let mut population = Vec::with_capacity(POPULATION_SIZE);
+    for _ in 0..POPULATION_SIZE {
+        if budget.is_exhausted(0) {
+            info!("Budget exhausted while creating the initial population of {} chromosomes", population.len());
+            break;
+        }
+        //Walk that runs out of expansions is dropped, the first generation is smaller then
+        if let Some(path) = random_walk(space, start, end, &HashSet::new(), INIT_WALK_LIMIT, WALK_NOISE, &mut rng) {
+            population.push(evaluate(path));
+        }
+    }
+    if population.is_empty() {
+        warn!("Ending position was not reached from the starting one within {} expansions", INIT_WALK_LIMIT);
+        return PathSearchResult { path: None, telemetry: None, partial: budget.is_exhausted(0) };
+    }
+    population.sort_by(|a, b| a.fitness.total_cmp(&b.fitness));
+
+    let mut recorder = record_telemetry.then(TelemetryRecorder::new);
+    let mut generations_without_improvement = 0;
+    let mut partial = false;
+    for generation in 0..MAX_GENERATIONS {
+        if budget.is_exhausted(generation) {
+            info!("Budget exhausted after {} generations, returning the best route found so far", generation);
+            partial = true;
+            break;
+        }
+        if generations_without_improvement >= STAGNATION_LIMIT {
+            info!("Best chromosome has not improved for {} generations, stopping evolution", STAGNATION_LIMIT);
+            break;
+        }
+
+        //Population is sorted, so the elite comes first
+        let mut next_generation: Vec<Chromosome<S::Position>> = population.iter().take(ELITE_COUNT).cloned().collect();
+        while next_generation.len() < POPULATION_SIZE {
+            let first_parent = tournament(&population, &mut rng);
+            let second_parent = tournament(&population, &mut rng);
+            let mut child = match rng.gen::<f32>() < CROSSOVER_RATE {
+                true => relink(&first_parent.path, &second_parent.path, &mut rng),
+                false => first_parent.path.clone(),
+            };
+            if rng.gen::<f32>() < MUTATION_RATE {
+                child = detour(space, &child, MUTATION_SEARCH_LIMIT, WALK_NOISE, &mut rng);
+            }
+            next_generation.push(evaluate(child));
+        }
+        next_generation.sort_by(|a, b| a.fitness.total_cmp(&b.fitness));
+
+        if next_generation[0].fitness < population[0].fitness {
+            generations_without_improvement = 0;
+        } else {
+            generations_without_improvement += 1;
+        }
+        population = next_generation;
+
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.record(population[0].fitness, mean(population.iter().map(|chromosome| chromosome.fitness)), None, None);
+        }
+    }
+
+    info!("Best chromosome fitness: {} with {} positions", population[0].fitness, population[0].path.len());
+    PathSearchResult {
+        path: Some(population.swap_remove(0).path),
+        telemetry: recorder.map(TelemetryRecorder::finish),
+        partial,
+    }
+}
+
+impl RouteGenerator for RouteGeneratorGA {
+    fn name(&self) -> &str {
+        "GA"
+    }
+
+    fn generate_route(&self, graph: &Graph, route_details: &RouteDetails) -> Route {
+        let seed = resolve_seed(route_details.seed);
+        info!("Starting GA route generation with seed {}", seed);
+        let budget = BudgetTracker::new(&route_details.budget);
+        let cost_evaluator = CostEvaluator::new(route_details);
+        let fitness = |path: &[NodeId]| cost_evaluator.weighted(&cost_evaluator.path_cost(graph, path));
+
+        let result = evolve(graph, route_details.starting_node, route_details.ending_node, &fitness, seed, route_details.telemetry, &budget);
+        let route = match result.path {
+            Some(path) => path_to_route(graph, &path, &cost_evaluator),
+            None => Route::default(),
+        };
+        Route { telemetry: result.telemetry, partial: result.partial, ..route }.with_seed(seed)
+    }
+
+    fn real_mode_supported(&self) -> bool {
+        true
+    }
+
+    fn generate_route_real_num(&self, route_details: &WxRouteDetails, grid: &[PixelColor]) -> PixRoute {
+        let seed = resolve_seed(route_details.seed);
+        info!("Starting GA real number generation with seed {}", seed);
+        let budget = BudgetTracker::new(&route_details.budget);
+        let (Some(starting_cell), Some(ending_cell)) = (position_to_cell(&route_details.starting_position), position_to_cell(&route_details.ending_position)) else {
+            warn!("Route ends are outside of the weather grid");
+            return PixRoute::default().with_seed(seed);
+        };
+        let fitness = |cells: &[GridCell]| cell_path_cost(grid, route_details.obstacles, cells);
+
+        let result = evolve(&WxLattice(grid, route_details.obstacles), starting_cell, ending_cell, &fitness, seed, route_details.telemetry, &budget);
+        let route = match result.path {
+            Some(cells) => PixRoute::new(cells.into_iter().map(cell_to_position).collect()),
+            None => PixRoute::default(),
+        };
+        PixRoute { telemetry: result.telemetry, partial: result.partial, ..route }.with_seed(seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mgr_map_extractor::graph::Node;
+    use crate::lattice::{connect, lattice_graph, lattice_node};
+
+    use super::*;
+
+    /// Lattice with a wall between the columns 3 and 4, open only in the last row, so paths heading straight to the target get stuck behind it
+    fn walled_lattice() -> Graph {
+        let mut graph = Graph::new();
+        for y in 0..8 {
+            for x in 0..8 {
+                graph.add_node(Node::new(lattice_node(8, x, y), y as f64 * 0.001, x as f64 * 0.001));
+            }
+        }
+        for y in 0..8 {
+            for x in 0..8 {
+                if x + 1 < 8 && (x != 3 || y == 7) {
+                    connect(&mut graph, lattice_node(8, x, y), lattice_node(8, x + 1, y));
+                }
+                if y + 1 < 8 {
+                    connect(&mut graph, lattice_node(8, x, y), lattice_node(8, x, y + 1));
+                }
+            }
+        }
+        graph
+    }
+
+    #[test]
+    fn test_graph_route_improves_over_generations() {
+        let graph = walled_lattice();
+        let route_details = RouteDetails::new(lattice_node(8, 0, 0), lattice_node(8, 7, 0)).with_seed(17).with_telemetry();
+
+        let route = RouteGeneratorGA.generate_route(&graph, &route_details);
+        let edges = route.edges();
+        assert_eq!(edges.first().unwrap().from, route_details.starting_node);
+        assert_eq!(edges.last().unwrap().to, route_details.ending_node);
+        for pair in edges.windows(2) {
+            assert_eq!(pair[0].to, pair[1].from);
+        }
+        //Down along the wall, through the opening and back up
+        assert_eq!(edges.len(), 21);
+
+        let records = route.telemetry.unwrap().records;
+        assert!(records.windows(2).all(|pair| pair[1].best_cost <= pair[0].best_cost));
+        assert!(records.last().unwrap().best_cost < records.first().unwrap().mean_cost);
+    }
+
+
+    #[test]
+    fn test_same_seed_gives_identical_route() {
+        let graph = lattice_graph(6, 6);
+        let route_details = RouteDetails::new(lattice_node(6, 0, 5), lattice_node(6, 5, 0)).with_seed(99);
+
+        let edge_ids = |route: Route| route.edges().iter().map(|edge| (edge.from, edge.to)).collect::<Vec<_>>();
+        let first_route = RouteGeneratorGA.generate_route(&graph, &route_details);
+        let second_route = RouteGeneratorGA.generate_route(&graph, &route_details);
+        assert_eq!(edge_ids(first_route), edge_ids(second_route));
+    }
+}
@@ -1,83 +1,335 @@
-use log::warn;
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use log::{info, warn};
 use osmpbfreader::NodeId;
 
-use mgr_map_extractor::graph::{Edge, Graph, Node};
+use mgr_map_extractor::graph::Graph;
+use mgr_weather::image_wrapper::PixelColor;
 
-use crate::{GenerationMode, Route, RouteDetails, RouteGenerator, RouteGeneratorStrategy};
+use crate::{PixRoute, Route, RouteDetails, RouteGenerator, WxRouteDetails};
+use crate::budget::BudgetTracker;
+use crate::cost::CostEvaluator;
+use crate::path::{node_distance, path_to_route};
+use crate::wx_grid::{cell_distance, cell_to_position, GridCell, neighbours, position_to_cell};
 
 pub struct RouteGeneratorGreedy;
 
-#[derive(PartialEq, Eq, Debug)]
-enum MetricType {
+/// How the walker scores the next step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GreedyMetric {
+    //Select node based on the cheapest hop (cost of the edge to the next node, plain length with default weights)
     EdgeLength,
-    //Select node based on the shortest hop distance (distance to the next node)
-    GoalDistance, //Selects node that is closest to the goal
+    //Selects node that is closest to the goal, straight line distance only - cost model is not used
+    GoalDistance,
+    //Sum of the hop cost and the distance to the goal, each multiplied by its weight
+    Weighted { edge_weight: f32, goal_weight: f32 },
 }
 
-const ALG_METRIC_TYPE: MetricType = MetricType::GoalDistance;
+/// Settings of the greedy walker, chosen per request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GreedyOptions {
+    pub metric: GreedyMetric,
+    //Number of steps looked ahead before the next one is chosen, 1 means only the next step is scored
+    pub lookahead: usize,
+    //Walker steps back from the dead ends instead of stopping there
+    pub backtracking: bool,
+}
 
-impl RouteGenerator for RouteGeneratorGreedy {
-    /// Generate route using greedy algorithm. Starting point and ending point are provided in route_details
-    fn generate_route(graph: &Graph, _generation_strategy: RouteGeneratorStrategy, route_details: &RouteDetails, _mode: GenerationMode) -> Route {
-        let mut route = Vec::new();
-        let mut current_node = route_details.starting_node;
-        let ending_node = graph.nodes().get(&Node::id(route_details.ending_node)).unwrap();
+//Default metric ignores the cost model of the request, EdgeLength or Weighted have to be chosen to take it into account
+const DEFAULT_METRIC: GreedyMetric = GreedyMetric::GoalDistance;
+
+//Lookahead grows exponentially with the number of neighbours, so it is limited
+pub const MAX_GREEDY_LOOKAHEAD: usize = 4;
+
+impl GreedyMetric {
+    /// Weights of the hop cost and of the distance to the goal
+    fn weights(&self) -> (f32, f32) {
+        match *self {
+            GreedyMetric::EdgeLength => (1.0, 0.0),
+            GreedyMetric::GoalDistance => (0.0, 1.0),
+            GreedyMetric::Weighted { edge_weight, goal_weight } => (edge_weight, goal_weight),
+        }
+    }
+}
+
+impl GreedyOptions {
+    pub const fn new() -> Self {
+        GreedyOptions {
+            metric: DEFAULT_METRIC,
+            lookahead: 1,
+            backtracking: false,
+        }
+    }
+
+    pub fn with_metric(mut self, metric: GreedyMetric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    pub fn with_lookahead(mut self, lookahead: usize) -> Self {
+        self.lookahead = lookahead.clamp(1, MAX_GREEDY_LOOKAHEAD);
+        self
+    }
+
+    pub fn with_backtracking(mut self) -> Self {
+        self.backtracking = true;
+        self
+    }
+}
 
-        while current_node != route_details.ending_node {
-            match select_next_best(&route, &mut current_node, graph, ending_node) {
+impl Default for GreedyOptions {
+    fn default() -> Self {
+        GreedyOptions::new()
+    }
+}
+
+/// Greedy walk over a graph or a grid lattice
+struct Walker<'a, T> {
+    options: GreedyOptions,
+    ending: T,
+    //Next steps together with the cost of the hop
+    successors: &'a dyn Fn(T) -> Vec<(T, f32)>,
+    goal_distance: &'a dyn Fn(T) -> f32,
+    //Nodes can be visited again and only reusing the same hop is forbidden
+    revisit_nodes: bool,
+    //Every step, forward or back, counts as an iteration
+    budget: &'a BudgetTracker<'a>,
+}
+
+impl<T: Copy + Eq + Hash> Walker<'_, T> {
+    /// Path from the starting node, ending with the ending node unless the walker got stuck or the budget ran out.
+    /// Second value tells whether the walk was interrupted by the budget
+    fn walk(&self, starting: T) -> (Vec<T>, bool) {
+        let mut path = vec![starting];
+        let mut visited: HashSet<T> = HashSet::from([starting]);
+        let mut used_hops: HashSet<(T, T)> = HashSet::new();
+        let mut steps = 0;
+
+        while let Some(&current) = path.last() {
+            if current == self.ending {
+                break;
+            }
+            if self.budget.is_exhausted(steps) {
+                info!("Budget exhausted after {} steps, returning the path walked so far", steps);
+                return (path, true);
+            }
+            steps += 1;
+            let allowed = |from: T, to: T| match self.revisit_nodes {
+                true => !used_hops.contains(&(from, to)),
+                false => !visited.contains(&to),
+            };
+            match self.select_next(current, &allowed) {
+                Some(next) => {
+                    visited.insert(next);
+                    used_hops.insert((current, next));
+                    path.push(next);
+                }
+                //Dead end stays visited, so the walker does not enter it again
+                None if self.options.backtracking && path.len() > 1 => {
+                    path.pop();
+                }
                 None => {
-                    warn!("Cannot select next best node");
+                    warn!("Cannot select next best step");
                     break;
                 }
-                Some(n) => {
-                    route.push(Edge::create(graph, current_node, n));
-                    current_node = n;
-                }
             }
         }
+        (path, false)
+    }
+
+    /// Next step with the best score of the lookahead. When every look ahead ends in a dead end, only the next step is scored
+    fn select_next(&self, current: T, allowed: &dyn Fn(T, T) -> bool) -> Option<T> {
+        let (edge_weight, goal_weight) = self.options.metric.weights();
+        let candidates: Vec<(T, f32)> = (self.successors)(current).into_iter()
+            .filter(|&(next, _)| allowed(current, next))
+            .collect();
 
-        Route(route)
+        let best = |scores: Vec<(T, Option<f32>)>| scores.into_iter()
+            .filter_map(|(next, score)| score.map(|score| (next, score)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(next, _)| next);
+
+        //Options built field by field skip the clamp of with_lookahead
+        let depth = self.options.lookahead.clamp(1, MAX_GREEDY_LOOKAHEAD) - 1;
+        let looked_ahead = candidates.iter().map(|&(next, cost)| {
+            let mut lookahead_path = vec![current, next];
+            let score = self.lookahead_score(next, depth, &mut lookahead_path, allowed);
+            (next, score.map(|score| edge_weight * cost + score))
+        }).collect();
+        best(looked_ahead).or_else(|| best(candidates.iter()
+            .map(|&(next, cost)| (next, Some(edge_weight * cost + goal_weight * (self.goal_distance)(next))))
+            .collect()))
+    }
+
+    /// Best score of the walk of `depth` steps from the node, None when every such walk runs into a dead end
+    fn lookahead_score(&self, node: T, depth: usize, lookahead_path: &mut Vec<T>, allowed: &dyn Fn(T, T) -> bool) -> Option<f32> {
+        let (edge_weight, goal_weight) = self.options.metric.weights();
+        if depth == 0 || node == self.ending {
+            return Some(goal_weight * (self.goal_distance)(node));
+        }
+
+        let mut best: Option<f32> = None;
+        for (next, cost) in (self.successors)(node) {
+            if !allowed(node, next) || lookahead_path.contains(&next) {
+                continue;
+            }
+            lookahead_path.push(next);
+            if let Some(score) = self.lookahead_score(next, depth - 1, lookahead_path, allowed) {
+                best = Some(best.map_or(edge_weight * cost + score, |best| best.min(edge_weight * cost + score)));
+            }
+            lookahead_path.pop();
+        }
+        best
     }
 }
 
-//Select next node that is closest to the current node
-fn select_next_best(route: &[Edge], current_node: &mut NodeId, graph: &Graph, ending_node: &Node) -> Option<NodeId> {
-    let graph_edge_connections = graph.edge_connections();
+/// Greedy path on the graph, with the flag telling whether the budget ran out
+pub(crate) fn graph_walk(graph: &Graph, route_details: &RouteDetails, cost_evaluator: &CostEvaluator, budget: &BudgetTracker) -> (Vec<NodeId>, bool) {
+    let successors = |node: NodeId| graph.edge_connections().get(&node).into_iter().flatten()
+        .map(|&next| (next, cost_evaluator.edge_cost(graph, node, next)))
+        .collect();
+    let goal_distance = |node: NodeId| node_distance(graph, node, route_details.ending_node);
+    let walker = Walker {
+        options: route_details.greedy,
+        ending: route_details.ending_node,
+        successors: &successors,
+        goal_distance: &goal_distance,
+        //Without backtracking only reusing the same edge is forbidden
+        revisit_nodes: !route_details.greedy.backtracking,
+        budget,
+    };
+    walker.walk(route_details.starting_node)
+}
+
+/// Greedy path on the 8-connected weather grid lattice. Cells cannot be visited twice, otherwise walker would oscillate between two cells
+pub(crate) fn grid_walk(route_details: &WxRouteDetails, grid: &[PixelColor], starting_cell: GridCell, ending_cell: GridCell, budget: &BudgetTracker) -> (Vec<GridCell>, bool) {
+    //Select next cell using the same metric as in the graph mode
+    let successors = |cell: GridCell| neighbours(grid, route_details.obstacles, cell);
+    let goal_distance = |cell: GridCell| cell_distance(cell, ending_cell);
+    let walker = Walker {
+        options: route_details.greedy,
+        ending: ending_cell,
+        successors: &successors,
+        goal_distance: &goal_distance,
+        revisit_nodes: false,
+        budget,
+    };
+    walker.walk(starting_cell)
+}
+
+impl RouteGenerator for RouteGeneratorGreedy {
+    fn name(&self) -> &str {
+        "Greedy"
+    }
+
+    /// Generate route using greedy algorithm. Starting point and ending point are provided in route_details
+    fn generate_route(&self, graph: &Graph, route_details: &RouteDetails) -> Route {
+        let cost_evaluator = CostEvaluator::new(route_details);
+        let (path, partial) = graph_walk(graph, route_details, &cost_evaluator, &BudgetTracker::new(&route_details.budget));
+        Route { partial, ..path_to_route(graph, &path, &cost_evaluator) }
+    }
 
-    let mut edge_candidates: Vec<Edge> = vec![];
+    fn real_mode_supported(&self) -> bool {
+        true
+    }
 
-    //Iterate over all edges for current node
-    for &edge in graph_edge_connections.get(current_node).unwrap() {
-        let new_edge = Edge::create(graph, *current_node, edge);
-        if route.contains(&new_edge) {
-            //Edge is already in the route, skip it
-            continue;
+    fn generate_route_real_num(&self, route_details: &WxRouteDetails, grid: &[PixelColor]) -> PixRoute {
+        let (Some(starting_cell), Some(ending_cell)) = (position_to_cell(&route_details.starting_position), position_to_cell(&route_details.ending_position)) else {
+            warn!("Route ends are outside of the weather grid");
+            return PixRoute::default();
+        };
+
+        let (cells, partial) = grid_walk(route_details, grid, starting_cell, ending_cell, &BudgetTracker::new(&route_details.budget));
+        PixRoute { partial, ..PixRoute::new(cells.into_iter().map(cell_to_position).collect()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mgr_weather::image_wrapper::{GRID_SIZE, Rgba};
+
+    use crate::GridPosition;
+    use crate::lattice::{connect, lattice_graph, lattice_node};
+
+    use super::*;
+
+    #[test]
+    fn test_grid_route_on_clear_grid() {
+        let grid = vec![Rgba([0, 0, 0, 0]); GRID_SIZE * GRID_SIZE];
+        let route_details = WxRouteDetails::new(GridPosition { x: 10.0, y: 10.0 }, GridPosition { x: 20.0, y: 14.0 });
+
+        let route = RouteGeneratorGreedy.generate_route_real_num(&route_details, &grid);
+        //Walking towards the goal on a clear grid takes as many moves as the chebyshev distance
+        assert_eq!(route.waypoints.len(), 11);
+        assert_eq!(route.waypoints.first(), Some(&route_details.starting_position));
+        assert_eq!(route.waypoints.last(), Some(&route_details.ending_position));
+    }
+
+    /// Two corridors of 4 nodes joined only at their first nodes, the goal is at the end of the lower one.
+    /// End of the upper corridor lies closest to the goal, but it is a dead end
+    fn dead_end_graph() -> Graph {
+        let lattice = lattice_graph(4, 2);
+        let mut graph = Graph::new();
+        for node in lattice.nodes() {
+            graph.add_node(*node);
         }
-        edge_candidates.push(new_edge);
+        for x in 0..3 {
+            connect(&mut graph, lattice_node(4, x, 0), lattice_node(4, x + 1, 0));
+            connect(&mut graph, lattice_node(4, x, 1), lattice_node(4, x + 1, 1));
+        }
+        connect(&mut graph, lattice_node(4, 0, 0), lattice_node(4, 0, 1));
+        graph
     }
 
-    if edge_candidates.is_empty() {
-        return None;
+    #[test]
+    fn test_backtracking_leaves_dead_end() {
+        let graph = dead_end_graph();
+        let route_details = RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 1))
+            .with_greedy(GreedyOptions::new().with_backtracking());
+
+        let route = RouteGeneratorGreedy.generate_route(&graph, &route_details);
+        assert_eq!(route.edges.len(), 4);
+        assert_eq!(route.edges.last().map(|edge| edge.to), Some(lattice_node(4, 3, 1)));
+
+        //Plain walker walks the whole dead end corridor back and forth
+        let plain_route = RouteGeneratorGreedy.generate_route(&graph, &RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 1)));
+        assert_eq!(plain_route.edges.len(), 10);
+    }
+
+    #[test]
+    fn test_lookahead_avoids_dead_end() {
+        let graph = dead_end_graph();
+        let route_details = RouteDetails::new(lattice_node(4, 1, 0), lattice_node(4, 3, 1))
+            .with_greedy(GreedyOptions::new().with_lookahead(MAX_GREEDY_LOOKAHEAD));
+
+        let route = RouteGeneratorGreedy.generate_route(&graph, &route_details);
+        //Straight to the goal through node 0, node 2 leads only to the dead end
+        assert_eq!(route.edges.first().map(|edge| edge.to), Some(lattice_node(4, 0, 0)));
+        assert_eq!(route.edges.last().map(|edge| edge.to), Some(lattice_node(4, 3, 1)));
     }
 
-    // debug!("Metric type: {:?}", ALG_METRIC_TYPE);
-    if ALG_METRIC_TYPE == MetricType::EdgeLength {
-        //Sort edges by the length in place
-        edge_candidates.sort_by(|a, b| a.length.partial_cmp(&b.length).unwrap());
-    } else {
-        let distance_to_destination = |edge: &Edge| {
-            Edge::length(
-                graph.nodes().get(&Node::id(edge.to)).unwrap(),
-                ending_node,
-            )
+    #[test]
+    fn test_zero_lookahead_scores_next_step() {
+        let graph = lattice_graph(4, 4);
+        let route_details = RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 3))
+            .with_greedy(GreedyOptions { lookahead: 0, ..GreedyOptions::new() });
+
+        let route = RouteGeneratorGreedy.generate_route(&graph, &route_details);
+        assert_eq!(route.edges, RouteGeneratorGreedy.generate_route(&graph, &RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 3))).edges);
+    }
+
+    #[test]
+    fn test_weighted_metric_matches_plain_ones() {
+        let graph = lattice_graph(5, 5);
+        let route_details = RouteDetails::new(lattice_node(5, 0, 0), lattice_node(5, 4, 3));
+        let generate = |metric: GreedyMetric| {
+            let details = route_details.clone().with_greedy(GreedyOptions::new().with_metric(metric));
+            RouteGeneratorGreedy.generate_route(&graph, &details).edges
         };
 
-        //Sort edges by the distance to the ending node
-        edge_candidates.sort_by(|a, b| {
-            let a_dist = distance_to_destination(a);
-            let b_dist = distance_to_destination(b);
-            a_dist.partial_cmp(&b_dist).unwrap()
-        })
+        assert_eq!(generate(GreedyMetric::GoalDistance), generate(GreedyMetric::Weighted { edge_weight: 0.0, goal_weight: 2.0 }));
+        assert_eq!(generate(GreedyMetric::EdgeLength), generate(GreedyMetric::Weighted { edge_weight: 1.0, goal_weight: 0.0 }));
+        assert_eq!(generate(GreedyMetric::Weighted { edge_weight: 1.0, goal_weight: 1.0 }).len(), 7);
     }
-    Some(edge_candidates[0].to)
-}
\ No newline at end of file
+}
@@ -1,3 +1,6 @@
 pub mod greedy;
 pub mod aco;
-pub mod pso;
\ No newline at end of file
+pub mod pso;
+pub mod astar;
+pub mod genetic;
+pub mod annealing;
\ No newline at end of file
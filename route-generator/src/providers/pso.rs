@@ -1,14 +1,23 @@
-use std::ops::Index;
+use std::collections::HashSet;
+use std::time::Instant;
 
 use log::{info, warn};
 use osmpbfreader::NodeId;
 use rand::distributions::Uniform;
 use rand::prelude::Distribution;
+use rand::rngs::StdRng;
 
 use mgr_map_extractor::graph::Graph;
 use mgr_weather::image_wrapper::{GRID_SIZE, PixelColor};
 
-use crate::{GenerationMode, GridPosition, PixRoute, Route, RouteDetails, RouteGenerator, RouteGeneratorStrategy, WxRouteDetails};
+use crate::{GridPosition, ObstacleHandling, PixRoute, Route, RouteDetails, RouteGenerator, WxRouteDetails};
+use crate::budget::{Budget, BudgetTracker};
+use crate::cost::CostEvaluator;
+use crate::path::{detour, path_to_route, random_walk, relink};
+use crate::providers::astar::{grid_path, SearchOutcome};
+use crate::rng::{resolve_seed, seeded_rng};
+use crate::telemetry::{mean, TelemetryRecorder};
+use crate::wx_grid::{cell_centre, distance, polyline_length, position_to_cell, segment_blocked_cells};
 
 pub struct RouteGeneratorPSO;
 
@@ -20,12 +29,40 @@ const MAX_VELOCITY: f32 = 15.0;
 const C1_COEFF: f32 = 0.9;
 const C2_COEFF: f32 = 1.25;
 
-const STATIC_WEIGHT_COEFF: f32 = 0.99; //0.6
+//Inertia weight decreases linearly from MAX to MIN over the simulation, first exploring and then refining the solution
+const MAX_WEIGHT_COEFF: f32 = 0.9;
+const MIN_WEIGHT_COEFF: f32 = 0.4;
 
-//Threshold fitness value at which simulation is stopped - good enough solution has been found
-const FITNESS_THRESHOLD_STOP: f32 = 1.15;
+//Number of intermediate waypoints between starting and ending position encoded by each particle
+const WAYPOINTS_COUNT: usize = 6;
 
-const NUMBER_OF_SEED_POINTS: usize = 5;
+//How far (in grid cells) initial waypoints can be scattered from the straight line between route ends
+const WAYPOINTS_INIT_SPREAD: f32 = 30.0;
+
+//Fitness penalty for every blocked cell sample that the path goes through
+const OBSTACLE_PENALTY: f32 = 50.0;
+
+//Number of iterations without improvement of the global best after which simulation is stopped
+const STAGNATION_LIMIT: u32 = 60;
+
+//Discrete (graph) mode works on whole paths, so it needs far fewer particles and iterations than real number mode
+const D_MAX_ITERATIONS: u32 = 150;
+const D_PARTICLES_COUNT: u32 = 40;
+
+//Discrete mode converges faster, so it is stopped earlier
+const D_STAGNATION_LIMIT: u32 = 25;
+
+//Chance that particle makes a random detour on a part of its path (inertia component)
+const D_DETOUR_CHANCE: f32 = 0.3;
+
+//Maximum number of nodes expanded while searching for a detour
+const D_DETOUR_SEARCH_LIMIT: usize = 400;
+
+//How much randomness is added to the goal distance while ordering neighbours in a random walk
+const D_WALK_NOISE: f32 = 0.6;
+
+//Maximum number of nodes expanded by the random walk placing a single particle
+const D_INIT_WALK_LIMIT: usize = 20_000;
 
 struct Velocity2D {
     vx: f32,
@@ -37,8 +74,9 @@ struct Simulation {
     route_details: WxRouteDetails,
     particles: Vec<Particle>,
     global_best: f32,
-    global_best_position: GridPosition,
-    last_fitness_value: f32,
+    global_best_position: Vec<GridPosition>,
+    iterations_without_improvement: u32,
+    rng: StdRng,
 }
 
 struct DSimulation {
@@ -47,52 +85,45 @@ struct DSimulation {
     particles: Vec<DParticle>,
     global_best: f32,
     global_best_position: Vec<NodeId>,
-    last_fitness_value: f32,
+    iterations_without_improvement: u32,
+    rng: StdRng,
 }
 
 struct Particle {
-    position: GridPosition,
-    velocity: Velocity2D,
+    position: Vec<GridPosition>,
+    //Position is a vector of intermediate waypoints, each one moves with its own velocity
+    velocity: Vec<Velocity2D>,
     personal_best: f32,
-    personal_best_position: GridPosition,
+    personal_best_position: Vec<GridPosition>,
+}
+
+/// Discrete velocity - set of path relinking moves that will be applied to the particle position
+#[derive(Default)]
+struct DVelocity {
+    towards_personal_best: bool,
+    towards_global_best: bool,
+    detour: bool,
 }
 
 struct DParticle {
     position: Vec<NodeId>,
-    //Position is a vector of nodes
-    velocity: Velocity2D,
+    //Position is a vector of nodes - connected path from starting to ending node
+    velocity: DVelocity,
     personal_best: f32,
     personal_best_position: Vec<NodeId>,
 }
 
 
-fn designate_points_on_line(start_point: GridPosition, end_point: GridPosition, number_of_points: usize) -> Vec<GridPosition> {
-    let dtx = (end_point.x - start_point.x) / number_of_points as f32;
-    let dty = (end_point.y - start_point.y) / number_of_points as f32;
-
-    let mut points = vec![];
-
-    for point_number in 0..number_of_points {
-        let tx = 0.5 * dtx + (point_number as f32) * dtx;
-        let ty = 0.5 * dty + (point_number as f32) * dty;
-        let check_point = GridPosition { x: (start_point.x + tx).floor(), y: (start_point.y + ty).floor() };
-        //add point if it is not already in the list
-        if !points.contains(&check_point) {
-            points.push(check_point);
-        }
-    }
-    points
-}
-
 impl DSimulation {
-    fn new(route_details: RouteDetails) -> DSimulation {
+    fn new(route_details: RouteDetails, seed: u64) -> DSimulation {
         DSimulation {
             iteration_number: 0,
             route_details,
             particles: vec![],
             global_best: f32::MAX,
             global_best_position: vec![],
-            last_fitness_value: f32::MAX,
+            iterations_without_improvement: 0,
+            rng: seeded_rng(seed),
         }
     }
 
@@ -100,95 +131,198 @@ impl DSimulation {
         self.iteration_number += 1;
     }
 
-    fn init_particles(&mut self) {}
+    /// Spawn particles on random paths connecting starting and ending node, until the swarm is full or the budget runs out
+    fn init_particles(&mut self, graph: &Graph, budget: &BudgetTracker) {
+        let starting_node = self.route_details.starting_node;
+        let ending_node = self.route_details.ending_node;
 
-    fn swarm_update(&mut self, graph: &Graph) {}
-}
-
-impl DParticle{
+        for _ in 0..D_PARTICLES_COUNT {
+            if budget.is_exhausted(0) {
+                info!("Budget exhausted after placing {} particles", self.particles.len());
+                break;
+            }
+            //Walk that runs out of expansions is dropped, the swarm is smaller then
+            if let Some(position) = random_walk(graph, starting_node, ending_node, &HashSet::new(), D_INIT_WALK_LIMIT, D_WALK_NOISE, &mut self.rng) {
+                self.particles.push(DParticle {
+                    position,
+                    velocity: DVelocity::default(),
+                    personal_best: f32::MAX,
+                    personal_best_position: vec![],
+                });
+            }
+        }
+        if self.particles.is_empty() {
+            warn!("Ending node was not reached from the starting node within {} expansions", D_INIT_WALK_LIMIT);
+        }
+    }
 
-    //Set new position while moving along discrete coordinates
-    fn update_position(&mut self, graph: &Graph, global_best: &[NodeId]){
-        //Update position
-        let mut new_position = self.position.clone();
-        let mut rng = rand::thread_rng();
-        let between = Uniform::from(0..self.position.len());
-        let random_index = between.sample(&mut rng);
+    /// Move the swarm and return the mean fitness of its particles before the move
+    fn swarm_update(&mut self, graph: &Graph) -> f32 {
+        let mean_fitness = self.evaluate_particles(graph);
+        for particle in self.particles.iter_mut() {
+            //Update velocity and position
+            particle.update_velocity(&mut self.rng);
+            particle.update_position(graph, &self.global_best_position, &mut self.rng);
+        }
+        mean_fitness
     }
 
-    fn update_velocity(){
+    /// Update personal and global bests with the current positions and return the mean fitness of the particles
+    fn evaluate_particles(&mut self, graph: &Graph) -> f32 {
+        let mut improved = false;
+        let mut fitness_sum = 0.0;
+        let cost_evaluator = CostEvaluator::new(&self.route_details);
+        for particle in self.particles.iter_mut() {
+            //Calculate fitness function for each particle
+            let fitness = particle.calculate_fitness(graph, &cost_evaluator);
+            fitness_sum += fitness;
+            //Set personal & global best values
+            if fitness < particle.personal_best {
+                particle.personal_best = fitness;
+                particle.personal_best_position = particle.position.clone();
+            }
+            if fitness < self.global_best {
+                self.global_best = fitness;
+                self.global_best_position = particle.position.clone();
+                improved = true;
+            }
+        }
 
+        if improved {
+            self.iterations_without_improvement = 0;
+        } else {
+            self.iterations_without_improvement += 1;
+        }
+        fitness_sum / self.particles.len() as f32
     }
 
+    /// Mean share of the nodes that particles do not have in common with the global best, from 0.0 to 1.0
+    fn diversity(&self) -> f32 {
+        let global_best: HashSet<NodeId> = self.global_best_position.iter().copied().collect();
+        mean(self.particles.iter().map(|particle| {
+            let nodes: HashSet<NodeId> = particle.position.iter().copied().collect();
+            let union = nodes.union(&global_best).count().max(1);
+            1.0 - nodes.intersection(&global_best).count() as f32 / union as f32
+        }))
+    }
 }
 
-impl Particle {
-    fn calculate_fitness(&self, target_point: &GridPosition) -> f32 {
-        let x_diff = self.position.x - target_point.x;
-        let y_diff = self.position.y - target_point.y;
-        (x_diff.powf(2.0) + y_diff.powf(2.0)).sqrt()
+impl DParticle {
+    fn calculate_fitness(&self, graph: &Graph, cost_evaluator: &CostEvaluator) -> f32 {
+        cost_evaluator.weighted(&cost_evaluator.path_cost(graph, &self.position))
     }
 
-    fn update_position(&mut self, global_best_pos: GridPosition, grid: &[PixelColor]) {
-        //Check if new position is inside permitted area
-        //New position cannot be inside colored area
+    //Set new position while moving along discrete coordinates
+    fn update_position(&mut self, graph: &Graph, global_best: &[NodeId], rng: &mut StdRng) {
+        let mut new_position = self.position.clone();
 
-        let new_pos_x = (self.position.x + self.velocity.vx).floor();
-        let new_pos_y = (self.position.y + self.velocity.vy).floor();
+        if self.velocity.detour {
+            new_position = detour(graph, &new_position, D_DETOUR_SEARCH_LIMIT, D_WALK_NOISE, rng);
+        }
+        if self.velocity.towards_personal_best {
+            new_position = relink(&new_position, &self.personal_best_position, rng);
+        }
+        if self.velocity.towards_global_best {
+            new_position = relink(&new_position, global_best, rng);
+        }
 
-        let mut points_to_check = designate_points_on_line(self.position, GridPosition { x: new_pos_x, y: new_pos_y }, 15);
-        points_to_check.push(GridPosition { x: new_pos_x, y: new_pos_y });
+        self.position = new_position;
+    }
 
-        for point in points_to_check {
-            let grid_cell_index = (point.y * (GRID_SIZE as f32) + point.x) as usize;
-            if grid_cell_index >= grid.len() {
-                // debug!("New position is outside of grid, not updating position");
-                return;
-            }
+    //Draw which relinking moves will be made in this iteration, using the same coefficients as real number mode
+    fn update_velocity(&mut self, rng: &mut StdRng) {
+        let between = Uniform::from(0.0..=1.0);
+        let max_attraction = C1_COEFF.max(C2_COEFF);
 
-            let pixel_value = grid[grid_cell_index];
-            if *pixel_value.index(0) >= 1 ||
-                *pixel_value.index(1) >= 1 ||
-                *pixel_value.index(2) >= 1
-            {
-                return;
-            }
-        }
+        self.velocity = DVelocity {
+            towards_personal_best: C1_COEFF * between.sample(rng) > 0.5 * max_attraction,
+            towards_global_best: C2_COEFF * between.sample(rng) > 0.5 * max_attraction,
+            detour: between.sample(rng) < D_DETOUR_CHANCE,
+        };
+    }
+}
+
+impl Particle {
+    /// Path length with penalty for every blocked cell on the way
+    fn calculate_fitness(&self, route_details: &WxRouteDetails, grid: &[PixelColor]) -> f32 {
+        let path = full_path(route_details, &self.position);
+        polyline_length(&path) + OBSTACLE_PENALTY * path_blocked_cells(grid, &path) as f32
+    }
 
-        self.position.x = new_pos_x;
-        self.position.y = new_pos_y;
+    //Move waypoints, keeping them inside the grid
+    fn update_position(&mut self) {
+        let max_position = (GRID_SIZE - 1) as f32;
+        for (waypoint, velocity) in self.position.iter_mut().zip(self.velocity.iter()) {
+            waypoint.x = (waypoint.x + velocity.vx).clamp(0.0, max_position);
+            waypoint.y = (waypoint.y + velocity.vy).clamp(0.0, max_position);
+        }
     }
 
-    fn update_velocity_partial(global_best_pos_partial: f32, particle_best_pos_partial: f32, particle_curr_position: f32, particle_velocity: &mut f32, iteration_number: i32) {
+    fn update_velocity_partial(global_best_pos_partial: f32, particle_best_pos_partial: f32, particle_curr_position: f32, particle_velocity: &mut f32, iteration_number: i32, rng: &mut StdRng) {
         let between = Uniform::from(0.0..=1.0);
-        let mut rng = rand::thread_rng();
-        *particle_velocity = calculate_weight_coeff(iteration_number)
+        *particle_velocity = (calculate_weight_coeff(iteration_number)
             * (*particle_velocity)
-            + C1_COEFF * between.sample(&mut rng)
+            + C1_COEFF * between.sample(rng)
             * (particle_best_pos_partial - particle_curr_position)
-            + C2_COEFF * between.sample(&mut rng)
-            * (global_best_pos_partial - particle_curr_position);
+            + C2_COEFF * between.sample(rng)
+            * (global_best_pos_partial - particle_curr_position))
+            .clamp(MIN_VELOCITY, MAX_VELOCITY);
     }
 
-    fn update_velocity(&mut self, global_best_pos: GridPosition, iteration_number: i32) {
-        Particle::update_velocity_partial(global_best_pos.x, self.personal_best_position.x, self.position.x, &mut self.velocity.vx, iteration_number);
-        Particle::update_velocity_partial(global_best_pos.y, self.personal_best_position.y, self.position.y, &mut self.velocity.vy, iteration_number);
+    fn update_velocity(&mut self, global_best_pos: &[GridPosition], iteration_number: i32, rng: &mut StdRng) {
+        for (idx, velocity) in self.velocity.iter_mut().enumerate() {
+            Particle::update_velocity_partial(global_best_pos[idx].x, self.personal_best_position[idx].x, self.position[idx].x, &mut velocity.vx, iteration_number, rng);
+            Particle::update_velocity_partial(global_best_pos[idx].y, self.personal_best_position[idx].y, self.position[idx].y, &mut velocity.vy, iteration_number, rng);
+        }
     }
 }
 
+/// Polyline going from the starting position through all waypoints to the ending position
+fn full_path(route_details: &WxRouteDetails, waypoints: &[GridPosition]) -> Vec<GridPosition> {
+    let mut path = Vec::with_capacity(waypoints.len() + 2);
+    path.push(route_details.starting_position);
+    path.extend_from_slice(waypoints);
+    path.push(route_details.ending_position);
+    path
+}
+
+/// Number of blocked cell samples on all segments of the polyline
+fn path_blocked_cells(grid: &[PixelColor], path: &[GridPosition]) -> usize {
+    path.windows(2).map(|pair| segment_blocked_cells(grid, &pair[0], &pair[1])).sum()
+}
+
+/// Replace the segments crossing blocked cells with Theta* paths through the centres of free cells.
+/// None when some of them cannot be rerouted
+fn reroute_blocked_segments(grid: &[PixelColor], path: &[GridPosition], budget: &BudgetTracker) -> Option<Vec<GridPosition>> {
+    let mut rerouted = vec![*path.first()?];
+    for pair in path.windows(2) {
+        if segment_blocked_cells(grid, &pair[0], &pair[1]) > 0 {
+            let (start, end) = (position_to_cell(&pair[0])?, position_to_cell(&pair[1])?);
+            let SearchOutcome::Found(cells) = grid_path(grid, ObstacleHandling::Blocked, start, end, true, budget) else {
+                return None;
+            };
+            rerouted.extend(cells.into_iter().map(cell_centre));
+        }
+        rerouted.push(pair[1]);
+    }
+    Some(rerouted)
+}
+
 fn calculate_weight_coeff(iter_number: i32) -> f32 {
-    STATIC_WEIGHT_COEFF
+    let progress = (iter_number as f32 / MAX_ITERATIONS as f32).min(1.0);
+    MAX_WEIGHT_COEFF - (MAX_WEIGHT_COEFF - MIN_WEIGHT_COEFF) * progress
 }
 
 impl Simulation {
-    fn new(route_details: WxRouteDetails) -> Simulation {
+    fn new(route_details: WxRouteDetails, seed: u64) -> Simulation {
         Simulation {
             iteration_number: 0,
             route_details,
             particles: vec![],
             global_best: f32::MAX,
-            global_best_position: GridPosition { x: 0.0, y: 0.0 },
-            last_fitness_value: f32::MAX,
+            global_best_position: vec![],
+            iterations_without_improvement: 0,
+            rng: seeded_rng(seed),
         }
     }
 
@@ -196,104 +330,200 @@ impl Simulation {
         self.iteration_number += 1;
     }
 
-    fn init_particles(&mut self, starting_position: Option<GridPosition>) {
-        let between = Uniform::from(MIN_VELOCITY..=MAX_VELOCITY);
-        let mut rng = rand::thread_rng();
+    /// Spawn particles with waypoints scattered around the straight line between route ends
+    fn init_particles(&mut self) {
+        let velocity_between = Uniform::from(MIN_VELOCITY..=MAX_VELOCITY);
+        let spread_between = Uniform::from(-WAYPOINTS_INIT_SPREAD..=WAYPOINTS_INIT_SPREAD);
+        let rng = &mut self.rng;
+        let max_position = (GRID_SIZE - 1) as f32;
+
+        let start = self.route_details.starting_position;
+        let end = self.route_details.ending_position;
 
         for _ in 0..PARTICLES_COUNT {
+            let position: Vec<GridPosition> = (1..=WAYPOINTS_COUNT).map(|waypoint_number| {
+                let t = waypoint_number as f32 / (WAYPOINTS_COUNT + 1) as f32;
+                GridPosition {
+                    x: (start.x + (end.x - start.x) * t + spread_between.sample(rng)).clamp(0.0, max_position),
+                    y: (start.y + (end.y - start.y) * t + spread_between.sample(rng)).clamp(0.0, max_position),
+                }
+            }).collect();
+            let velocity = (0..WAYPOINTS_COUNT)
+                .map(|_| Velocity2D { vx: velocity_between.sample(rng), vy: velocity_between.sample(rng) })
+                .collect();
+
             self.particles.push(Particle {
-                position: starting_position.unwrap_or(GridPosition { x: 0.0, y: 0.0 }),
-                velocity: Velocity2D { vx: between.sample(&mut rng), vy: between.sample(&mut rng) },
+                personal_best_position: position.clone(),
+                position,
+                velocity,
                 personal_best: f32::MAX,
-                personal_best_position: GridPosition { x: 0.0, y: 0.0 },
             });
         }
     }
 
-    fn swarm_update(&mut self, grid: &[PixelColor]) {
+    /// Move the swarm and return the mean fitness of its particles before the move
+    fn swarm_update(&mut self, grid: &[PixelColor]) -> f32 {
+        let mean_fitness = self.evaluate_particles(grid);
+        for particle in self.particles.iter_mut() {
+            //Update velocity and position
+            particle.update_velocity(&self.global_best_position, self.iteration_number, &mut self.rng);
+            particle.update_position();
+        }
+        mean_fitness
+    }
+
+    /// Update personal and global bests with the current positions and return the mean fitness of the particles
+    fn evaluate_particles(&mut self, grid: &[PixelColor]) -> f32 {
+        let mut improved = false;
+        let mut fitness_sum = 0.0;
         for particle in self.particles.iter_mut() {
             //Calculate fitness function for each particle
-            let fitness = particle.calculate_fitness(&self.route_details.ending_position);
-            self.last_fitness_value = fitness;
+            let fitness = particle.calculate_fitness(&self.route_details, grid);
+            fitness_sum += fitness;
             //Set personal & global best values
             if fitness < particle.personal_best {
                 particle.personal_best = fitness;
-                particle.personal_best_position = particle.position;
+                particle.personal_best_position = particle.position.clone();
             }
             if fitness < self.global_best {
                 self.global_best = fitness;
-                self.global_best_position = particle.position;
+                self.global_best_position = particle.position.clone();
+                improved = true;
             }
+        }
 
-            //Update position and velocity
-            particle.update_position(self.global_best_position, grid);
-            particle.update_velocity(self.global_best_position, self.iteration_number);
+        if improved {
+            self.iterations_without_improvement = 0;
+        } else {
+            self.iterations_without_improvement += 1;
         }
+        fitness_sum / self.particles.len() as f32
+    }
+
+    /// Mean distance of the particle waypoints from the centres of the swarm, in grid cells
+    fn diversity(&self) -> f32 {
+        let centres: Vec<GridPosition> = (0..WAYPOINTS_COUNT).map(|waypoint_idx| GridPosition {
+            x: mean(self.particles.iter().map(|particle| particle.position[waypoint_idx].x)),
+            y: mean(self.particles.iter().map(|particle| particle.position[waypoint_idx].y)),
+        }).collect();
+        mean(self.particles.iter().flat_map(|particle| {
+            particle.position.iter().zip(centres.iter()).map(|(position, centre)| distance(position, centre))
+        }))
     }
 }
 
 impl RouteGenerator for RouteGeneratorPSO {
-    fn generate_route(graph: &Graph, _generation_strategy: RouteGeneratorStrategy, route_details: &RouteDetails, _mode: GenerationMode) -> Route {
-        warn!("Starting discrete mode");
-        let mut pix_route = Route(vec![]);
+    fn name(&self) -> &str {
+        "PSO"
+    }
+
+    fn generate_route(&self, graph: &Graph, route_details: &RouteDetails) -> Route {
+        let seed = resolve_seed(route_details.seed);
+        info!("Starting discrete mode with seed {}", seed);
 
         //Spawn particles
         //Init positions
-        let mut simulation = DSimulation::new(route_details.clone());
-        simulation.init_particles();
-        //Log simulation solution
-        info!("Global best value: {} at: {:?}", simulation.global_best, simulation.global_best_position);
+        let budget = BudgetTracker::new(&route_details.budget);
+        let mut simulation = DSimulation::new(route_details.clone(), seed);
+        simulation.init_particles(graph, &budget);
+        if simulation.particles.is_empty() {
+            return Route { partial: budget.is_exhausted(0), ..Route::default() }.with_seed(seed);
+        }
 
-        for _iter_id in 0..MAX_ITERATIONS {
-            if FITNESS_THRESHOLD_STOP >= simulation.last_fitness_value {
-                info!("Good enough solution found, stopping simulation");
+        let mut recorder = route_details.telemetry.then(TelemetryRecorder::new);
+        let mut partial = false;
+        for iter_id in 0..D_MAX_ITERATIONS {
+            if budget.is_exhausted(iter_id) {
+                info!("Budget exhausted after {} iterations, returning the best route found so far", iter_id);
+                partial = true;
+                break;
+            }
+            if simulation.iterations_without_improvement >= D_STAGNATION_LIMIT {
+                info!("Global best has not improved for {} iterations, stopping simulation", D_STAGNATION_LIMIT);
                 break;
             }
-            simulation.swarm_update(graph);
-            // pix_route.0.push(simulation.global_best_position);
+            let mean_fitness = simulation.swarm_update(graph);
             simulation.increase_iteration_count();
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.record(simulation.global_best, mean_fitness, None, Some(simulation.diversity()));
+            }
         }
 
-        info!("Global best value: {} at: {:?}", simulation.global_best, simulation.global_best_position);
-        info!("Iteration count: {}", simulation.iteration_number);
-
-        pix_route
-
+        //Budget exhausted before the first iteration, the best of the initial particles is returned
+        if simulation.global_best_position.is_empty() {
+            simulation.evaluate_particles(graph);
+        }
 
+        //Log simulation solution
+        info!("Global best value: {} with {} nodes", simulation.global_best, simulation.global_best_position.len());
+        info!("Iteration count: {}", simulation.iteration_number);
 
+        let route = path_to_route(graph, &simulation.global_best_position, &CostEvaluator::new(route_details));
+        Route { telemetry: recorder.map(TelemetryRecorder::finish), partial, ..route }.with_seed(seed)
     }
 
-    fn real_mode_supported() -> bool {
+    fn real_mode_supported(&self) -> bool {
         true
     }
 
-    fn generate_route_real_num(route_details: &WxRouteDetails, grid: &Vec<PixelColor>) -> PixRoute {
-        info!("Starting real number generation");
-
-        let mut pix_route = PixRoute(vec![]);
+    fn generate_route_real_num(&self, route_details: &WxRouteDetails, grid: &[PixelColor]) -> PixRoute {
+        let seed = resolve_seed(route_details.seed);
+        info!("Starting real number generation with seed {}", seed);
 
         //Spawn particles
         //Init positions
-        let mut simulation = Simulation::new(*route_details);
-        simulation.init_particles(Some(route_details.starting_position));
-
-        //Log simulation solution
-        info!("Global best value: {} at: {:?}", simulation.global_best, simulation.global_best_position);
+        let started = Instant::now();
+        let budget = BudgetTracker::new(&route_details.budget);
+        let mut simulation = Simulation::new(route_details.clone(), seed);
+        simulation.init_particles();
 
-        for _iter_id in 0..MAX_ITERATIONS {
-            if FITNESS_THRESHOLD_STOP >= simulation.last_fitness_value {
-                info!("Good enough solution found, stopping simulation");
+        let mut recorder = route_details.telemetry.then(TelemetryRecorder::new);
+        let mut partial = false;
+        for iter_id in 0..MAX_ITERATIONS {
+            if budget.is_exhausted(iter_id) {
+                info!("Budget exhausted after {} iterations, returning the best route found so far", iter_id);
+                partial = true;
                 break;
             }
-            simulation.swarm_update(grid);
-            pix_route.0.push(simulation.global_best_position);
+            if simulation.iterations_without_improvement >= STAGNATION_LIMIT {
+                info!("Global best has not improved for {} iterations, stopping simulation", STAGNATION_LIMIT);
+                break;
+            }
+            let mean_fitness = simulation.swarm_update(grid);
             simulation.increase_iteration_count();
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.record(simulation.global_best, mean_fitness, None, Some(simulation.diversity()));
+            }
+        }
+
+        //Budget exhausted before the first iteration, the best of the initial particles is returned
+        if simulation.global_best_position.is_empty() {
+            simulation.evaluate_particles(grid);
         }
 
-        info!("Global best value: {} at: {:?}", simulation.global_best, simulation.global_best_position);
+        //Log simulation solution
+        let pix_route = full_path(route_details, &simulation.global_best_position);
+        info!("Global best value: {} with path length: {}", simulation.global_best, polyline_length(&pix_route));
         info!("Iteration count: {}", simulation.iteration_number);
 
-        pix_route
+        //Penalty does not guarantee that the echo is avoided, blocked segments are rerouted and the route is dropped when that fails.
+        //Swarm updates are not expansions of the search, so only the time limit and the cancellation apply to it
+        let blocked_cells = path_blocked_cells(grid, &pix_route);
+        let pix_route = if blocked_cells > 0 {
+            warn!("Best path found crosses {} blocked cell samples, rerouting its blocked segments", blocked_cells);
+            let reroute_budget = Budget { max_iterations: None, ..route_details.budget.remaining_since(started) };
+            match reroute_blocked_segments(grid, &pix_route, &BudgetTracker::new(&reroute_budget)) {
+                Some(rerouted) if path_blocked_cells(grid, &rerouted) == 0 => rerouted,
+                _ => {
+                    warn!("Blocked segments could not be rerouted, returning empty route");
+                    vec![]
+                }
+            }
+        } else {
+            pix_route
+        };
+
+        PixRoute { telemetry: recorder.map(TelemetryRecorder::finish), partial, ..PixRoute::new(pix_route) }.with_seed(seed)
     }
 }
 
@@ -301,45 +531,144 @@ impl RouteGenerator for RouteGeneratorPSO {
 #[cfg(test)]
 mod tests {
     #[test]
-    fn test_designate_points_on_line() {
-        let start_point = super::GridPosition { x: 0.0, y: 0.0 };
-        let end_point = super::GridPosition { x: 10.0, y: 10.0 };
-        let number_of_points = 5;
-
-        let points = super::designate_points_on_line(start_point, end_point, number_of_points);
-        assert_eq!(points.len(), 5);
-        assert_eq!(points[0], super::GridPosition { x: 1.0, y: 1.0 });
-        assert_eq!(points[1], super::GridPosition { x: 3.0, y: 3.0 });
-        assert_eq!(points[2], super::GridPosition { x: 5.0, y: 5.0 });
-        assert_eq!(points[3], super::GridPosition { x: 7.0, y: 7.0 });
-        assert_eq!(points[4], super::GridPosition { x: 9.0, y: 9.0 });
+    fn test_relink_keeps_path_ends() {
+        let current = [1, 2, 3, 4, 5].map(super::NodeId);
+        let guide = [1, 6, 3, 7, 5].map(super::NodeId);
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..10 {
+            let result = super::relink(&current, &guide, &mut rng);
+            assert_eq!(result.first(), Some(&super::NodeId(1)));
+            assert_eq!(result.last(), Some(&super::NodeId(5)));
+        }
+    }
+
+    #[test]
+    fn test_discrete_route_is_connected() {
+        use super::RouteGenerator;
+
+        let graph = crate::lattice::lattice_graph(6, 6);
+        let route_details = super::RouteDetails::new(crate::lattice::lattice_node(6, 0, 0), crate::lattice::lattice_node(6, 5, 5));
+
+        let route = super::RouteGeneratorPSO.generate_route(&graph, &route_details);
+        let edges = route.edges();
+        assert!(!edges.is_empty());
+        assert_eq!(edges.first().unwrap().from, route_details.starting_node);
+        assert_eq!(edges.last().unwrap().to, route_details.ending_node);
+        for pair in edges.windows(2) {
+            assert_eq!(pair[0].to, pair[1].from);
+        }
+        for edge in edges {
+            assert!(graph.edge_by_node_id().contains_key(&(edge.from, edge.to)));
+        }
+    }
+
+    #[test]
+    fn test_real_number_route_avoids_obstacle() {
+        use mgr_weather::image_wrapper::Rgba;
+        use super::RouteGenerator;
+
+        //Vertical wall of echo between route ends with free space above and below it
+        let mut grid = vec![Rgba([0, 0, 0, 0]); super::GRID_SIZE * super::GRID_SIZE];
+        for y in 30..70 {
+            grid[y * super::GRID_SIZE + 50] = Rgba([0, 200, 0, 255]);
+        }
+        let route_details = super::WxRouteDetails::new(super::GridPosition { x: 30.0, y: 50.0 }, super::GridPosition { x: 70.0, y: 50.0 }).with_seed(42);
+
+        let route = super::RouteGeneratorPSO.generate_route_real_num(&route_details, &grid);
+        assert_eq!(route.waypoints.len(), super::WAYPOINTS_COUNT + 2);
+        assert_eq!(route.waypoints.first(), Some(&route_details.starting_position));
+        assert_eq!(route.waypoints.last(), Some(&route_details.ending_position));
+        let blocked_cells: usize = route.waypoints.windows(2).map(|pair| super::segment_blocked_cells(&grid, &pair[0], &pair[1])).sum();
+        assert_eq!(blocked_cells, 0);
+    }
+
+    #[test]
+    fn test_blocked_segments_are_rerouted_or_route_dropped() {
+        use mgr_weather::image_wrapper::Rgba;
+        use super::RouteGenerator;
+
+        //Wall of echo between route ends is too long for the initial particles, the only gap is at its top
+        let mut grid = vec![Rgba([0, 0, 0, 0]); super::GRID_SIZE * super::GRID_SIZE];
+        for y in 5..super::GRID_SIZE {
+            grid[y * super::GRID_SIZE + 50] = Rgba([0, 200, 0, 255]);
+        }
+        let route_details = super::WxRouteDetails::new(super::GridPosition { x: 30.5, y: 80.5 }, super::GridPosition { x: 70.5, y: 80.5 })
+            .with_seed(11)
+            .with_max_iterations(0);
+
+        let route = super::RouteGeneratorPSO.generate_route_real_num(&route_details, &grid);
+        assert_eq!(route.waypoints.first(), Some(&route_details.starting_position));
+        assert_eq!(route.waypoints.last(), Some(&route_details.ending_position));
+        assert_eq!(super::path_blocked_cells(&grid, &route.waypoints), 0);
+        //Rerouted segment goes through the gap instead of straight through the wall
+        assert!(route.waypoints.len() > super::WAYPOINTS_COUNT + 2);
+        assert!(route.waypoints.iter().any(|waypoint| waypoint.y < 5.0));
+
+        //Without any gap the route cannot be repaired
+        for y in 0..5 {
+            grid[y * super::GRID_SIZE + 50] = Rgba([0, 200, 0, 255]);
+        }
+        let route = super::RouteGeneratorPSO.generate_route_real_num(&route_details, &grid);
+        assert!(route.waypoints.is_empty());
+    }
+
+    #[test]
+    fn test_same_seed_gives_identical_route() {
+        use super::RouteGenerator;
+
+        let graph = crate::lattice::lattice_graph(6, 6);
+        let route_details = super::RouteDetails::new(crate::lattice::lattice_node(6, 0, 0), crate::lattice::lattice_node(6, 5, 5)).with_seed(99);
+        let wx_route_details = super::WxRouteDetails::new(super::GridPosition { x: 10.0, y: 10.0 }, super::GridPosition { x: 60.0, y: 40.0 }).with_seed(99);
+        let grid = vec![mgr_weather::image_wrapper::Rgba([0, 0, 0, 0]); super::GRID_SIZE * super::GRID_SIZE];
+
+        let edge_ids = |route: &super::Route| route.edges().iter().map(|edge| (edge.from, edge.to)).collect::<Vec<_>>();
+        let first_route = super::RouteGeneratorPSO.generate_route(&graph, &route_details);
+        let second_route = super::RouteGeneratorPSO.generate_route(&graph, &route_details);
+        assert_eq!(edge_ids(&first_route), edge_ids(&second_route));
+        assert_eq!(first_route.seed, Some(99));
+
+        let first_pix_route = super::RouteGeneratorPSO.generate_route_real_num(&wx_route_details, &grid);
+        let second_pix_route = super::RouteGeneratorPSO.generate_route_real_num(&wx_route_details, &grid);
+        assert_eq!(first_pix_route.waypoints, second_pix_route.waypoints);
+        assert_eq!(first_pix_route.seed, Some(99));
     }
 
     #[test]
-    fn test_designate_points_on_line_2() {
-        let start_point = super::GridPosition { x: 0.0, y: 0.0 };
-        let end_point = super::GridPosition { x: 20.0, y: 10.0 };
-        let number_of_points = 5;
-
-        let points = super::designate_points_on_line(start_point, end_point, number_of_points);
-        assert_eq!(points.len(), 5);
-        assert_eq!(points[0], super::GridPosition { x: 2.0, y: 1.0 });
-        assert_eq!(points[1], super::GridPosition { x: 6.0, y: 3.0 });
-        assert_eq!(points[2], super::GridPosition { x: 10.0, y: 5.0 });
-        assert_eq!(points[3], super::GridPosition { x: 14.0, y: 7.0 });
-        assert_eq!(points[4], super::GridPosition { x: 18.0, y: 9.0 });
+    fn test_budget_exhausted_before_first_iteration() {
+        use super::RouteGenerator;
+
+        let graph = crate::lattice::lattice_graph(6, 6);
+        let route_details = super::RouteDetails::new(crate::lattice::lattice_node(6, 0, 0), crate::lattice::lattice_node(6, 5, 5)).with_max_iterations(0);
+        let wx_route_details = super::WxRouteDetails::new(super::GridPosition { x: 10.0, y: 10.0 }, super::GridPosition { x: 60.0, y: 40.0 }).with_max_iterations(0);
+        let grid = vec![mgr_weather::image_wrapper::Rgba([0, 0, 0, 0]); super::GRID_SIZE * super::GRID_SIZE];
+
+        //No random walk is started once the budget is exhausted
+        let route = super::RouteGeneratorPSO.generate_route(&graph, &route_details);
+        assert!(route.partial);
+        assert!(route.edges().is_empty());
+
+        //Initial particles are evaluated, so the best of them is returned instead of the straight line between route ends
+        let pix_route = super::RouteGeneratorPSO.generate_route_real_num(&wx_route_details, &grid);
+        assert!(pix_route.partial);
+        assert_eq!(pix_route.waypoints.len(), super::WAYPOINTS_COUNT + 2);
     }
 
     #[test]
-    fn test_designate_points_on_line_short_route() {
-        let start_point = super::GridPosition { x: 0.0, y: 0.0 };
-        let end_point = super::GridPosition { x: 3.0, y: -1.0 };
-        let number_of_points = 5;
-
-        let points = super::designate_points_on_line(start_point, end_point, number_of_points);
-        assert_eq!(points.len(), 3);
-        assert_eq!(points[0], super::GridPosition { x: 0.0, y: -1.0 });
-        assert_eq!(points[1], super::GridPosition { x: 1.0, y: -1.0 });
-        assert_eq!(points[2], super::GridPosition { x: 2.0, y: -1.0 });
+    fn test_telemetry_tracks_convergence() {
+        use super::RouteGenerator;
+
+        let wx_route_details = super::WxRouteDetails::new(super::GridPosition { x: 10.0, y: 10.0 }, super::GridPosition { x: 60.0, y: 40.0 })
+            .with_seed(3)
+            .with_telemetry();
+        let grid = vec![mgr_weather::image_wrapper::Rgba([0, 0, 0, 0]); super::GRID_SIZE * super::GRID_SIZE];
+
+        let records = super::RouteGeneratorPSO.generate_route_real_num(&wx_route_details, &grid).telemetry.unwrap().records;
+
+        assert!(!records.is_empty());
+        assert!(records.windows(2).all(|pair| pair[1].best_cost <= pair[0].best_cost));
+        assert!(records.iter().all(|record| record.mean_cost >= record.best_cost && record.swarm_diversity.is_some()));
+        //Particles gather around the best route while the swarm converges
+        assert!(records.last().unwrap().swarm_diversity < records[0].swarm_diversity);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock};
+
+use crate::RouteGenerator;
+use crate::providers::aco::RouteGeneratorACO;
+use crate::providers::annealing::RouteGeneratorSA;
+use crate::providers::astar::{RouteGeneratorAStar, RouteGeneratorThetaStar};
+use crate::providers::genetic::RouteGeneratorGA;
+use crate::providers::greedy::RouteGeneratorGreedy;
+use crate::providers::pso::RouteGeneratorPSO;
+
+/// Providers keyed by their names. Built-in providers are registered by `new`, third-party ones are added with `register`
+#[derive(Clone)]
+pub struct ProviderRegistry {
+    providers: BTreeMap<String, Arc<dyn RouteGenerator>>,
+}
+
+impl ProviderRegistry {
+    pub fn new() -> Self {
+        let mut registry = ProviderRegistry::empty();
+        registry.register(RouteGeneratorGreedy);
+        registry.register(RouteGeneratorACO);
+        registry.register(RouteGeneratorPSO);
+        registry.register(RouteGeneratorAStar);
+        registry.register(RouteGeneratorThetaStar);
+        registry.register(RouteGeneratorGA);
+        registry.register(RouteGeneratorSA);
+        registry
+    }
+
+    pub fn empty() -> Self {
+        ProviderRegistry {
+            providers: BTreeMap::new(),
+        }
+    }
+
+    /// Provider registered earlier under the same name is replaced and returned
+    pub fn register(&mut self, provider: impl RouteGenerator + 'static) -> Option<Arc<dyn RouteGenerator>> {
+        self.providers.insert(provider.name().to_string(), Arc::new(provider))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn RouteGenerator> {
+        self.providers.get(name).map(Arc::as_ref)
+    }
+
+    /// Names of the registered providers in alphabetical order
+    pub fn names(&self) -> impl Iterator<Item=&str> {
+        self.providers.keys().map(String::as_str)
+    }
+}
+
+impl Default for ProviderRegistry {
+    fn default() -> Self {
+        ProviderRegistry::new()
+    }
+}
+
+/// Built-in providers, shared by all generations
+pub(crate) fn builtin_providers() -> &'static ProviderRegistry {
+    static BUILTIN_PROVIDERS: OnceLock<ProviderRegistry> = OnceLock::new();
+    BUILTIN_PROVIDERS.get_or_init(ProviderRegistry::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use mgr_map_extractor::graph::Graph;
+
+    use crate::{generate_route_with, Route, RouteDetails, RouteGeneratorStrategy};
+    use crate::lattice::{lattice_graph, lattice_node};
+    use crate::providers::greedy::RouteGeneratorGreedy;
+
+    use super::*;
+
+    /// Third-party provider wrapping the greedy one
+    struct CustomProvider;
+
+    impl RouteGenerator for CustomProvider {
+        fn name(&self) -> &str {
+            "Custom"
+        }
+
+        fn generate_route(&self, graph: &Graph, route_details: &RouteDetails) -> Route {
+            RouteGeneratorGreedy.generate_route(graph, route_details)
+        }
+    }
+
+    #[test]
+    fn test_strategies_resolve_to_builtin_providers() {
+        let registry = ProviderRegistry::new();
+        for strategy in [
+            RouteGeneratorStrategy::Greedy,
+            RouteGeneratorStrategy::ACO,
+            RouteGeneratorStrategy::PSO,
+            RouteGeneratorStrategy::AStar,
+            RouteGeneratorStrategy::ThetaStar,
+            RouteGeneratorStrategy::GA,
+            RouteGeneratorStrategy::SA,
+        ] {
+            let name = strategy.provider_name().unwrap();
+            assert_eq!(registry.get(name).unwrap().name(), name);
+            assert_eq!(strategy.provider().unwrap().name(), name);
+        }
+        assert!(RouteGeneratorStrategy::Empty.provider().is_none());
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["ACO", "AStar", "GA", "Greedy", "PSO", "SA", "ThetaStar"]);
+    }
+
+    #[test]
+    fn test_custom_provider_is_registered_and_used() {
+        let mut registry = ProviderRegistry::new();
+        assert!(registry.get("Custom").is_none());
+        assert!(registry.register(CustomProvider).is_none());
+        assert!(registry.register(CustomProvider).is_some());
+
+        let graph = lattice_graph(5, 5);
+        let route_details = RouteDetails::new(lattice_node(5, 0, 0), lattice_node(5, 4, 4))
+            .with_waypoints(vec![lattice_node(5, 4, 0)]);
+        let route = generate_route_with(&graph, registry.get("Custom").unwrap(), &route_details);
+        assert_eq!(route.legs.len(), 2);
+        assert_eq!(route.edges().first().unwrap().from, route_details.starting_node);
+        assert_eq!(route.edges().last().unwrap().to, route_details.ending_node);
+        assert!(ProviderRegistry::empty().names().next().is_none());
+    }
+}
@@ -0,0 +1,326 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use log::{debug, warn};
+use osmpbfreader::NodeId;
+
+use mgr_map_extractor::graph::Graph;
+
+use crate::{Route, RouteDetails};
+use crate::cost::CostEvaluator;
+use crate::path::path_to_route;
+
+/// New weighted cost of the edge in the given direction, None when the edge cannot be used anymore.
+/// Cost should not drop below the lower bound of the request's cost model, otherwise the heuristic overestimates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EdgeCostUpdate {
+    pub from: NodeId,
+    pub to: NodeId,
+    pub cost: Option<f32>,
+}
+
+/// Key of the node in the priority queue, compared lexicographically
+type Key = (f32, f32);
+
+/// Entry of the priority queue, ordered so the one with the lowest key is popped first from the max-heap
+struct QueueEntry {
+    key: Key,
+    node: NodeId,
+}
+
+/// Incremental planner (D* Lite). Search goes backwards from the ending node, so when the drone moves
+/// and some edge costs change only the affected part of the search is repeated
+pub struct RoutePlanner<'a> {
+    graph: &'a Graph,
+    route_details: &'a RouteDetails,
+    //Built once for the request, evaluates edges without overrides and the heuristic
+    cost_evaluator: CostEvaluator<'a>,
+    predecessors: HashMap<NodeId, Vec<NodeId>>,
+    cost_overrides: HashMap<(NodeId, NodeId), Option<f32>>,
+    g: HashMap<NodeId, f32>,
+    rhs: HashMap<NodeId, f32>,
+    queue: BinaryHeap<QueueEntry>,
+    //Current key of every node in the queue, entries with a different key are stale and skipped
+    queued: HashMap<NodeId, Key>,
+    //Heuristic offset accumulated while the drone moves
+    key_modifier: f32,
+    current_node: NodeId,
+    //Time at which the drone leaves the current node, edge costs of the search stay evaluated at the initial departure
+    departure_time: f32,
+    //Number of nodes expanded by the last plan or replan
+    expansions: usize,
+}
+
+impl PartialEq for QueueEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for QueueEntry {}
+
+impl PartialOrd for QueueEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueueEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key.partial_cmp(&self.key).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl<'a> RoutePlanner<'a> {
+    /// Plan the initial route from the starting to the ending node of the request
+    pub fn new(graph: &'a Graph, route_details: &'a RouteDetails) -> Self {
+        let mut predecessors: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+        for (&from, connections) in graph.edge_connections().iter() {
+            for &to in connections {
+                predecessors.entry(to).or_default().push(from);
+            }
+        }
+
+        let mut planner = RoutePlanner {
+            graph,
+            route_details,
+            cost_evaluator: CostEvaluator::new(route_details),
+            predecessors,
+            cost_overrides: HashMap::new(),
+            g: HashMap::new(),
+            rhs: HashMap::from([(route_details.ending_node, 0.0)]),
+            queue: BinaryHeap::new(),
+            queued: HashMap::new(),
+            key_modifier: 0.0,
+            current_node: route_details.starting_node,
+            departure_time: route_details.departure_time,
+            expansions: 0,
+        };
+        let ending_node = route_details.ending_node;
+        let key = planner.calculate_key(ending_node);
+        planner.enqueue(ending_node, key);
+        planner.compute_shortest_path();
+        planner
+    }
+
+    /// Route from the current position of the drone to the ending node
+    pub fn route(&self) -> Route {
+        let Some(path) = self.best_path() else {
+            warn!("Ending node is not reachable from {:?}", self.current_node);
+            return Route::default();
+        };
+        let route_details = RouteDetails {
+            starting_node: self.current_node,
+            departure_time: self.departure_time,
+            ..self.route_details.clone()
+        };
+        path_to_route(self.graph, &path, &CostEvaluator::new(&route_details).with_overrides(&self.cost_overrides))
+    }
+
+    /// Repair the route after the drone has flown `progress` edges of the `route` and costs of some edges have changed.
+    /// Returned route starts where the drone currently is
+    pub fn replan(&mut self, route: &Route, progress: usize, updates: &[EdgeCostUpdate]) -> Route {
+        let current_node = match progress.min(route.edges.len()) {
+            0 => self.current_node,
+            flown => route.edges[flown - 1].to,
+        };
+        if let Some(&time) = route.node_times.get(progress) {
+            self.departure_time = time;
+        }
+
+        self.key_modifier += self.heuristic(self.current_node, current_node);
+        self.current_node = current_node;
+
+        for update in updates {
+            self.cost_overrides.insert((update.from, update.to), update.cost);
+            self.update_vertex(update.from);
+        }
+
+        self.expansions = 0;
+        self.compute_shortest_path();
+        debug!("Route repaired after {} expansions", self.expansions);
+        self.route()
+    }
+
+    pub fn expansions(&self) -> usize {
+        self.expansions
+    }
+
+    fn edge_cost(&self, from: NodeId, to: NodeId) -> f32 {
+        match self.cost_overrides.get(&(from, to)) {
+            Some(cost) => cost.unwrap_or(f32::INFINITY),
+            None => self.cost_evaluator.edge_cost(self.graph, from, to),
+        }
+    }
+
+    fn heuristic(&self, from: NodeId, to: NodeId) -> f32 {
+        self.cost_evaluator.estimate(self.graph, from, to)
+    }
+
+    fn g(&self, node: NodeId) -> f32 {
+        *self.g.get(&node).unwrap_or(&f32::INFINITY)
+    }
+
+    fn rhs(&self, node: NodeId) -> f32 {
+        *self.rhs.get(&node).unwrap_or(&f32::INFINITY)
+    }
+
+    fn calculate_key(&self, node: NodeId) -> Key {
+        let best = self.g(node).min(self.rhs(node));
+        (best + self.heuristic(self.current_node, node) + self.key_modifier, best)
+    }
+
+    fn enqueue(&mut self, node: NodeId, key: Key) {
+        self.queued.insert(node, key);
+        self.queue.push(QueueEntry { key, node });
+    }
+
+    /// Lowest valid entry of the queue, stale entries are dropped on the way
+    fn top(&mut self) -> Option<(Key, NodeId)> {
+        while let Some(entry) = self.queue.peek() {
+            if self.queued.get(&entry.node) == Some(&entry.key) {
+                return Some((entry.key, entry.node));
+            }
+            self.queue.pop();
+        }
+        None
+    }
+
+    fn successors_cost(&self, node: NodeId) -> f32 {
+        self.graph.edge_connections().get(&node).into_iter().flatten()
+            .map(|&next| self.edge_cost(node, next) + self.g(next))
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    fn update_vertex(&mut self, node: NodeId) {
+        if node != self.route_details.ending_node {
+            let rhs = self.successors_cost(node);
+            self.rhs.insert(node, rhs);
+        }
+        self.queued.remove(&node);
+        if self.g(node) != self.rhs(node) {
+            let key = self.calculate_key(node);
+            self.enqueue(node, key);
+        }
+    }
+
+    fn compute_shortest_path(&mut self) {
+        while let Some((key, node)) = self.top() {
+            let start_key = self.calculate_key(self.current_node);
+            if key >= start_key && self.rhs(self.current_node) == self.g(self.current_node) {
+                break;
+            }
+            self.expansions += 1;
+
+            let new_key = self.calculate_key(node);
+            if key < new_key {
+                self.enqueue(node, new_key);
+                continue;
+            }
+
+            self.queued.remove(&node);
+            let predecessors = self.predecessors.get(&node).cloned().unwrap_or_default();
+            if self.g(node) > self.rhs(node) {
+                self.g.insert(node, self.rhs(node));
+            } else {
+                self.g.insert(node, f32::INFINITY);
+                self.update_vertex(node);
+            }
+            for predecessor in predecessors {
+                self.update_vertex(predecessor);
+            }
+        }
+    }
+
+    /// Follow the cheapest successors from the current node down to the ending node
+    fn best_path(&self) -> Option<Vec<NodeId>> {
+        let mut path = vec![self.current_node];
+        let mut current = self.current_node;
+        while current != self.route_details.ending_node {
+            if self.g(current).is_infinite() || path.len() > self.g.len() {
+                return None;
+            }
+            current = *self.graph.edge_connections().get(&current)?.iter()
+                .min_by(|&&a, &&b| {
+                    let a_cost = self.edge_cost(current, a) + self.g(a);
+                    let b_cost = self.edge_cost(current, b) + self.g(b);
+                    a_cost.partial_cmp(&b_cost).unwrap_or(Ordering::Equal)
+                })?;
+            path.push(current);
+        }
+        Some(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cost::CostEvaluator;
+    use crate::lattice::{lattice_graph, lattice_node};
+    use crate::providers::astar::shortest_path;
+
+    use super::*;
+
+    #[test]
+    fn test_initial_route_is_shortest() {
+        let graph = lattice_graph(5, 4);
+        let route_details = RouteDetails::new(lattice_node(5, 0, 0), lattice_node(5, 4, 3));
+
+        let route = RoutePlanner::new(&graph, &route_details).route();
+
+        assert_eq!(route.edges.len(), 7);
+        assert_eq!(route.edges.last().map(|edge| edge.to), Some(lattice_node(5, 4, 3)));
+    }
+
+    #[test]
+    fn test_replan_avoids_blocked_edge() {
+        let graph = lattice_graph(5, 4);
+        let route_details = RouteDetails::new(lattice_node(5, 0, 0), lattice_node(5, 4, 3));
+        let mut planner = RoutePlanner::new(&graph, &route_details);
+        let initial_expansions = planner.expansions();
+        let route = planner.route();
+
+        //Drone has flown the first edge, the next one on its way gets closed in both directions
+        let blocked = route.edges[1];
+        let updates = [
+            EdgeCostUpdate { from: blocked.from, to: blocked.to, cost: None },
+            EdgeCostUpdate { from: blocked.to, to: blocked.from, cost: None },
+        ];
+        let repaired = planner.replan(&route, 1, &updates);
+
+        assert_eq!(repaired.edges.first().map(|edge| edge.from), Some(route.edges[0].to));
+        assert_eq!(repaired.edges.last().map(|edge| edge.to), Some(lattice_node(5, 4, 3)));
+        assert!(!repaired.edges.contains(&blocked));
+        //Lattice has many equally short detours, so the repaired route is still as short as possible
+        assert_eq!(repaired.edges.len(), 6);
+        assert!(planner.expansions() < initial_expansions);
+    }
+
+    #[test]
+    fn test_replan_when_target_becomes_unreachable() {
+        let graph = lattice_graph(3, 1);
+        let route_details = RouteDetails::new(lattice_node(3, 0, 0), lattice_node(3, 2, 0));
+        let mut planner = RoutePlanner::new(&graph, &route_details);
+        let route = planner.route();
+
+        let updates = [EdgeCostUpdate { from: lattice_node(3, 1, 0), to: lattice_node(3, 2, 0), cost: None }];
+        assert!(planner.replan(&route, 1, &updates).edges.is_empty());
+
+        let reopened = [EdgeCostUpdate { from: lattice_node(3, 1, 0), to: lattice_node(3, 2, 0), cost: Some(500.0) }];
+        let repaired = planner.replan(&route, 1, &reopened);
+        let expected = shortest_path(&graph, lattice_node(3, 1, 0), lattice_node(3, 2, 0), &CostEvaluator::new(&route_details)).unwrap();
+        assert_eq!(repaired.edges.len(), expected.len() - 1);
+        //Reopened edge is costed with its new cost, not the one of the cost model
+        assert!((repaired.cost.weighted(&route_details.cost_weights) - 500.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_replan_empty_route_keeps_position() {
+        let graph = lattice_graph(3, 1);
+        let route_details = RouteDetails::new(lattice_node(3, 0, 0), lattice_node(3, 2, 0));
+        let mut planner = RoutePlanner::new(&graph, &route_details);
+
+        let repaired = planner.replan(&Route::default(), 2, &[]);
+        assert_eq!(repaired.edges.first().map(|edge| edge.from), Some(lattice_node(3, 0, 0)));
+        assert_eq!(repaired.edges.len(), 2);
+    }
+}
@@ -0,0 +1,19 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+//Odd constant spreading stream numbers over the whole seed space
+const STREAM_MULTIPLIER: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Seed given in the request or a random one, so every run can be repeated afterwards
+pub(crate) fn resolve_seed(seed: Option<u64>) -> u64 {
+    seed.unwrap_or_else(rand::random)
+}
+
+pub(crate) fn seeded_rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+/// Independent generator for a single actor of the simulation (ant, particle), derived from the run seed
+pub(crate) fn derived_rng(seed: u64, stream: u64) -> StdRng {
+    StdRng::seed_from_u64(seed.wrapping_add(stream.wrapping_add(1).wrapping_mul(STREAM_MULTIPLIER)))
+}
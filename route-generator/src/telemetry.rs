@@ -0,0 +1,163 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+/// State of the metaheuristic after a single iteration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IterationRecord {
+    pub iteration: u32,
+    //Cost of the best route found so far, infinite until any route reaches the target
+    pub best_cost: f32,
+    //Mean cost of the routes of this iteration that reached the target
+    pub mean_cost: f32,
+    //Normalised Shannon entropy of the pheromone levels, from 0.0 (single trail) to 1.0 (uniform), ACO only
+    pub pheromone_entropy: Option<f32>,
+    //Mean distance of the particles from the swarm centre or the global best, PSO only
+    pub swarm_diversity: Option<f32>,
+    //Seconds since the start of the generation
+    pub elapsed: f32,
+}
+
+/// Per-iteration trace of the provider, for plotting convergence curves
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Telemetry {
+    pub records: Vec<IterationRecord>,
+}
+
+const CSV_HEADER: &str = "iteration,best_cost,mean_cost,pheromone_entropy,swarm_diversity,elapsed";
+
+impl Telemetry {
+    pub fn to_csv(&self) -> String {
+        let mut csv = format!("{}\n", CSV_HEADER);
+        for record in &self.records {
+            let _ = writeln!(csv, "{},{},{},{},{},{}",
+                             record.iteration,
+                             csv_value(Some(record.best_cost)),
+                             csv_value(Some(record.mean_cost)),
+                             csv_value(record.pheromone_entropy),
+                             csv_value(record.swarm_diversity),
+                             record.elapsed);
+        }
+        csv
+    }
+
+    /// Records of the following part of the generation, like the next route leg, continue the iteration numbers.
+    /// `offset` is the number of seconds between the start of the generation and the start of that part
+    pub(crate) fn append(&mut self, part: Telemetry, offset: f32) {
+        for record in part.records {
+            self.records.push(IterationRecord {
+                iteration: self.records.len() as u32,
+                elapsed: record.elapsed + offset,
+                ..record
+            });
+        }
+    }
+
+    /// Array of records, missing and infinite values are written as null
+    pub fn to_json(&self) -> String {
+        let records: Vec<String> = self.records.iter().map(|record| {
+            format!("{{\"iteration\":{},\"best_cost\":{},\"mean_cost\":{},\"pheromone_entropy\":{},\"swarm_diversity\":{},\"elapsed\":{}}}",
+                    record.iteration,
+                    json_value(Some(record.best_cost)),
+                    json_value(Some(record.mean_cost)),
+                    json_value(record.pheromone_entropy),
+                    json_value(record.swarm_diversity),
+                    record.elapsed)
+        }).collect();
+        format!("[{}]", records.join(","))
+    }
+
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_csv())
+    }
+
+    pub fn write_json(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+}
+
+fn csv_value(value: Option<f32>) -> String {
+    value.filter(|value| value.is_finite()).map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn json_value(value: Option<f32>) -> String {
+    value.filter(|value| value.is_finite()).map(|value| value.to_string()).unwrap_or("null".to_string())
+}
+
+/// Collects records while the provider runs, only when the request asks for telemetry
+pub(crate) struct TelemetryRecorder {
+    started: Instant,
+    records: Vec<IterationRecord>,
+}
+
+impl TelemetryRecorder {
+    pub(crate) fn new() -> Self {
+        TelemetryRecorder {
+            started: Instant::now(),
+            records: vec![],
+        }
+    }
+
+    pub(crate) fn record(&mut self, best_cost: f32, mean_cost: f32, pheromone_entropy: Option<f32>, swarm_diversity: Option<f32>) {
+        self.records.push(IterationRecord {
+            iteration: self.records.len() as u32,
+            best_cost,
+            mean_cost,
+            pheromone_entropy,
+            swarm_diversity,
+            elapsed: self.started.elapsed().as_secs_f32(),
+        });
+    }
+
+    pub(crate) fn finish(self) -> Telemetry {
+        Telemetry { records: self.records }
+    }
+}
+
+/// Mean of the values, NaN when there are none
+pub(crate) fn mean(values: impl Iterator<Item=f32>) -> f32 {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+    sum / count as f32
+}
+
+/// Shannon entropy of the levels divided by its maximum, so it does not depend on the number of levels
+pub(crate) fn normalised_entropy(levels: impl Iterator<Item=f32>) -> f32 {
+    let levels: Vec<f32> = levels.filter(|&level| level > 0.0).collect();
+    let total: f32 = levels.iter().sum();
+    if levels.len() < 2 || total <= 0.0 {
+        return 0.0;
+    }
+    let entropy: f32 = levels.iter()
+        .map(|level| level / total)
+        .map(|probability| -probability * probability.ln())
+        .sum();
+    entropy / (levels.len() as f32).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_formats() {
+        let telemetry = Telemetry {
+            records: vec![
+                IterationRecord { iteration: 0, best_cost: f32::INFINITY, mean_cost: f32::NAN, pheromone_entropy: Some(1.0), swarm_diversity: None, elapsed: 0.5 },
+                IterationRecord { iteration: 1, best_cost: 12.5, mean_cost: 20.0, pheromone_entropy: Some(0.75), swarm_diversity: None, elapsed: 1.0 },
+            ],
+        };
+
+        assert_eq!(telemetry.to_csv(), format!("{}\n0,,,1,,0.5\n1,12.5,20,0.75,,1\n", CSV_HEADER));
+        assert_eq!(telemetry.to_json(), "[{\"iteration\":0,\"best_cost\":null,\"mean_cost\":null,\"pheromone_entropy\":1,\"swarm_diversity\":null,\"elapsed\":0.5},\
+{\"iteration\":1,\"best_cost\":12.5,\"mean_cost\":20,\"pheromone_entropy\":0.75,\"swarm_diversity\":null,\"elapsed\":1}]");
+    }
+
+    #[test]
+    fn test_normalised_entropy() {
+        assert!((normalised_entropy([5.0, 5.0, 5.0].into_iter()) - 1.0).abs() < 0.0001);
+        assert!(normalised_entropy([100.0, 0.001, 0.001].into_iter()) < 0.01);
+        assert_eq!(normalised_entropy(std::iter::empty()), 0.0);
+    }
+}
@@ -0,0 +1,386 @@
+use std::time::Instant;
+
+use log::{info, warn};
+use osmpbfreader::NodeId;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+
+use mgr_map_extractor::graph::Graph;
+
+use crate::{Route, RouteDetails, RouteLeg};
+use crate::budget::BudgetTracker;
+use crate::cost::CostEvaluator;
+use crate::providers::aco::{ALPHA_COEFF, BETA_COEFF, PH_EVAPORATION_RATE};
+use crate::providers::astar::{graph_path, SearchOutcome};
+use crate::rng::{resolve_seed, seeded_rng};
+use crate::telemetry::Telemetry;
+
+pub const TOUR_SIMULATION_COUNT: i32 = 100;
+
+pub const TOUR_ANT_COUNT: i32 = 40;
+
+//Pheromone levels of the tour are relative to the best tour cost, so they stay around 1.0 regardless of the units
+const TOUR_PH_INITIAL_LEVEL: f32 = 1.0;
+
+/// Matrix of costs between every pair of tour stops, None when the stop cannot be reached
+type CostMatrix = Vec<Vec<Option<f32>>>;
+
+/// Generate the route leg by leg, going through the waypoints of the request in the given order.
+/// Every leg gets the same seed, so the whole route can be generated again with the seed recorded in it
+pub(crate) fn generate_through_waypoints(route_details: &RouteDetails, generate_leg: &dyn Fn(&RouteDetails) -> Route) -> Route {
+    let seed = resolve_seed(route_details.seed);
+    let mut stops = vec![route_details.starting_node];
+    stops.extend_from_slice(&route_details.waypoints);
+    stops.push(route_details.ending_node);
+
+    let started = Instant::now();
+    let mut route = Route::default().with_seed(seed);
+    route.departure_time = route_details.departure_time;
+    for pair in stops.windows(2) {
+        //Next leg starts when the previous one has finished, with the time left by the previous legs
+        let leg_details = RouteDetails {
+            starting_node: pair[0],
+            ending_node: pair[1],
+            waypoints: vec![],
+            departure_time: route.arrival_time().unwrap_or(route_details.departure_time),
+            budget: route_details.budget.remaining_since(started),
+            ..route_details.clone()
+        }.with_seed(seed);
+
+        let leg_started = started.elapsed().as_secs_f32();
+        let leg_route = generate_leg(&leg_details);
+        if leg_route.edges.is_empty() && pair[0] != pair[1] && !leg_route.partial {
+            warn!("Cannot generate route leg from {:?} to {:?}", pair[0], pair[1]);
+            return Route::default().with_seed(seed);
+        }
+
+        route.legs.push(RouteLeg {
+            from: pair[0],
+            to: pair[1],
+            edge_count: leg_route.edges.len(),
+            cost: leg_route.cost,
+        });
+        route.cost += leg_route.cost;
+        route.edges.extend(leg_route.edges);
+        //First node of the leg is the last node of the previous one
+        let skipped_times = if route.node_times.is_empty() { 0 } else { 1 };
+        route.node_times.extend(leg_route.node_times.into_iter().skip(skipped_times));
+        if let Some(leg_telemetry) = leg_route.telemetry {
+            route.telemetry.get_or_insert_with(Telemetry::default).append(leg_telemetry, leg_started);
+        }
+        //Leg interrupted by the budget may not reach its waypoint, so the following legs would not be connected
+        if leg_route.partial {
+            warn!("Budget exhausted on the leg from {:?} to {:?}", pair[0], pair[1]);
+            route.partial = true;
+            break;
+        }
+    }
+    route
+}
+
+/// Weighted costs of the cheapest paths between every pair of stops, with the flag telling whether the budget ran out.
+/// Pairs whose search was interrupted are left without the cost
+fn cost_matrix(graph: &Graph, stops: &[NodeId], cost_evaluator: &CostEvaluator, budget: &BudgetTracker) -> (CostMatrix, bool) {
+    let mut partial = false;
+    let costs = stops.iter().map(|&from| {
+        stops.iter().map(|&to| {
+            if from == to {
+                return Some(0.0);
+            }
+            match graph_path(graph, from, to, cost_evaluator, budget) {
+                SearchOutcome::Found(path) => Some(cost_evaluator.weighted(&cost_evaluator.path_cost(graph, &path))),
+                SearchOutcome::Interrupted(_) => {
+                    partial = true;
+                    None
+                }
+                SearchOutcome::Unreachable => None,
+            }
+        }).collect()
+    }).collect();
+    if partial {
+        warn!("Budget exhausted while costing the paths between the stops");
+    }
+    (costs, partial)
+}
+
+fn tour_cost(costs: &CostMatrix, tour: &[usize]) -> Option<f32> {
+    tour.windows(2).map(|pair| costs[pair[0]][pair[1]]).sum()
+}
+
+/// Single ant of the tour colony. Starts at the first stop, visits every inner stop and finishes at the last one
+fn walk_tour(costs: &CostMatrix, ph_levels: &[Vec<f32>], rng: &mut StdRng) -> Option<Vec<usize>> {
+    let last_stop = costs.len() - 1;
+    let mut tour = vec![0];
+    let mut unvisited: Vec<usize> = (1..last_stop).collect();
+
+    while !unvisited.is_empty() {
+        let current = *tour.last().unwrap();
+        let candidates: Vec<(usize, f32)> = unvisited.iter()
+            .filter_map(|&next| costs[current][next].map(|cost| {
+                let attraction = ph_levels[current][next].powf(ALPHA_COEFF);
+                let visibility = (1.0 / cost.max(0.001)).powf(BETA_COEFF);
+                (next, attraction * visibility)
+            }))
+            .collect();
+
+        let distribution = WeightedIndex::new(candidates.iter().map(|(_, weight)| *weight)).ok()?;
+        let next = candidates[distribution.sample(rng)].0;
+        unvisited.retain(|&stop| stop != next);
+        tour.push(next);
+    }
+
+    tour.push(last_stop);
+    tour_cost(costs, &tour).map(|_| tour)
+}
+
+/// Order of the stops with the cheapest tour, found with the ant colony.
+/// First and last stop stay in place, inner ones are reordered
+fn order_stops(costs: &CostMatrix, seed: u64) -> Option<Vec<usize>> {
+    let stops_count = costs.len();
+    if stops_count <= 3 {
+        let tour: Vec<usize> = (0..stops_count).collect();
+        return tour_cost(costs, &tour).map(|_| tour);
+    }
+
+    let mut rng = seeded_rng(seed);
+    let mut ph_levels = vec![vec![TOUR_PH_INITIAL_LEVEL; stops_count]; stops_count];
+    let mut best: Option<(Vec<usize>, f32)> = None;
+
+    for _simulation_number in 0..TOUR_SIMULATION_COUNT {
+        let tours: Vec<(Vec<usize>, f32)> = (0..TOUR_ANT_COUNT)
+            .filter_map(|_| walk_tour(costs, &ph_levels, &mut rng))
+            .filter_map(|tour| tour_cost(costs, &tour).map(|cost| (tour, cost)))
+            .collect();
+
+        for (tour, cost) in tours.iter() {
+            if best.as_ref().map_or(true, |(_, best_cost)| cost < best_cost) {
+                best = Some((tour.clone(), *cost));
+            }
+        }
+        let Some((best_tour, best_cost)) = best.as_ref() else {
+            continue;
+        };
+
+        //Evaporate pheromone levels
+        ph_levels.iter_mut().flatten().for_each(|ph_level| *ph_level *= 1.0 - PH_EVAPORATION_RATE);
+
+        //Every tour deposits pheromone relative to the best one, best tour gets it twice
+        for (tour, cost) in tours.iter().chain([(best_tour.clone(), *best_cost)].iter()) {
+            let deposit = best_cost.max(0.001) / cost.max(0.001);
+            for pair in tour.windows(2) {
+                ph_levels[pair[0]][pair[1]] += deposit;
+            }
+        }
+    }
+
+    best.map(|(tour, cost)| {
+        info!("Best delivery tour found with cost {}", cost);
+        tour
+    })
+}
+
+/// All the stops of the delivery - starting node, delivery points and ending node
+fn delivery_stops(route_details: &RouteDetails, deliveries: &[NodeId]) -> Vec<NodeId> {
+    let mut stops = vec![route_details.starting_node];
+    stops.extend_from_slice(deliveries);
+    stops.push(route_details.ending_node);
+    stops
+}
+
+/// Find the cheapest order of visiting the delivery points (travelling salesman problem) and generate the route through them.
+/// `generate_route` gets the request with the delivery points as its waypoints, in the chosen order
+pub(crate) fn generate_delivery_tour(graph: &Graph, route_details: &RouteDetails, deliveries: &[NodeId], generate_route: &dyn Fn(&RouteDetails) -> Route) -> Route {
+    let started = Instant::now();
+    let seed = resolve_seed(route_details.seed);
+    let stops = delivery_stops(route_details, deliveries);
+    let (costs, partial) = cost_matrix(graph, &stops, &CostEvaluator::new(route_details), &BudgetTracker::new(&route_details.budget));
+
+    match order_stops(&costs, seed) {
+        Some(tour) => {
+            let ordered_details = RouteDetails {
+                waypoints: tour[1..tour.len() - 1].iter().map(|&stop| stops[stop]).collect(),
+                budget: route_details.budget.remaining_since(started),
+                ..route_details.clone()
+            }.with_seed(seed);
+            let route = generate_route(&ordered_details);
+            Route { partial: route.partial || partial, ..route }
+        }
+        None => {
+            warn!("Delivery points cannot be connected into a single tour");
+            Route { partial, ..Route::default() }.with_seed(seed)
+        }
+    }
+}
+
+/// Split the ordered delivery points between at most `drones_count` drones (vehicle routing problem).
+/// Delivery points are ordered into a single tour first, then it is cut into consecutive parts so the most expensive one is as cheap as possible.
+/// Every part is flown from the starting node to the ending node
+pub(crate) fn generate_delivery_routes(graph: &Graph, route_details: &RouteDetails, deliveries: &[NodeId], drones_count: usize, generate_route: &dyn Fn(&RouteDetails) -> Route) -> Vec<Route> {
+    let started = Instant::now();
+    let seed = resolve_seed(route_details.seed);
+    let stops = delivery_stops(route_details, deliveries);
+    let (costs, partial) = cost_matrix(graph, &stops, &CostEvaluator::new(route_details), &BudgetTracker::new(&route_details.budget));
+
+    let Some(tour) = order_stops(&costs, seed) else {
+        warn!("Delivery points cannot be connected into a single tour");
+        return vec![];
+    };
+    let inner_stops = &tour[1..tour.len() - 1];
+    let last_stop = stops.len() - 1;
+
+    split_tour(&costs, inner_stops, last_stop, drones_count.max(1)).into_iter()
+        .map(|part| {
+            let part_details = RouteDetails {
+                waypoints: part.iter().map(|&stop| stops[stop]).collect(),
+                budget: route_details.budget.remaining_since(started),
+                ..route_details.clone()
+            }.with_seed(seed);
+            let route = generate_route(&part_details);
+            Route { partial: route.partial || partial, ..route }
+        })
+        .collect()
+}
+
+/// Cut the ordered stops into at most `parts_count` consecutive parts, minimising the cost of the most expensive one.
+/// Every part is a separate tour from the first stop (0) to the `last_stop`
+fn split_tour(costs: &CostMatrix, stops: &[usize], last_stop: usize, parts_count: usize) -> Vec<Vec<usize>> {
+    let part_cost = |from: usize, to: usize| -> f32 {
+        let mut tour = vec![0];
+        tour.extend_from_slice(&stops[from..to]);
+        tour.push(last_stop);
+        tour_cost(costs, &tour).unwrap_or(f32::MAX)
+    };
+
+    //best[k][i] - the most expensive part when first i stops are split into k parts, together with the start of the last part
+    let stops_count = stops.len();
+    let mut best = vec![vec![(f32::MAX, 0); stops_count + 1]; parts_count + 1];
+    best[0][0] = (0.0, 0);
+    for parts in 1..=parts_count {
+        for end in 0..=stops_count {
+            best[parts][end] = best[parts - 1][end];
+            for start in 0..end {
+                let (previous_cost, _) = best[parts - 1][start];
+                let cost = previous_cost.max(part_cost(start, end));
+                if cost < best[parts][end].0 {
+                    best[parts][end] = (cost, start);
+                }
+            }
+        }
+    }
+
+    let mut split = vec![];
+    let mut end = stops_count;
+    let mut parts = parts_count;
+    while end > 0 && parts > 0 {
+        let start = best[parts][end].1;
+        if best[parts][end] != best[parts - 1][end] || parts == 1 {
+            split.push(stops[start..end].to_vec());
+            end = start;
+        }
+        parts -= 1;
+    }
+    split.reverse();
+    split
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{generate_delivery_routes, generate_delivery_tour, generate_route, RouteGeneratorStrategy};
+    use crate::lattice::{lattice_graph, lattice_node};
+
+    use super::*;
+
+    #[test]
+    fn test_route_through_waypoints_has_legs() {
+        let graph = lattice_graph(4, 4);
+        let route_details = RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 3))
+            .with_waypoints(vec![lattice_node(4, 3, 0), lattice_node(4, 0, 3)]);
+
+        let route = generate_route(&graph, RouteGeneratorStrategy::AStar, &route_details);
+
+        assert_eq!(route.legs.iter().map(|leg| leg.edge_count).collect::<Vec<_>>(), vec![3, 6, 3]);
+        assert_eq!(route.edges.len(), 12);
+        assert_eq!(route.edges[2].to, lattice_node(4, 3, 0));
+        assert_eq!(route.edges[8].to, lattice_node(4, 0, 3));
+        let legs_distance: f32 = route.legs.iter().map(|leg| leg.cost.distance).sum();
+        assert!((route.cost.distance - legs_distance).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_route_through_waypoints_keeps_telemetry_of_every_leg() {
+        let graph = lattice_graph(4, 4);
+        let route_details = RouteDetails::new(lattice_node(4, 0, 0), lattice_node(4, 3, 3))
+            .with_waypoints(vec![lattice_node(4, 3, 0)])
+            .with_seed(8)
+            .with_telemetry();
+
+        let route = generate_route(&graph, RouteGeneratorStrategy::ACO, &route_details);
+
+        let records = route.telemetry.unwrap().records;
+        assert_eq!(records.len(), 2 * crate::providers::aco::SIMULATION_COUNT as usize);
+        assert!(records.iter().enumerate().all(|(idx, record)| record.iteration == idx as u32));
+        assert!(records.windows(2).all(|pair| pair[1].elapsed >= pair[0].elapsed));
+    }
+
+    #[test]
+    fn test_cancelled_delivery_tour_is_partial() {
+        let graph = lattice_graph(5, 1);
+        let cancellation = crate::budget::CancellationToken::new();
+        cancellation.cancel();
+        let route_details = RouteDetails::new(lattice_node(5, 0, 0), lattice_node(5, 0, 0))
+            .with_seed(3)
+            .with_cancellation(cancellation);
+        let deliveries = [4, 1, 3, 2].map(|x| lattice_node(5, x, 0));
+
+        //Paths between the stops are not searched once the generation is cancelled
+        let route = generate_delivery_tour(&graph, RouteGeneratorStrategy::AStar, &route_details, &deliveries);
+        assert!(route.partial);
+        assert!(route.edges.is_empty());
+    }
+
+    #[test]
+    fn test_delivery_tour_finds_best_order() {
+        let graph = lattice_graph(5, 1);
+        let route_details = RouteDetails::new(lattice_node(5, 0, 0), lattice_node(5, 0, 0)).with_seed(3);
+        //Given in the worst order, optimal tour goes along the row and back
+        let deliveries = [4, 1, 3, 2].map(|x| lattice_node(5, x, 0));
+
+        let route = generate_delivery_tour(&graph, RouteGeneratorStrategy::AStar, &route_details, &deliveries);
+
+        assert_eq!(route.edges.len(), 8);
+        assert_eq!(route.legs.len(), 5);
+    }
+
+    #[test]
+    fn test_validated_delivery_tour_is_kept() {
+        let graph = lattice_graph(5, 1);
+        //Validation expects the delivery points in the order chosen for the tour, not the waypoints of the request
+        let route_details = RouteDetails::new(lattice_node(5, 0, 0), lattice_node(5, 0, 0))
+            .with_waypoints(vec![lattice_node(5, 2, 0)])
+            .with_seed(3)
+            .with_validation();
+        let deliveries = [4, 1, 3].map(|x| lattice_node(5, x, 0));
+
+        let route = generate_delivery_tour(&graph, RouteGeneratorStrategy::AStar, &route_details, &deliveries);
+        assert_eq!(route.edges.len(), 8);
+
+        let routes = generate_delivery_routes(&graph, RouteGeneratorStrategy::AStar, &route_details, &deliveries, 2);
+        assert!(routes.iter().all(|route| !route.edges.is_empty()));
+    }
+
+    #[test]
+    fn test_delivery_routes_are_balanced() {
+        let graph = lattice_graph(7, 1);
+        let route_details = RouteDetails::new(lattice_node(7, 3, 0), lattice_node(7, 3, 0)).with_seed(5);
+        let deliveries = [0, 6, 1, 5].map(|x| lattice_node(7, x, 0));
+
+        let routes = generate_delivery_routes(&graph, RouteGeneratorStrategy::AStar, &route_details, &deliveries, 2);
+
+        //One drone flies west and the other one east
+        assert_eq!(routes.len(), 2);
+        for route in routes.iter() {
+            assert_eq!(route.edges.len(), 6);
+            assert_eq!(route.legs.len(), 3);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use osmpbfreader::NodeId;
+
+use mgr_map_extractor::graph::Graph;
+use mgr_weather::image_wrapper::PixelColor;
+
+use crate::{GridPosition, PixRoute, Route, RouteDetails, WxRouteDetails};
+use crate::wx_grid::{line_cost, position_to_cell};
+
+/// Problem found in the graph route
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RouteIssue {
+    //Route has no edges while the starting and the ending node differ
+    Empty,
+    WrongStart { expected: NodeId, actual: NodeId },
+    WrongEnd { expected: NodeId, actual: NodeId },
+    //Edge does not start at the node where the previous one has finished
+    Disconnected { edge_idx: usize },
+    //Edge is not a part of the graph
+    UnknownEdge { edge_idx: usize, from: NodeId, to: NodeId },
+    MissedWaypoint { waypoint: NodeId },
+}
+
+/// Problem found in the weather grid route
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixRouteIssue {
+    //Route has no waypoints while the starting and the ending position differ
+    Empty,
+    WrongStart { expected: GridPosition, actual: GridPosition },
+    WrongEnd { expected: GridPosition, actual: GridPosition },
+    OutsideGrid { waypoint_idx: usize },
+    //Straight line between the waypoint and the next one crosses a cell that cannot be entered
+    BlockedSegment { segment_idx: usize },
+}
+
+/// Check that the route is connected, uses only edges of the graph, goes through the waypoints in the given order
+/// and connects the starting node with the ending one. Valid route has no issues
+pub fn validate_route(graph: &Graph, route_details: &RouteDetails, route: &Route) -> Vec<RouteIssue> {
+    let (Some(first), Some(last)) = (route.edges.first(), route.edges.last()) else {
+        return match route_details.starting_node == route_details.ending_node {
+            true => vec![],
+            false => vec![RouteIssue::Empty],
+        };
+    };
+
+    let mut issues = vec![];
+    if first.from != route_details.starting_node {
+        issues.push(RouteIssue::WrongStart { expected: route_details.starting_node, actual: first.from });
+    }
+    if last.to != route_details.ending_node {
+        issues.push(RouteIssue::WrongEnd { expected: route_details.ending_node, actual: last.to });
+    }
+
+    for (edge_idx, edge) in route.edges.iter().enumerate() {
+        if edge_idx > 0 && route.edges[edge_idx - 1].to != edge.from {
+            issues.push(RouteIssue::Disconnected { edge_idx });
+        }
+        let known = graph.edge_connections().get(&edge.from).is_some_and(|connections| connections.contains(&edge.to));
+        if !known {
+            issues.push(RouteIssue::UnknownEdge { edge_idx, from: edge.from, to: edge.to });
+        }
+    }
+
+    //Waypoints are matched in order, each one after the node where the previous one was reached
+    let mut nodes = std::iter::once(first.from).chain(route.edges.iter().map(|edge| edge.to));
+    for &waypoint in &route_details.waypoints {
+        if !nodes.any(|node| node == waypoint) {
+            issues.push(RouteIssue::MissedWaypoint { waypoint });
+            break;
+        }
+    }
+    issues
+}
+
+/// Check that the grid route connects the starting position with the ending one (within the same cell)
+/// and that none of its segments crosses a cell that cannot be entered. Valid route has no issues
+pub fn validate_pix_route(grid: &[PixelColor], route_details: &WxRouteDetails, route: &PixRoute) -> Vec<PixRouteIssue> {
+    let (Some(&first), Some(&last)) = (route.waypoints.first(), route.waypoints.last()) else {
+        return match same_cell(&route_details.starting_position, &route_details.ending_position) {
+            true => vec![],
+            false => vec![PixRouteIssue::Empty],
+        };
+    };
+
+    let mut issues = vec![];
+    if !same_cell(&first, &route_details.starting_position) {
+        issues.push(PixRouteIssue::WrongStart { expected: route_details.starting_position, actual: first });
+    }
+    if !same_cell(&last, &route_details.ending_position) {
+        issues.push(PixRouteIssue::WrongEnd { expected: route_details.ending_position, actual: last });
+    }
+
+    let cells: Vec<_> = route.waypoints.iter().map(position_to_cell).collect();
+    for (waypoint_idx, cell) in cells.iter().enumerate() {
+        if cell.is_none() {
+            issues.push(PixRouteIssue::OutsideGrid { waypoint_idx });
+        }
+    }
+    for (segment_idx, pair) in cells.windows(2).enumerate() {
+        if let (Some(from), Some(to)) = (pair[0], pair[1]) {
+            if line_cost(grid, route_details.obstacles, from, to).is_none() {
+                issues.push(PixRouteIssue::BlockedSegment { segment_idx });
+            }
+        }
+    }
+    issues
+}
+
+fn same_cell(first: &GridPosition, second: &GridPosition) -> bool {
+    position_to_cell(first) == position_to_cell(second)
+}
+
+#[cfg(test)]
+mod tests {
+    use mgr_weather::image_wrapper::{GRID_SIZE, Rgba};
+
+    use crate::{generate_route_rn_with, generate_route_with};
+    use crate::lattice::{lattice_graph, lattice_node};
+    use crate::registry::ProviderRegistry;
+
+    use super::*;
+
+    #[test]
+    fn test_graph_routes_of_every_provider_are_valid() {
+        let graph = lattice_graph(5, 5);
+        let route_details = RouteDetails::new(lattice_node(5, 0, 0), lattice_node(5, 4, 4))
+            .with_waypoints(vec![lattice_node(5, 4, 0)])
+            .with_seed(11);
+
+        let registry = ProviderRegistry::new();
+        for name in registry.names() {
+            let provider = registry.get(name).unwrap();
+            let route = generate_route_with(&graph, provider, &route_details);
+            assert_eq!(validate_route(&graph, &route_details, &route), vec![], "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_grid_routes_of_every_provider_are_valid() {
+        //Wall of echo between the route ends, every provider has to go around it
+        let mut grid = vec![Rgba([0, 0, 0, 0]); GRID_SIZE * GRID_SIZE];
+        for y in 40..60 {
+            grid[y * GRID_SIZE + 50] = Rgba([200, 0, 0, 255]);
+        }
+        let route_details = WxRouteDetails::new(GridPosition { x: 40.0, y: 50.0 }, GridPosition { x: 60.0, y: 50.0 }).with_seed(11);
+
+        let registry = ProviderRegistry::new();
+        for name in registry.names() {
+            let provider = registry.get(name).unwrap();
+            let route = generate_route_rn_with(provider, &route_details, &grid);
+            assert_eq!(validate_pix_route(&grid, &route_details, &route), vec![], "{}", name);
+            assert_eq!(route.waypoints.first(), Some(&route_details.starting_position), "{}", name);
+            assert_eq!(route.waypoints.last(), Some(&route_details.ending_position), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_broken_route_is_diagnosed() {
+        let graph = lattice_graph(3, 3);
+        let route_details = RouteDetails::new(lattice_node(3, 0, 0), lattice_node(3, 2, 2))
+            .with_waypoints(vec![lattice_node(3, 0, 2)]);
+        let edge = |from: (usize, usize), to: (usize, usize)| graph.edge_by_node_id()[&(lattice_node(3, from.0, from.1), lattice_node(3, to.0, to.1))];
+        let mut route = Route::new(vec![edge((0, 1), (1, 1)), edge((1, 1), (2, 1))]);
+        route.edges.push(mgr_map_extractor::graph::Edge::create(&graph, lattice_node(3, 2, 1), lattice_node(3, 0, 0)));
+
+        let issues = validate_route(&graph, &route_details, &route);
+
+        assert_eq!(issues, vec![
+            RouteIssue::WrongStart { expected: lattice_node(3, 0, 0), actual: lattice_node(3, 0, 1) },
+            RouteIssue::WrongEnd { expected: lattice_node(3, 2, 2), actual: lattice_node(3, 0, 0) },
+            RouteIssue::UnknownEdge { edge_idx: 2, from: lattice_node(3, 2, 1), to: lattice_node(3, 0, 0) },
+            RouteIssue::MissedWaypoint { waypoint: lattice_node(3, 0, 2) },
+        ]);
+        assert_eq!(validate_route(&graph, &route_details, &Route::default()), vec![RouteIssue::Empty]);
+        assert_eq!(crate::enforce_validation(&graph, &route_details, route.clone()).edges.len(), 3);
+        let rejected = crate::enforce_validation(&graph, &route_details.with_validation(), route.with_seed(4));
+        assert!(rejected.edges.is_empty());
+        assert_eq!(rejected.seed, Some(4));
+        assert_eq!(rejected.rejection, Some(crate::RouteRejection::Validation(issues)));
+    }
+
+    #[test]
+    fn test_route_through_echo_is_diagnosed() {
+        let mut grid = vec![Rgba([0, 0, 0, 0]); GRID_SIZE * GRID_SIZE];
+        grid[10 * GRID_SIZE + 15] = Rgba([0, 0, 90, 255]);
+        let route_details = WxRouteDetails::new(GridPosition { x: 10.0, y: 10.0 }, GridPosition { x: 20.0, y: 10.0 });
+        let route = PixRoute::new(vec![GridPosition { x: 10.0, y: 10.0 }, GridPosition { x: 20.0, y: 10.0 }, GridPosition { x: 120.0, y: 10.0 }]);
+
+        let issues = validate_pix_route(&grid, &route_details, &route);
+
+        assert_eq!(issues, vec![
+            PixRouteIssue::WrongEnd { expected: route_details.ending_position, actual: GridPosition { x: 120.0, y: 10.0 } },
+            PixRouteIssue::OutsideGrid { waypoint_idx: 2 },
+            PixRouteIssue::BlockedSegment { segment_idx: 0 },
+        ]);
+        let rejected = crate::enforce_validation_rn(&grid, &route_details.with_validation(), route);
+        assert!(rejected.waypoints.is_empty());
+        assert_eq!(rejected.rejection, Some(crate::PixRouteRejection::Validation(issues)));
+    }
+}
@@ -0,0 +1,267 @@
+use std::ops::Index;
+
+use mgr_weather::image_wrapper::{GRID_SIZE, PixelColor};
+
+use crate::{GridPosition, ObstacleHandling};
+use crate::path::PathSpace;
+
+/// Cell of the grid given as (column, row)
+pub(crate) type GridCell = (usize, usize);
+
+/// Approximate length of the weather grid cell side in metres.
+/// Keeps grid costs, like the ACO heuristic and pheromone deposits or the drone energy, in the same range as on the road graph
+pub const GRID_CELL_LENGTH: f32 = 30.0;
+
+//How many times more expensive it is to cross the cell with the strongest echo than the clear one
+pub(crate) const OBSTACLE_COST_FACTOR: f32 = 20.0;
+
+//Neighbourhood of the cell in the 8-connected lattice
+const NEIGHBOUR_OFFSETS: [(isize, isize); 8] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+
+/// Index of the cell that contains given position, None if position lies outside of the grid
+pub(crate) fn cell_index(position: &GridPosition) -> Option<usize> {
+    let x = position.x.floor();
+    let y = position.y.floor();
+    if x < 0.0 || y < 0.0 || x >= GRID_SIZE as f32 || y >= GRID_SIZE as f32 {
+        return None;
+    }
+    Some(y as usize * GRID_SIZE + x as usize)
+}
+
+/// Cell is blocked when it lies outside the grid or any of its colour components is set (weather echo)
+pub(crate) fn is_blocked(grid: &[PixelColor], position: &GridPosition) -> bool {
+    match cell_index(position).and_then(|idx| grid.get(idx)) {
+        Some(pixel_value) => {
+            *pixel_value.index(0) >= 1 ||
+                *pixel_value.index(1) >= 1 ||
+                *pixel_value.index(2) >= 1
+        }
+        None => true,
+    }
+}
+
+pub(crate) fn designate_points_on_line(start_point: GridPosition, end_point: GridPosition, number_of_points: usize) -> Vec<GridPosition> {
+    let dtx = (end_point.x - start_point.x) / number_of_points as f32;
+    let dty = (end_point.y - start_point.y) / number_of_points as f32;
+
+    let mut points = vec![];
+
+    for point_number in 0..number_of_points {
+        let tx = 0.5 * dtx + (point_number as f32) * dtx;
+        let ty = 0.5 * dty + (point_number as f32) * dty;
+        let check_point = GridPosition { x: (start_point.x + tx).floor(), y: (start_point.y + ty).floor() };
+        //add point if it is not already in the list
+        if !points.contains(&check_point) {
+            points.push(check_point);
+        }
+    }
+    points
+}
+
+/// Number of blocked cells crossed by a straight segment, sampled twice per cell of its length
+pub(crate) fn segment_blocked_cells(grid: &[PixelColor], from: &GridPosition, to: &GridPosition) -> usize {
+    let number_of_points = (distance(from, to) * 2.0).ceil().max(1.0) as usize;
+    let mut points_to_check = designate_points_on_line(*from, *to, number_of_points);
+    points_to_check.push(*to);
+
+    points_to_check.iter().filter(|point| is_blocked(grid, point)).count()
+}
+
+pub(crate) fn position_to_cell(position: &GridPosition) -> Option<GridCell> {
+    cell_index(position).map(|idx| (idx % GRID_SIZE, idx / GRID_SIZE))
+}
+
+pub(crate) fn cell_to_position(cell: GridCell) -> GridPosition {
+    GridPosition { x: cell.0 as f32, y: cell.1 as f32 }
+}
+
+pub(crate) fn cell_centre(cell: GridCell) -> GridPosition {
+    GridPosition { x: cell.0 as f32 + 0.5, y: cell.1 as f32 + 0.5 }
+}
+
+/// Strength of the weather echo in the cell containing given position, from 0.0 (clear) to 1.0
+pub(crate) fn echo_intensity(grid: &[PixelColor], position: &GridPosition) -> Option<f32> {
+    let pixel_value = grid.get(cell_index(position)?)?;
+    Some((0..3).map(|channel| *pixel_value.index(channel)).max().unwrap_or(0) as f32 / 255.0)
+}
+
+/// Cost of crossing one unit of distance inside the cell, None if the cell cannot be entered
+pub(crate) fn cell_cost(grid: &[PixelColor], obstacles: ObstacleHandling, cell: GridCell) -> Option<f32> {
+    if cell.0 >= GRID_SIZE || cell.1 >= GRID_SIZE {
+        return None;
+    }
+    let intensity = echo_intensity(grid, &cell_to_position(cell))?;
+
+    if intensity == 0.0 {
+        return Some(1.0);
+    }
+    match obstacles {
+        ObstacleHandling::Blocked => None,
+        ObstacleHandling::Costly => Some(1.0 + OBSTACLE_COST_FACTOR * intensity),
+    }
+}
+
+/// Cost of flying straight between centres of two cells - distance multiplied by the mean cost of crossed cells.
+/// None if any of the crossed cells cannot be entered
+pub(crate) fn line_cost(grid: &[PixelColor], obstacles: ObstacleHandling, from: GridCell, to: GridCell) -> Option<f32> {
+    let from_centre = cell_centre(from);
+    let to_centre = cell_centre(to);
+    let line_length = distance(&from_centre, &to_centre);
+
+    let number_of_points = (line_length * 2.0).ceil().max(1.0) as usize;
+    let mut points_to_check = designate_points_on_line(from_centre, to_centre, number_of_points);
+    points_to_check.push(to_centre);
+
+    let mut total_cost = 0.0;
+    for point in points_to_check.iter() {
+        total_cost += cell_cost(grid, obstacles, position_to_cell(point)?)?;
+    }
+    Some(line_length * total_cost / points_to_check.len() as f32)
+}
+
+/// Neighbours in the 8-connected lattice that can be entered from the cell, together with the cost of the move.
+/// Diagonal moves cannot cut corners of the cells that cannot be entered
+pub(crate) fn neighbours(grid: &[PixelColor], obstacles: ObstacleHandling, cell: GridCell) -> Vec<(GridCell, f32)> {
+    let offset_cell = |dx: isize, dy: isize| -> Option<GridCell> {
+        let x = cell.0.checked_add_signed(dx)?;
+        let y = cell.1.checked_add_signed(dy)?;
+        (x < GRID_SIZE && y < GRID_SIZE).then_some((x, y))
+    };
+
+    NEIGHBOUR_OFFSETS.iter().filter_map(|&(dx, dy)| {
+        let next = offset_cell(dx, dy)?;
+        if dx != 0 && dy != 0 {
+            cell_cost(grid, obstacles, offset_cell(dx, 0)?)?;
+            cell_cost(grid, obstacles, offset_cell(0, dy)?)?;
+        }
+        line_cost(grid, obstacles, cell, next).map(|cost| (next, cost))
+    }).collect()
+}
+
+/// 8-connected lattice of the weather grid cells
+pub(crate) struct WxLattice<'a>(pub(crate) &'a [PixelColor], pub(crate) ObstacleHandling);
+
+impl PathSpace for WxLattice<'_> {
+    type Position = GridCell;
+
+    fn neighbours(&self, cell: GridCell) -> Vec<GridCell> {
+        neighbours(self.0, self.1, cell).into_iter().map(|(next, _)| next).collect()
+    }
+
+    fn distance(&self, from: GridCell, to: GridCell) -> f32 {
+        cell_distance(from, to)
+    }
+}
+
+/// Sum of the move costs along the path of neighbouring cells, infinite when any cell cannot be entered
+pub(crate) fn cell_path_cost(grid: &[PixelColor], obstacles: ObstacleHandling, cells: &[GridCell]) -> f32 {
+    cells.windows(2).map(|pair| line_cost(grid, obstacles, pair[0], pair[1]).unwrap_or(f32::INFINITY)).sum()
+}
+
+pub(crate) fn cell_distance(from: GridCell, to: GridCell) -> f32 {
+    distance(&cell_to_position(from), &cell_to_position(to))
+}
+
+pub(crate) fn distance(from: &GridPosition, to: &GridPosition) -> f32 {
+    ((to.x - from.x).powf(2.0) + (to.y - from.y).powf(2.0)).sqrt()
+}
+
+/// Total length of the polyline
+pub(crate) fn polyline_length(points: &[GridPosition]) -> f32 {
+    points.windows(2).map(|pair| distance(&pair[0], &pair[1])).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use mgr_weather::image_wrapper::{GRID_SIZE, Rgba};
+
+    use super::*;
+
+    #[test]
+    fn test_designate_points_on_line() {
+        let start_point = super::GridPosition { x: 0.0, y: 0.0 };
+        let end_point = super::GridPosition { x: 10.0, y: 10.0 };
+        let number_of_points = 5;
+
+        let points = super::designate_points_on_line(start_point, end_point, number_of_points);
+        assert_eq!(points.len(), 5);
+        assert_eq!(points[0], super::GridPosition { x: 1.0, y: 1.0 });
+        assert_eq!(points[1], super::GridPosition { x: 3.0, y: 3.0 });
+        assert_eq!(points[2], super::GridPosition { x: 5.0, y: 5.0 });
+        assert_eq!(points[3], super::GridPosition { x: 7.0, y: 7.0 });
+        assert_eq!(points[4], super::GridPosition { x: 9.0, y: 9.0 });
+    }
+
+    #[test]
+    fn test_designate_points_on_line_2() {
+        let start_point = super::GridPosition { x: 0.0, y: 0.0 };
+        let end_point = super::GridPosition { x: 20.0, y: 10.0 };
+        let number_of_points = 5;
+
+        let points = super::designate_points_on_line(start_point, end_point, number_of_points);
+        assert_eq!(points.len(), 5);
+        assert_eq!(points[0], super::GridPosition { x: 2.0, y: 1.0 });
+        assert_eq!(points[1], super::GridPosition { x: 6.0, y: 3.0 });
+        assert_eq!(points[2], super::GridPosition { x: 10.0, y: 5.0 });
+        assert_eq!(points[3], super::GridPosition { x: 14.0, y: 7.0 });
+        assert_eq!(points[4], super::GridPosition { x: 18.0, y: 9.0 });
+    }
+
+    #[test]
+    fn test_designate_points_on_line_short_route() {
+        let start_point = super::GridPosition { x: 0.0, y: 0.0 };
+        let end_point = super::GridPosition { x: 3.0, y: -1.0 };
+        let number_of_points = 5;
+
+        let points = super::designate_points_on_line(start_point, end_point, number_of_points);
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0], super::GridPosition { x: 0.0, y: -1.0 });
+        assert_eq!(points[1], super::GridPosition { x: 1.0, y: -1.0 });
+        assert_eq!(points[2], super::GridPosition { x: 2.0, y: -1.0 });
+    }
+
+    #[test]
+    fn test_neighbours_do_not_cut_corners() {
+        let mut grid = vec![Rgba([0, 0, 0, 0]); GRID_SIZE * GRID_SIZE];
+        grid[GRID_SIZE + 2] = Rgba([200, 0, 0, 255]);
+
+        let cells: Vec<GridCell> = neighbours(&grid, ObstacleHandling::Blocked, (1, 1)).into_iter().map(|(cell, _)| cell).collect();
+        assert_eq!(cells.len(), 5);
+        assert!(!cells.contains(&(2, 1)));
+        assert!(!cells.contains(&(2, 0)));
+        assert!(!cells.contains(&(2, 2)));
+
+        assert_eq!(neighbours(&grid, ObstacleHandling::Blocked, (0, 0)).len(), 3);
+    }
+
+    #[test]
+    fn test_costly_echo_can_be_crossed() {
+        let mut grid = vec![Rgba([0, 0, 0, 0]); GRID_SIZE * GRID_SIZE];
+        grid[GRID_SIZE + 2] = Rgba([255, 0, 0, 255]);
+
+        assert_eq!(cell_cost(&grid, ObstacleHandling::Blocked, (2, 1)), None);
+        assert_eq!(cell_cost(&grid, ObstacleHandling::Costly, (2, 1)), Some(1.0 + OBSTACLE_COST_FACTOR));
+        assert_eq!(cell_cost(&grid, ObstacleHandling::Costly, (1, 1)), Some(1.0));
+        assert_eq!(neighbours(&grid, ObstacleHandling::Costly, (1, 1)).len(), 8);
+        assert!(line_cost(&grid, ObstacleHandling::Costly, (1, 1), (3, 1)).unwrap() > line_cost(&grid, ObstacleHandling::Costly, (1, 2), (3, 2)).unwrap());
+    }
+
+    #[test]
+    fn test_cell_outside_grid_is_blocked() {
+        let grid = vec![Rgba([0, 0, 0, 0]); GRID_SIZE * GRID_SIZE];
+
+        assert!(!is_blocked(&grid, &GridPosition { x: 0.0, y: 0.0 }));
+        assert!(is_blocked(&grid, &GridPosition { x: -1.0, y: 0.0 }));
+        assert!(is_blocked(&grid, &GridPosition { x: 0.0, y: GRID_SIZE as f32 }));
+    }
+
+    #[test]
+    fn test_segment_crossing_echo() {
+        let mut grid = vec![Rgba([0, 0, 0, 0]); GRID_SIZE * GRID_SIZE];
+        grid[5 * GRID_SIZE + 5] = Rgba([0, 200, 0, 255]);
+
+        let from = GridPosition { x: 0.5, y: 5.5 };
+        assert!(segment_blocked_cells(&grid, &from, &GridPosition { x: 10.5, y: 5.5 }) > 0);
+        assert_eq!(segment_blocked_cells(&grid, &from, &GridPosition { x: 10.5, y: 8.5 }), 0);
+    }
+}
@@ -5,10 +5,29 @@ use mgr_map_extractor::NodeId;
 
 pub type DroneId = u32;
 
+/// Time window in seconds, counted from the start of the plan
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeWindow {
+    pub start: f32,
+    pub end: f32,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct CongestionStats {
     //Holds info about which drones are currently on which edges
     congestion: HashMap<(NodeId, NodeId), Vec<DroneId>>,
+    //Holds info about which drones are going to fly through which edges and when
+    reservations: HashMap<(NodeId, NodeId), Vec<(DroneId, TimeWindow)>>,
+}
+
+impl TimeWindow {
+    pub fn new(start: f32, end: f32) -> Self {
+        TimeWindow { start, end }
+    }
+
+    pub fn overlaps(&self, other: &TimeWindow) -> bool {
+        self.start < other.end && other.start < self.end
+    }
 }
 
 
@@ -22,6 +41,7 @@ impl CongestionStats {
         Self {
             //@TODO
             congestion: HashMap::new(),
+            reservations: HashMap::new(),
         }
     }
 
@@ -38,4 +58,51 @@ impl CongestionStats {
         let drones = self.congestion.get_mut(&(edge.from, edge.to)).unwrap();
         drones.retain(|&x| x != drone_id);
     }
+
+    pub fn reserve(&mut self, edge: Edge, drone_id: DroneId, window: TimeWindow) {
+        let reservations = self.reservations.entry((edge.from, edge.to)).or_default();
+        reservations.push((drone_id, window));
+    }
+
+    pub fn reservations(&self) -> &HashMap<(NodeId, NodeId), Vec<(DroneId, TimeWindow)>> {
+        &self.reservations
+    }
+
+    /// Check if any other drone flies through the edge, in any direction, during given time window
+    pub fn is_reserved(&self, edge: Edge, window: TimeWindow, drone_id: DroneId) -> bool {
+        [(edge.from, edge.to), (edge.to, edge.from)].iter()
+            .filter_map(|key| self.reservations.get(key))
+            .flatten()
+            .any(|(other_drone_id, other_window)| *other_drone_id != drone_id && other_window.overlaps(&window))
+    }
+
+    //Remove all reservations of the drone
+    pub fn release(&mut self, drone_id: DroneId) {
+        for reservations in self.reservations.values_mut() {
+            reservations.retain(|(other_drone_id, _)| *other_drone_id != drone_id);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use mgr_map_extractor::ApprovedHighwayType;
+
+    use super::*;
+
+    #[test]
+    fn test_reservation_blocks_other_drones_in_both_directions() {
+        let edge = Edge::new(NodeId(1), NodeId(2), 10.0, ApprovedHighwayType::Residential);
+        let reversed = Edge::new(NodeId(2), NodeId(1), 10.0, ApprovedHighwayType::Residential);
+        let mut stats = CongestionStats::new();
+        stats.reserve(edge, 1, TimeWindow::new(0.0, 10.0));
+
+        assert!(stats.is_reserved(reversed, TimeWindow::new(5.0, 15.0), 2));
+        assert!(!stats.is_reserved(edge, TimeWindow::new(10.0, 20.0), 2));
+        assert!(!stats.is_reserved(edge, TimeWindow::new(5.0, 15.0), 1));
+
+        stats.release(1);
+        assert!(!stats.is_reserved(edge, TimeWindow::new(5.0, 15.0), 2));
+    }
 }
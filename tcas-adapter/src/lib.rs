@@ -23,6 +23,10 @@ impl TCASAdapter {
         }
     }
 
+    pub fn congestion_stats(&self) -> &CongestionStats {
+        &self.congestion_stats
+    }
+
     /// Read edge usage from the graph context
     pub fn read_edge_usage(&self, graph: &Graph, edge: Edge) -> Option<EdgeCongestionLevel> {
         graph.edge_by_node_id().get(&(edge.from, edge.to)).map(|_| {
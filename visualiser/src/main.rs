@@ -1,13 +1,15 @@
-use log::info;
+use log::{info, warn};
 
 use mgr_map_extractor::produce_connection_graph;
 use mgr_route_generator::{generate_route, generate_route_rn, RouteGeneratorStrategy};
+use mgr_route_generator::export::GeoTrack;
 use mgr_weather::image_wrapper::produce_grid;
 
 use crate::renderer::{event, event_wx, get_selected_wx_route_1, model_graph, model_wx, view_graph_route, view_weather_route};
 
 mod renderer;
 
+#[allow(dead_code)]
 enum RenderTarget {
     Weather,
     Graph,
@@ -64,7 +66,7 @@ fn main() {
                 let grid = produce_grid();
                 info!("Weather model initialized");
                 let route = generate_route_rn(RouteGeneratorStrategy::PSO, &get_selected_wx_route_1(), &grid);
-                info!("Route generated, number of waypoints: {:?}", route.0.len());
+                info!("Route generated, number of waypoints: {:?}", route.waypoints().len());
             }
             RenderTarget::Graph => {
                 let route_details = crate::renderer::get_selected_route();
@@ -73,6 +75,15 @@ fn main() {
                 info!("Generating route");
                 let route = generate_route(&graph, RouteGeneratorStrategy::ACO, &route_details);
                 info!("Route generated. Number of edges: {}", route.edges().len());
+
+                //Format is chosen by the file extension - .geojson, .gpx or .kml
+                if let Ok(path) = std::env::var("ROUTE_EXPORT") {
+                    match GeoTrack::from_route(&graph, &route).map(|track| track.write(&path)) {
+                        Some(Ok(())) => info!("Route exported to {}", path),
+                        Some(Err(error)) => warn!("Route export to {} failed: {}", path, error),
+                        None => warn!("Route has less than two nodes, it is not exported to {}", path),
+                    }
+                }
             }
         }
 
@@ -20,16 +20,11 @@ const DRAW_WX_ROUTE_LINE: bool = false;
 
 //Hardcoded routes for testing
 // from 'szpital bielnaski' to 'policja na perzynskiego'
-const ROUTE_1: RouteDetails = RouteDetails {
-    starting_node: NodeId(2092909105),
-    ending_node: NodeId(120225778),
-};
+#[allow(dead_code)]
+const ROUTE_1: RouteDetails = RouteDetails::new(NodeId(2092909105), NodeId(120225778));
 
 //Metro marymont to urzad dzielnicy bielany
-const ROUTE_2: RouteDetails = RouteDetails {
-    starting_node: NodeId(3771159058),
-    ending_node: NodeId(5784242419),
-};
+const ROUTE_2: RouteDetails = RouteDetails::new(NodeId(3771159058), NodeId(5784242419));
 
 pub struct ModelWX {
     grid: Vec<PixelColor>,
@@ -54,10 +49,7 @@ pub(crate) fn get_selected_route() -> RouteDetails {
 }
 
 pub(crate) fn get_selected_wx_route_1() -> WxRouteDetails {
-    WxRouteDetails {
-        starting_position: GridPosition { x: 47.0, y: 40.0 },
-        ending_position: GridPosition { x: 84.0, y: 50.0 },
-    }
+    WxRouteDetails::new(GridPosition { x: 47.0, y: 40.0 }, GridPosition { x: 84.0, y: 50.0 })
 }
 
 /// Return Render mode based on the RENDER_MODE environment variable
@@ -139,7 +131,7 @@ pub fn view_weather_route(app: &App, model: &ModelWX, frame: Frame) {
 
 //Iterate over points and connect them with lines
 pub fn draw_generated_wx_route(draw: &Draw, model: &ModelWX, boundary: &Rect) {
-    let points = &model.route.0;
+    let points = model.route.waypoints();
 
     for i in 0..points.len() - 1 {
         draw.ellipse()
@@ -237,7 +229,7 @@ fn draw_edges(draw: &Draw, edges: &[Edge], nodes: &HashSet<Node>, coordinate_sta
             if route.edges().contains(edge) {
                 color = Srgb::from_components(ROUTE_COLOR_COMPONENTS);
 
-                if let Some(edge_usage) = model.tcas.read_edge_usage(&model.graph, edge.clone()) {
+                if let Some(edge_usage) = model.tcas.read_edge_usage(&model.graph, *edge) {
                     match edge_usage {
                         EdgeCongestionLevel::Low => {
                             color = color::PALEGREEN